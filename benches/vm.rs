@@ -0,0 +1,69 @@
+//! Times the scripts in `benches/scripts` with the release binary:
+//!
+//!     cargo bench
+//!     cargo bench --features nan-boxing
+//!
+//! Each script runs a few times on each backend, the stack machine and the
+//! register machine, and the fastest and median runs are reported along with
+//! the longest garbage collector pause of the median run.
+//! Pass a name to run only the scripts containing it: `cargo bench -- fib`.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const RUNS: usize = 7;
+const BACKENDS: [&str; 2] = ["stack", "register"];
+
+fn main() {
+    // cargo passes `--bench` along with any filter
+    let filter: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    let binary = env!("CARGO_BIN_EXE_lockhart");
+    let representation = if cfg!(feature = "nan-boxing") { "nan-boxed" } else { "enum" };
+    println!("values: {}", representation);
+
+    let mut scripts: Vec<_> = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/scripts"))
+        .expect("benches/scripts is missing")
+        .map(|entry| entry.expect("unreadable bench script").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lh"))
+        .filter(|path| filter.is_empty() || filter.iter().any(|f| path.to_string_lossy().contains(f.as_str())))
+        .collect();
+    scripts.sort();
+
+    for script in scripts {
+        for backend in BACKENDS {
+            let mut runs: Vec<(Duration, String)> = (0..RUNS).map(|_| time(binary, &script, backend)).collect();
+            runs.sort();
+            println!(
+                "{:<12} {:<9} min {:>8.1?}  median {:>8.1?}  gc pause {:>9}",
+                script.file_stem().unwrap().to_string_lossy(),
+                backend,
+                runs[0].0,
+                runs[RUNS / 2].0,
+                runs[RUNS / 2].1
+            );
+        }
+    }
+}
+
+/// How long the script took, and its longest collector pause.
+fn time(binary: &str, script: &Path, backend: &str) -> (Duration, String) {
+    let start = Instant::now();
+    let output = Command::new(binary)
+        .arg("run")
+        .arg(format!("--backend={}", backend))
+        .arg("--gc-stats")
+        .arg(script)
+        .stdout(Stdio::null())
+        .output()
+        .expect("failed to start lockhart");
+    let elapsed = start.elapsed();
+    assert!(output.status.success(), "{} failed", script.display());
+    // "gc: 4 collections in 1993 pauses, longest 531.881µs, 36.815ms in all"
+    let stats = String::from_utf8_lossy(&output.stderr);
+    let longest = stats.split("longest ").nth(1).and_then(|rest| rest.split(',').next()).unwrap_or("-");
+    (elapsed, longest.to_string())
+}
@@ -0,0 +1,306 @@
+use serde_json::{json, Value as Json};
+
+use crate::{
+    compiler::analysis::CompileError,
+    gc::{Gc, GcRef},
+    globals::Globals,
+    lexer::Lexer,
+    object::ObjFunction,
+    vm::InterpretError,
+};
+
+use self::{codegen::CodeGen, parser::Parser};
+
+mod codegen;
+mod parser;
+#[cfg(test)]
+mod tests;
+
+/// Where a node sits in the source: byte offset and length, plus the line it starts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+}
+
+impl Span {
+    /// the span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        let end = (other.offset + other.len).max(self.offset + self.len);
+        Span {
+            offset: self.offset,
+            len: end - self.offset,
+            line: self.line,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+    Nil,
+    Variable(Ident),
+    Assign(Ident, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Logical(LogicalOp, Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Grouping(Box<Expr>),
+    List(Vec<Expr>),
+    /// `list[index]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `list[index] = value`
+    SetIndex(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Ident,
+    pub params: Vec<Ident>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Expression(Expr),
+    Print(Expr),
+    Let(Ident, Option<Expr>),
+    Function(Function),
+    /// `test "name" { ... }`: a function without parameters named by the string
+    Test(Function),
+    Block(Vec<Stmt>),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    /// initializer, condition, increment and body
+    For(Option<Box<Stmt>>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    Return(Option<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+/// Parse `source` into a syntax tree, collecting every syntax error.
+pub fn parse(source: &str) -> Result<Vec<Stmt>, Vec<CompileError>> {
+    Parser::new(Lexer::new(source.to_string())).parse()
+}
+
+/// Compile `source` by way of the syntax tree. Produces the same bytecode as
+/// `compiler::compile`, except that instructions carry the line their node starts on.
+pub fn compile(source: String, gc: &mut Gc, globals: &mut Globals) -> Result<GcRef<ObjFunction>, InterpretError> {
+    let errors = match parse(&source) {
+        Ok(program) => match CodeGen::new(gc, globals).generate(&program) {
+            Ok(function) => return Ok(gc.alloc(function)),
+            Err(errors) => errors,
+        },
+        Err(errors) => errors,
+    };
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    Err(InterpretError::InterpretCompileError(messages.join("\n")))
+}
+
+/// The tree as JSON, for `--emit=ast`.
+pub fn to_json(program: &[Stmt]) -> Json {
+    Json::Array(program.iter().map(Stmt::to_json).collect())
+}
+
+impl Span {
+    fn to_json(self) -> Json {
+        json!({ "offset": self.offset, "len": self.len, "line": self.line })
+    }
+}
+
+impl Ident {
+    fn to_json(&self) -> Json {
+        json!({ "name": self.name, "span": self.span.to_json() })
+    }
+}
+
+impl UnaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+        }
+    }
+}
+
+impl LogicalOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            LogicalOp::And => "and",
+            LogicalOp::Or => "or",
+        }
+    }
+}
+
+fn optional<T>(node: Option<T>, to_json: impl Fn(T) -> Json) -> Json {
+    node.map(to_json).unwrap_or(Json::Null)
+}
+
+impl Expr {
+    pub fn to_json(&self) -> Json {
+        let mut node = match &self.kind {
+            ExprKind::Number(n) => json!({ "kind": "Number", "value": n }),
+            ExprKind::Str(s) => json!({ "kind": "String", "value": s }),
+            ExprKind::Bool(b) => json!({ "kind": "Bool", "value": b }),
+            ExprKind::Nil => json!({ "kind": "Nil" }),
+            ExprKind::Variable(name) => json!({ "kind": "Variable", "name": name.to_json() }),
+            ExprKind::Assign(name, value) => json!({
+                "kind": "Assign",
+                "name": name.to_json(),
+                "value": value.to_json(),
+            }),
+            ExprKind::Unary(op, operand) => json!({
+                "kind": "Unary",
+                "operator": op.symbol(),
+                "operand": operand.to_json(),
+            }),
+            ExprKind::Binary(op, left, right) => json!({
+                "kind": "Binary",
+                "operator": op.symbol(),
+                "left": left.to_json(),
+                "right": right.to_json(),
+            }),
+            ExprKind::Logical(op, left, right) => json!({
+                "kind": "Logical",
+                "operator": op.symbol(),
+                "left": left.to_json(),
+                "right": right.to_json(),
+            }),
+            ExprKind::Call(callee, args) => json!({
+                "kind": "Call",
+                "callee": callee.to_json(),
+                "arguments": args.iter().map(Expr::to_json).collect::<Vec<_>>(),
+            }),
+            ExprKind::Grouping(inner) => json!({ "kind": "Grouping", "expression": inner.to_json() }),
+            ExprKind::List(items) => json!({
+                "kind": "List",
+                "items": items.iter().map(Expr::to_json).collect::<Vec<_>>(),
+            }),
+            ExprKind::Index(list, index) => json!({
+                "kind": "Index",
+                "list": list.to_json(),
+                "index": index.to_json(),
+            }),
+            ExprKind::SetIndex(list, index, value) => json!({
+                "kind": "SetIndex",
+                "list": list.to_json(),
+                "index": index.to_json(),
+                "value": value.to_json(),
+            }),
+        };
+        node["span"] = self.span.to_json();
+        node
+    }
+}
+
+impl Stmt {
+    pub fn to_json(&self) -> Json {
+        let mut node = match &self.kind {
+            StmtKind::Expression(expr) => json!({ "kind": "Expression", "expression": expr.to_json() }),
+            StmtKind::Print(expr) => json!({ "kind": "Print", "expression": expr.to_json() }),
+            StmtKind::Let(name, init) => json!({
+                "kind": "Let",
+                "name": name.to_json(),
+                "initializer": optional(init.as_ref(), Expr::to_json),
+            }),
+            StmtKind::Function(function) => json!({
+                "kind": "Function",
+                "name": function.name.to_json(),
+                "params": function.params.iter().map(Ident::to_json).collect::<Vec<_>>(),
+                "body": to_json(&function.body),
+            }),
+            StmtKind::Test(test) => json!({
+                "kind": "Test",
+                "name": test.name.to_json(),
+                "body": to_json(&test.body),
+            }),
+            StmtKind::Block(body) => json!({ "kind": "Block", "body": to_json(body) }),
+            StmtKind::If(condition, then_branch, else_branch) => json!({
+                "kind": "If",
+                "condition": condition.to_json(),
+                "then": then_branch.to_json(),
+                "else": optional(else_branch.as_deref(), Stmt::to_json),
+            }),
+            StmtKind::While(condition, body) => json!({
+                "kind": "While",
+                "condition": condition.to_json(),
+                "body": body.to_json(),
+            }),
+            StmtKind::For(init, condition, increment, body) => json!({
+                "kind": "For",
+                "initializer": optional(init.as_deref(), Stmt::to_json),
+                "condition": optional(condition.as_ref(), Expr::to_json),
+                "increment": optional(increment.as_ref(), Expr::to_json),
+                "body": body.to_json(),
+            }),
+            StmtKind::Return(value) => json!({
+                "kind": "Return",
+                "value": optional(value.as_ref(), Expr::to_json),
+            }),
+        };
+        node["span"] = self.span.to_json();
+        node
+    }
+}
@@ -0,0 +1,511 @@
+use std::mem;
+
+use crate::{
+    bytecode::Opcode,
+    chunk::{Chunk, Lineno, LocalVar},
+    compiler::{analysis::CompileError, FunctionType, STACK_SIZE},
+    gc::{Gc, GcRef},
+    globals::Globals,
+    object::ObjFunction,
+    value::Value,
+};
+
+use super::{BinaryOp, Expr, ExprKind, Function, Ident, LogicalOp, Span, Stmt, StmtKind, UnaryOp};
+
+struct Local {
+    name: String,
+    depth: i8,
+}
+
+/// Per-function state, the counterpart of the single-pass compiler's `Compiler`.
+struct FunctionState {
+    enclosing: Option<Box<FunctionState>>,
+    function: ObjFunction,
+    f_type: FunctionType,
+    locals: Vec<Local>,
+    scope_depth: i8,
+}
+
+impl FunctionState {
+    fn new(function: ObjFunction, f_type: FunctionType) -> Box<FunctionState> {
+        // 0th slot for vm internal use
+        let reserved = Local {
+            name: String::new(),
+            depth: 0,
+        };
+        Box::new(FunctionState {
+            enclosing: None,
+            function,
+            f_type,
+            locals: vec![reserved],
+            scope_depth: 0,
+        })
+    }
+}
+
+/// Lowers a syntax tree into bytecode. Scope resolution happens here, so the
+/// errors it reports are the ones the parser can't see: duplicate locals,
+/// reading a local in its own initializer and returning from top-level code.
+pub struct CodeGen<'a> {
+    gc: &'a mut Gc,
+    globals: &'a mut Globals,
+    state: Box<FunctionState>,
+    errors: Vec<CompileError>,
+}
+
+impl<'a> CodeGen<'a> {
+    pub fn new(gc: &'a mut Gc, globals: &'a mut Globals) -> CodeGen<'a> {
+        let name = gc.intern("script".to_owned());
+        let state = FunctionState::new(ObjFunction::script(name), FunctionType::SCRIPT);
+        CodeGen {
+            gc,
+            globals,
+            state,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn generate(mut self, program: &[Stmt]) -> Result<ObjFunction, Vec<CompileError>> {
+        for stmt in program {
+            self.stmt(stmt);
+        }
+        let line = program.last().map_or(0, |stmt| stmt.span.line);
+        self.emit_return(line);
+        if self.errors.is_empty() {
+            Ok(self.state.function)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    /* ======================= plumbing ====================== */
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state.function.chunk
+    }
+
+    fn emit(&mut self, op: Opcode, line: usize) {
+        self.chunk().write_chunk(op, Lineno(line));
+    }
+
+    fn emit_jump(&mut self, op: Opcode, line: usize) -> usize {
+        self.chunk().write_jump(op, Lineno(line))
+    }
+
+    /// Jump back to `loop_start`; a loop too long to jump is reported at
+    /// `keyword`.
+    fn emit_loop(&mut self, loop_start: usize, keyword: Span, lexeme: &str) {
+        if self.chunk().write_loop(loop_start, Lineno(keyword.line)).is_err() {
+            self.error(keyword, lexeme, "Loop body too large");
+        }
+    }
+
+    fn emit_return(&mut self, line: usize) {
+        self.emit(Opcode::OP_NIL, line);
+        self.emit(Opcode::OP_RETURN, line);
+    }
+
+    fn emit_constant(&mut self, value: Value, line: usize) {
+        let idx = self.chunk().add_constant(value);
+        self.emit(Opcode::OP_CONSTANT(idx), line);
+    }
+
+    /// Land the jump at `offset` here; code too long to jump over is reported
+    /// at `keyword`.
+    fn patch_jump(&mut self, offset: usize, keyword: Span, lexeme: &str) {
+        if self.chunk().patch_jump(offset).is_err() {
+            self.error(keyword, lexeme, "Too much code to jump over");
+        }
+    }
+
+    fn error(&mut self, span: Span, lexeme: &str, message: &str) {
+        self.errors.push(CompileError {
+            message: message.to_string(),
+            line: span.line,
+            offset: span.offset,
+            len: lexeme.len().max(1),
+            lexeme: Some(lexeme.to_string()),
+        });
+    }
+
+    /* ==================== scopes =========================== */
+    fn begin_scope(&mut self) {
+        self.state.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, line: usize) {
+        self.state.scope_depth -= 1;
+        while let Some(local) = self.state.locals.last() {
+            if local.depth <= self.state.scope_depth {
+                break;
+            }
+            self.emit(Opcode::OP_POP, line);
+            self.state.locals.pop();
+            self.close_local(self.state.locals.len());
+        }
+    }
+
+    fn global_slot(&mut self, name: &Ident) -> usize {
+        let identifier = self.gc.intern(name.name.clone());
+        self.globals.slot(identifier)
+    }
+
+    fn declare_local(&mut self, name: &Ident) {
+        let exists = self
+            .state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth == -1 || local.depth >= self.state.scope_depth)
+            .any(|local| local.name == name.name);
+        if exists {
+            let msg = format!("Variable with name {} already exists", name.name);
+            self.error(name.span, &name.name, &msg);
+        }
+        if self.state.locals.len() == STACK_SIZE {
+            self.error(name.span, &name.name, "Stack overflow; too many local variables");
+            return;
+        }
+        self.state.locals.push(Local {
+            name: name.name.clone(),
+            depth: -1,
+        });
+    }
+
+    fn mark_initialized(&mut self) {
+        let slot = self.state.locals.len() - 1;
+        if self.state.locals[slot].depth == -1 {
+            // record the local's name for debuggers
+            let name = self.state.locals[slot].name.clone();
+            let start = self.chunk().code.len();
+            self.chunk().locals.push(LocalVar {
+                name,
+                slot,
+                start,
+                end: usize::MAX,
+            });
+        }
+        self.state.locals[slot].depth = self.state.scope_depth;
+    }
+
+    fn close_local(&mut self, slot: usize) {
+        let end = self.chunk().code.len();
+        if let Some(local) = self
+            .chunk()
+            .locals
+            .iter_mut()
+            .rev()
+            .find(|local| local.slot == slot && local.end == usize::MAX)
+        {
+            local.end = end;
+        }
+    }
+
+    fn resolve_local(&mut self, name: &Ident) -> Option<usize> {
+        let (slot, depth) = self
+            .state
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name.name)
+            .map(|(slot, local)| (slot, local.depth))?;
+        if depth == -1 {
+            self.error(name.span, &name.name, "Cannot read variable into its own initializer");
+        }
+        Some(slot)
+    }
+
+    /* ==================== statements ======================= */
+    fn stmt(&mut self, stmt: &Stmt) {
+        let line = stmt.span.line;
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                self.expr(expr);
+                self.emit(Opcode::OP_POP, line);
+            }
+            StmtKind::Print(expr) => {
+                self.expr(expr);
+                self.emit(Opcode::OP_PRINT, line);
+            }
+            StmtKind::Let(name, init) => {
+                let global = self.declare(name);
+                match init {
+                    Some(init) => self.expr(init),
+                    None => self.emit(Opcode::OP_NIL, line),
+                }
+                self.define(global, line);
+            }
+            StmtKind::Function(function) => {
+                let global = self.declare(&function.name);
+                if self.state.scope_depth > 0 {
+                    // a local function can refer to itself
+                    self.mark_initialized();
+                }
+                self.function(function, line);
+                self.define(global, line);
+            }
+            StmtKind::Test(test) => {
+                if !matches!(self.state.f_type, FunctionType::SCRIPT) || self.state.scope_depth > 0 {
+                    let keyword = Span { len: "test".len(), ..stmt.span };
+                    self.error(keyword, "test", "Tests can only be declared at top level");
+                }
+                let function = self.function_object(test, line);
+                let idx = self.chunk().add_constant(Value::FUNCTION(function));
+                self.chunk().tests.push(idx);
+            }
+            StmtKind::Block(body) => {
+                self.begin_scope();
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                self.end_scope(line);
+            }
+            StmtKind::If(condition, then_branch, else_branch) => {
+                let keyword = Span { len: "if".len(), ..stmt.span };
+                self.expr(condition);
+                let then_jump = self.emit_jump(Opcode::OP_JUMP_IF_FALSE(0), line);
+                self.emit(Opcode::OP_POP, line);
+                self.stmt(then_branch);
+                let else_jump = self.emit_jump(Opcode::OP_JUMP(0), line);
+                self.patch_jump(then_jump, keyword, "if");
+                self.emit(Opcode::OP_POP, line);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+                self.patch_jump(else_jump, keyword, "if");
+            }
+            StmtKind::While(condition, body) => {
+                let keyword = Span { len: "while".len(), ..stmt.span };
+                let loop_start = self.chunk().code.len();
+                self.expr(condition);
+                let exit_jump = self.emit_jump(Opcode::OP_JUMP_IF_FALSE(0), line);
+                self.emit(Opcode::OP_POP, line);
+                self.stmt(body);
+                self.emit_loop(loop_start, keyword, "while");
+                self.patch_jump(exit_jump, keyword, "while");
+                self.emit(Opcode::OP_POP, line);
+            }
+            StmtKind::For(init, condition, increment, body) => {
+                let keyword = Span { len: "for".len(), ..stmt.span };
+                self.for_loop(init.as_deref(), condition.as_ref(), increment.as_ref(), body, keyword);
+            }
+            StmtKind::Return(value) => {
+                if let FunctionType::SCRIPT = self.state.f_type {
+                    let keyword = Span { len: "return".len(), ..stmt.span };
+                    self.error(keyword, "return", "Cannot return from top-level code");
+                }
+                match value {
+                    Some(value) => {
+                        self.expr(value);
+                        if ends_in_call(value) {
+                            // the call is the last thing `expr` wrote, two bytes
+                            let end = self.chunk().code.len();
+                            self.chunk().make_tail_call(end - 2);
+                        }
+                        self.emit(Opcode::OP_RETURN, line);
+                    }
+                    None => self.emit_return(line),
+                }
+            }
+        }
+    }
+
+    fn for_loop(
+        &mut self,
+        init: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+        keyword: Span,
+    ) {
+        let line = keyword.line;
+        self.begin_scope();
+        if let Some(init) = init {
+            self.stmt(init);
+        }
+
+        let mut loop_start = self.chunk().code.len();
+        let mut exit_jump = None;
+        if let Some(condition) = condition {
+            self.expr(condition);
+            exit_jump = Some(self.emit_jump(Opcode::OP_JUMP_IF_FALSE(0), line));
+            self.emit(Opcode::OP_POP, line);
+        }
+
+        if let Some(increment) = increment {
+            let body_jump = self.emit_jump(Opcode::OP_JUMP(0), line);
+            let increment_start = self.chunk().code.len();
+            self.expr(increment);
+            self.emit(Opcode::OP_POP, line);
+            self.emit_loop(loop_start, keyword, "for");
+            loop_start = increment_start;
+            self.patch_jump(body_jump, keyword, "for");
+        }
+
+        self.stmt(body);
+        self.emit_loop(loop_start, keyword, "for");
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump, keyword, "for");
+            self.emit(Opcode::OP_POP, line);
+        }
+        self.end_scope(line);
+    }
+
+    /// declares `name` in the current scope, returning its constant index if it's a global
+    fn declare(&mut self, name: &Ident) -> Option<usize> {
+        if self.state.scope_depth > 0 {
+            self.declare_local(name);
+            None
+        } else {
+            Some(self.global_slot(name))
+        }
+    }
+
+    fn define(&mut self, global: Option<usize>, line: usize) {
+        match global {
+            Some(idx) => self.emit(Opcode::OP_DEFINE_GLOBAL(idx), line),
+            None => self.mark_initialized(),
+        }
+    }
+
+    fn function(&mut self, function: &Function, line: usize) {
+        let function = self.function_object(function, line);
+        self.emit_constant(Value::FUNCTION(function), line);
+    }
+
+    fn function_object(&mut self, function: &Function, line: usize) -> GcRef<ObjFunction> {
+        let name = self.gc.intern(function.name.name.clone());
+        let state = FunctionState::new(ObjFunction::new(name), FunctionType::FUNCTION);
+        let enclosing = mem::replace(&mut self.state, state);
+        self.state.enclosing = Some(enclosing);
+
+        self.begin_scope();
+        self.state.function.arity = function.params.len() as u8;
+        for param in &function.params {
+            self.declare_local(param);
+            self.mark_initialized();
+        }
+        for stmt in &function.body {
+            self.stmt(stmt);
+        }
+        let end_line = function.body.last().map_or(line, |stmt| stmt.span.line);
+        self.emit_return(end_line);
+
+        let enclosing = self.state.enclosing.take().expect("Enclosing function not found");
+        let state = mem::replace(&mut self.state, enclosing);
+        self.gc.alloc(state.function)
+    }
+
+    /* ==================== expressions ====================== */
+    fn expr(&mut self, expr: &Expr) {
+        let line = expr.span.line;
+        match &expr.kind {
+            ExprKind::Number(n) => self.emit_constant(Value::NUMBER(*n), line),
+            ExprKind::Str(s) => {
+                let interned = self.gc.intern(s.clone());
+                self.emit_constant(Value::STR(interned), line);
+            }
+            ExprKind::Bool(true) => self.emit(Opcode::OP_TRUE, line),
+            ExprKind::Bool(false) => self.emit(Opcode::OP_FALSE, line),
+            ExprKind::Nil => self.emit(Opcode::OP_NIL, line),
+            ExprKind::Variable(name) => {
+                let op = match self.resolve_local(name) {
+                    Some(slot) => Opcode::OP_GET_LOCAL(slot),
+                    None => Opcode::OP_GET_GLOBAL(self.global_slot(name)),
+                };
+                self.emit(op, line);
+            }
+            ExprKind::Assign(name, value) => {
+                let op = match self.resolve_local(name) {
+                    Some(slot) => Opcode::OP_SET_LOCAL(slot),
+                    None => Opcode::OP_SET_GLOBAL(self.global_slot(name)),
+                };
+                self.expr(value);
+                self.emit(op, line);
+            }
+            ExprKind::Unary(op, operand) => {
+                self.expr(operand);
+                match op {
+                    UnaryOp::Negate => self.emit(Opcode::OP_NEGATE, line),
+                    UnaryOp::Not => self.emit(Opcode::OP_NOT, line),
+                }
+            }
+            ExprKind::Binary(op, left, right) => {
+                self.expr(left);
+                self.expr(right);
+                self.binary_op(*op, line);
+            }
+            ExprKind::Logical(LogicalOp::And, left, right) => {
+                self.expr(left);
+                let jump = self.emit_jump(Opcode::OP_JUMP_IF_FALSE(0), line);
+                self.emit(Opcode::OP_POP, line);
+                self.expr(right);
+                self.patch_jump(jump, expr.span, "and");
+            }
+            ExprKind::Logical(LogicalOp::Or, left, right) => {
+                self.expr(left);
+                let else_jump = self.emit_jump(Opcode::OP_JUMP_IF_FALSE(0), line);
+                let end_jump = self.emit_jump(Opcode::OP_JUMP(0), line);
+                self.patch_jump(else_jump, expr.span, "or");
+                self.emit(Opcode::OP_POP, line);
+                self.expr(right);
+                self.patch_jump(end_jump, expr.span, "or");
+            }
+            ExprKind::Call(callee, args) => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(Opcode::OP_CALL(args.len() as u8), line);
+            }
+            ExprKind::Grouping(inner) => self.expr(inner),
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Opcode::OP_BUILD_LIST(items.len()), line);
+            }
+            ExprKind::Index(list, index) => {
+                self.expr(list);
+                self.expr(index);
+                self.emit(Opcode::OP_GET_INDEX, line);
+            }
+            ExprKind::SetIndex(list, index, value) => {
+                self.expr(list);
+                self.expr(index);
+                self.expr(value);
+                self.emit(Opcode::OP_SET_INDEX, line);
+            }
+        }
+    }
+
+    fn binary_op(&mut self, op: BinaryOp, line: usize) {
+        // todo: use dedicated opcodes and implementations for double operators
+        let (first, negate) = match op {
+            BinaryOp::Add => (Opcode::OP_ADD, false),
+            BinaryOp::Subtract => (Opcode::OP_SUBSTRACT, false),
+            BinaryOp::Multiply => (Opcode::OP_MULTIPLY, false),
+            BinaryOp::Divide => (Opcode::OP_DIVIDE, false),
+            BinaryOp::Greater => (Opcode::OP_GT, false),
+            BinaryOp::Less => (Opcode::OP_LT, false),
+            BinaryOp::Equal => (Opcode::OP_EQ, false),
+            BinaryOp::GreaterEqual => (Opcode::OP_LT, true),
+            BinaryOp::LessEqual => (Opcode::OP_GT, true),
+            BinaryOp::NotEqual => (Opcode::OP_EQ, true),
+        };
+        self.emit(first, line);
+        if negate {
+            self.emit(Opcode::OP_NOT, line);
+        }
+    }
+}
+
+/// Whether the last instruction `expr` compiles to is a call whose result is
+/// the value: a call, in parentheses or on the right of `and`/`or`.
+fn ends_in_call(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Call(..) => true,
+        ExprKind::Grouping(inner) | ExprKind::Logical(_, _, inner) => ends_in_call(inner),
+        _ => false,
+    }
+}
@@ -0,0 +1,510 @@
+use crate::{
+    compiler::{analysis::CompileError, precedence::Precedence},
+    lexer::Lexer,
+    token::{Token, TokenType},
+};
+
+use super::{BinaryOp, Expr, ExprKind, Function, Ident, LogicalOp, Span, Stmt, StmtKind, UnaryOp};
+
+/// Recursive descent parser producing a syntax tree. Accepts the same language,
+/// with the same error messages and recovery, as the single-pass compiler.
+pub struct Parser {
+    previous: Token,
+    current: Token,
+    lexer: Lexer,
+    errors: Vec<CompileError>,
+    panic_mode: bool,
+}
+
+impl Parser {
+    pub fn new(lexer: Lexer) -> Parser {
+        Parser {
+            previous: Token::new_def(),
+            current: Token::new_def(),
+            lexer,
+            errors: Vec::new(),
+            panic_mode: false,
+        }
+    }
+
+    pub fn parse(mut self) -> Result<Vec<Stmt>, Vec<CompileError>> {
+        self.advance();
+        let mut program = Vec::new();
+        while !self.match_token(TokenType::EOF) {
+            program.push(self.declaration());
+        }
+        if self.errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    /* ======================= plumbing ====================== */
+    fn advance(&mut self) {
+        self.previous = self.current.clone();
+        loop {
+            self.current = self.lexer.next_token();
+            match self.current.type_ {
+                TokenType::COMMENT => continue,
+                TokenType::ILLEGAL => {
+                    let msg = self.current.literal.clone();
+                    self.error_at_current(&msg);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn check(&self, type_: TokenType) -> bool {
+        self.current.type_ == type_
+    }
+
+    /// `test` is only a keyword when it starts a declaration like `test "name"`,
+    /// so scripts can still use it as a name.
+    fn at_test_block(&mut self) -> bool {
+        self.check(TokenType::IDENT) && self.current.literal == "test" && self.lexer.peek_token().type_ == TokenType::STRING
+    }
+
+    fn match_token(&mut self, type_: TokenType) -> bool {
+        if self.check(type_) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume(&mut self, type_: TokenType, err: &str) {
+        if self.check(type_) {
+            self.advance();
+        } else {
+            self.error_at_current(err);
+        }
+    }
+
+    fn span_of(token: &Token) -> Span {
+        // string literals don't include their quotes
+        let len = match token.type_ {
+            TokenType::STRING => token.literal.len() + 2,
+            TokenType::EOF | TokenType::ILLEGAL => 0,
+            _ => token.literal.len(),
+        };
+        Span {
+            offset: token.offset,
+            len,
+            line: token.lineno,
+        }
+    }
+
+    /// from `start` up to the end of the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        start.to(Parser::span_of(&self.previous))
+    }
+
+    fn ident(&self) -> Ident {
+        Ident {
+            name: self.previous.literal.clone(),
+            span: Parser::span_of(&self.previous),
+        }
+    }
+
+    /* ====================== errors ========================= */
+    fn error_at(&mut self, token: Token, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+        let error = CompileError::at(&token, message, self.lexer.source());
+        self.errors.push(error);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.error_at(self.previous.clone(), message);
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        self.error_at(self.current.clone(), message);
+    }
+
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+        while self.current.type_ != TokenType::EOF {
+            if self.previous.type_ == TokenType::SEMICOLON || self.at_test_block() {
+                return;
+            }
+            match self.current.type_ {
+                TokenType::FUNCTION
+                | TokenType::LET
+                | TokenType::FOR
+                | TokenType::IF
+                | TokenType::WHILE
+                | TokenType::PRINT
+                | TokenType::RETURN => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    /* ==================== declarations ===================== */
+    fn declaration(&mut self) -> Stmt {
+        let stmt = if self.match_token(TokenType::FUNCTION) {
+            self.function_declaration()
+        } else if self.match_token(TokenType::LET) {
+            self.variable_declaration()
+        } else if self.at_test_block() {
+            self.advance();
+            self.test_declaration()
+        } else {
+            self.statement()
+        };
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+        stmt
+    }
+
+    fn function_declaration(&mut self) -> Stmt {
+        let start = Parser::span_of(&self.previous);
+        self.consume(TokenType::IDENT, "Expected Function name");
+        let name = self.ident();
+
+        let mut params = Vec::new();
+        self.consume(TokenType::LPAREN, "Expected '(' after function name");
+        if !self.check(TokenType::RPAREN) {
+            loop {
+                if params.len() == u8::MAX as usize {
+                    let msg = format!("Cannot have more than {} parameters", u8::MAX);
+                    self.error_at_current(&msg);
+                }
+                self.consume(TokenType::IDENT, "Expected parameter name");
+                params.push(self.ident());
+                if !self.match_token(TokenType::COMMA) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RPAREN, "Expected ')' after parameters");
+        self.consume(TokenType::LBRACE, "Expected '{' before function body");
+        let body = self.block();
+
+        let function = Function { name, params, body };
+        self.stmt(StmtKind::Function(function), start)
+    }
+
+    fn test_declaration(&mut self) -> Stmt {
+        let start = Parser::span_of(&self.previous);
+        self.consume(TokenType::STRING, "Expected test name");
+        let name = Ident {
+            name: self.previous.literal.clone(),
+            span: Parser::span_of(&self.previous),
+        };
+        self.consume(TokenType::LBRACE, "Expected '{' before test body");
+        let body = self.block();
+        let test = Function {
+            name,
+            params: Vec::new(),
+            body,
+        };
+        self.stmt(StmtKind::Test(test), start)
+    }
+
+    fn variable_declaration(&mut self) -> Stmt {
+        let start = Parser::span_of(&self.previous);
+        self.consume(TokenType::IDENT, "Expected variable name");
+        let name = self.ident();
+        let init = if self.match_token(TokenType::ASSIGN) {
+            Some(self.expression())
+        } else {
+            None
+        };
+        self.consume(
+            TokenType::SEMICOLON,
+            "Expected ';' after variable declaration",
+        );
+        self.stmt(StmtKind::Let(name, init), start)
+    }
+
+    fn stmt(&self, kind: StmtKind, start: Span) -> Stmt {
+        Stmt {
+            kind,
+            span: self.span_from(start),
+        }
+    }
+
+    /* ==================== statements ======================= */
+    fn statement(&mut self) -> Stmt {
+        if self.match_token(TokenType::PRINT) {
+            self.print_statement()
+        } else if self.match_token(TokenType::LBRACE) {
+            let start = Parser::span_of(&self.previous);
+            let body = self.block();
+            self.stmt(StmtKind::Block(body), start)
+        } else if self.match_token(TokenType::IF) {
+            self.if_statement()
+        } else if self.match_token(TokenType::RETURN) {
+            self.return_statement()
+        } else if self.match_token(TokenType::WHILE) {
+            self.while_statement()
+        } else if self.match_token(TokenType::FOR) {
+            self.for_statement()
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    fn print_statement(&mut self) -> Stmt {
+        let start = Parser::span_of(&self.previous);
+        let value = self.expression();
+        self.consume(TokenType::SEMICOLON, "Expected ';' after value");
+        self.stmt(StmtKind::Print(value), start)
+    }
+
+    fn if_statement(&mut self) -> Stmt {
+        let start = Parser::span_of(&self.previous);
+        self.consume(TokenType::LPAREN, "Expected '(' before expression");
+        let condition = self.expression();
+        self.consume(TokenType::RPAREN, "Expected ')' after expression");
+        let then_branch = Box::new(self.statement());
+        let else_branch = if self.match_token(TokenType::ELSE) {
+            Some(Box::new(self.statement()))
+        } else {
+            None
+        };
+        self.stmt(StmtKind::If(condition, then_branch, else_branch), start)
+    }
+
+    fn while_statement(&mut self) -> Stmt {
+        let start = Parser::span_of(&self.previous);
+        self.consume(TokenType::LPAREN, "Expected '(' after while");
+        let condition = self.expression();
+        self.consume(TokenType::RPAREN, "Expected ')' after condition");
+        let body = Box::new(self.statement());
+        self.stmt(StmtKind::While(condition, body), start)
+    }
+
+    fn for_statement(&mut self) -> Stmt {
+        let start = Parser::span_of(&self.previous);
+        self.consume(TokenType::LPAREN, "Expected '(' after for");
+        let init = if self.match_token(TokenType::SEMICOLON) {
+            None
+        } else if self.match_token(TokenType::LET) {
+            Some(Box::new(self.variable_declaration()))
+        } else {
+            Some(Box::new(self.expression_statement()))
+        };
+
+        let condition = if self.match_token(TokenType::SEMICOLON) {
+            None
+        } else {
+            let condition = self.expression();
+            self.consume(TokenType::SEMICOLON, "Expected ';' after loop condition");
+            Some(condition)
+        };
+
+        let increment = if self.match_token(TokenType::RPAREN) {
+            None
+        } else {
+            let increment = self.expression();
+            self.consume(TokenType::RPAREN, "Expected ')' after for clause");
+            Some(increment)
+        };
+
+        let body = Box::new(self.statement());
+        self.stmt(StmtKind::For(init, condition, increment, body), start)
+    }
+
+    fn return_statement(&mut self) -> Stmt {
+        let start = Parser::span_of(&self.previous);
+        let value = if self.match_token(TokenType::SEMICOLON) {
+            None
+        } else {
+            let value = self.expression();
+            self.consume(TokenType::SEMICOLON, "Expected ; after return statement");
+            Some(value)
+        };
+        self.stmt(StmtKind::Return(value), start)
+    }
+
+    fn expression_statement(&mut self) -> Stmt {
+        let expr = self.expression();
+        let start = expr.span;
+        self.consume(TokenType::SEMICOLON, "Expected ; after expression");
+        self.stmt(StmtKind::Expression(expr), start)
+    }
+
+    /// statements up to and including the closing brace
+    fn block(&mut self) -> Vec<Stmt> {
+        let mut body = Vec::new();
+        while !self.check(TokenType::RBRACE) && !self.check(TokenType::EOF) {
+            body.push(self.declaration());
+        }
+        self.consume(TokenType::RBRACE, "Expected } at end of block");
+        body
+    }
+
+    /* ==================== expressions ====================== */
+    fn expression(&mut self) -> Expr {
+        self.parse_precedence(Precedence::PrecAssignment)
+    }
+
+    fn infix_precedence(type_: TokenType) -> Precedence {
+        match type_ {
+            TokenType::OR => Precedence::PrecOr,
+            TokenType::AND => Precedence::PrecAnd,
+            TokenType::EQ | TokenType::NEQ => Precedence::PrecEquality,
+            TokenType::GT | TokenType::LT | TokenType::GEQ | TokenType::LEQ => {
+                Precedence::PrecComparison
+            }
+            TokenType::PLUS | TokenType::MINUS => Precedence::PrecTerm,
+            TokenType::MUL | TokenType::DIV => Precedence::PrecFactor,
+            TokenType::LPAREN | TokenType::LBRACKET => Precedence::PrecCall,
+            _ => Precedence::PrecNone,
+        }
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Expr {
+        self.advance();
+        let can_assign = precedence <= Precedence::PrecAssignment;
+        let mut expr = self.prefix(can_assign);
+
+        while precedence <= Parser::infix_precedence(self.current.type_) {
+            self.advance();
+            expr = self.infix(expr, can_assign);
+        }
+
+        if can_assign && self.match_token(TokenType::ASSIGN) {
+            self.error("Invalid Assignment target");
+        }
+        expr
+    }
+
+    fn prefix(&mut self, can_assign: bool) -> Expr {
+        let start = Parser::span_of(&self.previous);
+        let kind = match self.previous.type_ {
+            TokenType::NUM => ExprKind::Number(self.previous.literal.parse::<f64>().unwrap()),
+            TokenType::STRING => ExprKind::Str(self.previous.literal.clone()),
+            TokenType::TRUE => ExprKind::Bool(true),
+            TokenType::FALSE => ExprKind::Bool(false),
+            TokenType::NIL => ExprKind::Nil,
+            TokenType::IDENT => {
+                let name = self.ident();
+                if can_assign && self.match_token(TokenType::ASSIGN) {
+                    let value = self.expression();
+                    ExprKind::Assign(name, Box::new(value))
+                } else {
+                    ExprKind::Variable(name)
+                }
+            }
+            TokenType::MINUS | TokenType::NOT => {
+                let op = if self.previous.type_ == TokenType::MINUS {
+                    UnaryOp::Negate
+                } else {
+                    UnaryOp::Not
+                };
+                let operand = self.parse_precedence(Precedence::PrecUnary);
+                ExprKind::Unary(op, Box::new(operand))
+            }
+            TokenType::LPAREN => {
+                let inner = self.expression();
+                self.consume(TokenType::RPAREN, "Expected )");
+                ExprKind::Grouping(Box::new(inner))
+            }
+            TokenType::LBRACKET => {
+                let mut items = Vec::new();
+                if !self.check(TokenType::RBRACKET) {
+                    loop {
+                        items.push(self.expression());
+                        if !self.match_token(TokenType::COMMA) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(TokenType::RBRACKET, "Expected ']' after list elements");
+                ExprKind::List(items)
+            }
+            _ => {
+                self.error("Expected expression");
+                ExprKind::Nil
+            }
+        };
+        Expr {
+            kind,
+            span: self.span_from(start),
+        }
+    }
+
+    fn infix(&mut self, left: Expr, can_assign: bool) -> Expr {
+        let start = left.span;
+        let operator = self.previous.type_;
+        let kind = match operator {
+            TokenType::AND | TokenType::OR => {
+                // the right operand binds at the same level, as the compiler does
+                let precedence = Parser::infix_precedence(operator);
+                let right = self.parse_precedence(precedence);
+                let op = if operator == TokenType::AND {
+                    LogicalOp::And
+                } else {
+                    LogicalOp::Or
+                };
+                ExprKind::Logical(op, Box::new(left), Box::new(right))
+            }
+            TokenType::LPAREN => {
+                let args = self.arguments();
+                ExprKind::Call(Box::new(left), args)
+            }
+            TokenType::LBRACKET => {
+                let index = self.expression();
+                self.consume(TokenType::RBRACKET, "Expected ']' after index");
+                if can_assign && self.match_token(TokenType::ASSIGN) {
+                    let value = self.expression();
+                    ExprKind::SetIndex(Box::new(left), Box::new(index), Box::new(value))
+                } else {
+                    ExprKind::Index(Box::new(left), Box::new(index))
+                }
+            }
+            _ => {
+                let right = self.parse_precedence(Parser::infix_precedence(operator).next());
+                let op = match operator {
+                    TokenType::PLUS => BinaryOp::Add,
+                    TokenType::MINUS => BinaryOp::Subtract,
+                    TokenType::MUL => BinaryOp::Multiply,
+                    TokenType::DIV => BinaryOp::Divide,
+                    TokenType::GT => BinaryOp::Greater,
+                    TokenType::GEQ => BinaryOp::GreaterEqual,
+                    TokenType::LT => BinaryOp::Less,
+                    TokenType::LEQ => BinaryOp::LessEqual,
+                    TokenType::EQ => BinaryOp::Equal,
+                    TokenType::NEQ => BinaryOp::NotEqual,
+                    _ => unreachable!(),
+                };
+                ExprKind::Binary(op, Box::new(left), Box::new(right))
+            }
+        };
+        Expr {
+            kind,
+            span: self.span_from(start),
+        }
+    }
+
+    fn arguments(&mut self) -> Vec<Expr> {
+        let mut args = Vec::new();
+        if !self.check(TokenType::RPAREN) {
+            loop {
+                let arg = self.expression();
+                if args.len() == u8::MAX as usize {
+                    let msg = format!("Cannot have more than {} arguments", u8::MAX);
+                    self.error(&msg);
+                } else {
+                    args.push(arg);
+                }
+                if !self.match_token(TokenType::COMMA) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RPAREN, "Expected ')' after arguments.");
+        args
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    compiler,
+    gc::Gc,
+    globals::Globals,
+    object::ObjFunction,
+    value::Value,
+    vm::{Frontend, InterpretError, Vm},
+};
+
+use super::{compile, parse, to_json, BinaryOp, ExprKind, LogicalOp, Span, StmtKind};
+
+const PROGRAMS: [&str; 10] = [
+    "let x = 1 + 2 * 3 - -4 / (2 - 1);\nprint x >= 3 and x != 4 or !true;\n",
+    "let a = \"s\";\na = a + \"t\";\nlet b;\nprint a == \"st\" and b == nil;\n",
+    "{\n  let a = 1;\n  {\n    let b = a;\n    a = b = 3;\n  }\n  print a <= 2;\n}\n",
+    "let i = 0;\nwhile (i < 3) {\n  if (i > 1) print i; else { print -i; }\n  i = i + 1;\n}\n",
+    "for (let i = 0; i < 2; i = i + 1) print i;\nfor (;;) { }\nlet j = 0;\nfor (j = 1; j < 3;) j = j + 1;\n",
+    "fn fib(n) {\n  if (n < 2) return n;\n  return fib(n - 1) + fib(n - 2);\n}\nprint fib(10);\n",
+    "fn outer() {\n  fn inner(a, b) { return; }\n  let r = inner(1, 2);\n  return r;\n}\nprint outer() == nil or false and true;\n",
+    "let xs = [1, [\"a\", nil], []];\nxs[1][0] = xs[0] = len(args);\nprint push(xs, 2)[3] + xs[0];\n",
+    "fn add(a, b) { return a + b; }\ntest \"adds\" {\n  let r = add(1, 2);\n  assert_eq(r, 3);\n}\nprint add(2, 2);\n",
+    "fn f(n, a) {\n  if (n == 0) return a or len(args);\n  if (n < 0) return -f(0, a);\n  return (f(n - 1, a + 1));\n}\nprint f(3, 0);\n",
+];
+
+/// opcodes and constants of `function` and every function nested in it
+fn bytecode(function: &ObjFunction) -> Vec<String> {
+    let mut out: Vec<String> = function.chunk.instructions().map(|(_, op)| format!("{:?}", op)).collect();
+    for constant in &function.chunk.constants {
+        match constant {
+            Value::FUNCTION(nested) => {
+                out.push(format!("fn {}/{}", nested.name.s, nested.arity));
+                out.extend(bytecode(nested));
+            }
+            other => out.push(other.to_string()),
+        }
+    }
+    out
+}
+
+fn compile_error(source: &str) -> String {
+    match compile(source.to_string(), &mut Gc::new(), &mut Globals::new()) {
+        Err(InterpretError::InterpretCompileError(message)) => message,
+        other => panic!("expected compile error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn lowers_to_the_same_bytecode_as_the_compiler() {
+    for source in PROGRAMS {
+        let mut gc = Gc::new();
+        let expected = compiler::compile(source.to_string(), &mut gc, &mut Globals::new()).unwrap();
+        let actual = compile(source.to_string(), &mut gc, &mut Globals::new()).unwrap();
+        assert_eq!(bytecode(&actual), bytecode(&expected), "{}", source);
+    }
+}
+
+#[test]
+fn only_calls_in_tail_position_become_tail_calls() {
+    let source = "fn f(n) {\n  if (n > 0) return f(n - 1);\n  let r = f(1);\n  return 1 + f(r);\n}\nprint f(2);\n";
+    for function in [
+        compiler::compile(source.to_string(), &mut Gc::new(), &mut Globals::new()).unwrap(),
+        compile(source.to_string(), &mut Gc::new(), &mut Globals::new()).unwrap(),
+    ] {
+        let ops = bytecode(&function);
+        let calls: Vec<&str> = ops.iter().filter(|op| op.contains("CALL")).map(String::as_str).collect();
+        assert_eq!(calls, ["OP_CALL(1)", "OP_TAIL_CALL(1)", "OP_CALL(1)", "OP_CALL(1)"]);
+    }
+}
+
+#[test]
+fn runs_programs_through_the_tree() {
+    let mut vm = Vm::init_vm();
+    vm.set_frontend(Frontend::Ast);
+    let source = "fn fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\nlet r = fib(15);";
+    assert!(vm.interpret(source.to_string()).is_ok());
+    let r = vm.globals().find(|(name, _)| name.s == "r").map(|(_, value)| value);
+    assert_eq!(r.and_then(|value| value.get_number()), Some(610.0));
+}
+
+#[test]
+fn builds_typed_nodes_with_spans() {
+    let program = parse("let x = 1 + 2 * y;\nprint a or b;").unwrap();
+    assert_eq!(program.len(), 2);
+    assert_eq!(program[0].span, Span { offset: 0, len: 18, line: 1 });
+    let (name, init) = match &program[0].kind {
+        StmtKind::Let(name, Some(init)) => (name, init),
+        other => panic!("expected let, got {:?}", other),
+    };
+    assert_eq!(name.name, "x");
+    assert_eq!(name.span, Span { offset: 4, len: 1, line: 1 });
+    match &init.kind {
+        ExprKind::Binary(BinaryOp::Add, left, right) => {
+            assert_eq!(left.kind, ExprKind::Number(1.0));
+            assert!(matches!(right.kind, ExprKind::Binary(BinaryOp::Multiply, _, _)));
+            assert_eq!(right.span, Span { offset: 12, len: 5, line: 1 });
+        }
+        other => panic!("expected addition, got {:?}", other),
+    }
+    match &program[1].kind {
+        StmtKind::Print(expr) => {
+            assert!(matches!(expr.kind, ExprKind::Logical(LogicalOp::Or, _, _)));
+            assert_eq!(expr.span, Span { offset: 25, len: 6, line: 2 });
+        }
+        other => panic!("expected print, got {:?}", other),
+    }
+}
+
+#[test]
+fn string_spans_include_quotes() {
+    let program = parse("print \"hi\";").unwrap();
+    match &program[0].kind {
+        StmtKind::Print(expr) => assert_eq!(expr.span, Span { offset: 6, len: 4, line: 1 }),
+        other => panic!("expected print, got {:?}", other),
+    }
+}
+
+#[test]
+fn syntax_errors_match_the_compiler() {
+    let source = "let = 1;\nprint (1;\nlet ok = 2;\n1 + 2 = 3;";
+    let errors = parse(source).unwrap_err();
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    let expected = match compiler::compile(source.to_string(), &mut Gc::new(), &mut Globals::new()) {
+        Err(InterpretError::InterpretCompileError(message)) => message,
+        _ => panic!("expected compile error"),
+    };
+    assert_eq!(messages.join("\n"), expected);
+    assert_eq!(errors.len(), 3);
+}
+
+#[test]
+fn scope_errors_are_reported_by_codegen() {
+    assert_eq!(
+        compile_error("return 1;"),
+        "[line 1] Error at 'return': Cannot return from top-level code"
+    );
+    assert_eq!(
+        compile_error("{\n  let a = 1;\n  let a = 2;\n}"),
+        "[line 3] Error at 'a': Variable with name a already exists"
+    );
+    assert_eq!(
+        compile_error("{ let a = a; }"),
+        "[line 1] Error at 'a': Cannot read variable into its own initializer"
+    );
+    let nested = "fn f() {\n  test \"inner\" { }\n}";
+    let expected = match compiler::compile(nested.to_string(), &mut Gc::new(), &mut Globals::new()) {
+        Err(InterpretError::InterpretCompileError(message)) => message,
+        _ => panic!("expected compile error"),
+    };
+    assert_eq!(compile_error(nested), expected);
+    assert_eq!(expected, "[line 2] Error at 'test': Tests can only be declared at top level");
+}
+
+#[test]
+fn dumps_json() {
+    let program = parse("fn f(a) { return a; }\nf(1);").unwrap();
+    let json = to_json(&program);
+    assert_eq!(json[0]["kind"], "Function");
+    assert_eq!(json[0]["name"]["name"], "f");
+    assert_eq!(json[0]["params"][0]["name"], "a");
+    assert_eq!(json[0]["body"][0]["kind"], "Return");
+    assert_eq!(json[0]["body"][0]["value"]["kind"], "Variable");
+    assert_eq!(json[1]["expression"]["kind"], "Call");
+    assert_eq!(json[1]["expression"]["arguments"][0]["value"], 1.0);
+    assert_eq!(json[1]["span"]["line"], 2);
+
+    let program = parse("xs[0] = [1];").unwrap();
+    let json = to_json(&program);
+    assert_eq!(json[0]["expression"]["kind"], "SetIndex");
+    assert_eq!(json[0]["expression"]["list"]["kind"], "Variable");
+    assert_eq!(json[0]["expression"]["value"]["items"][0]["value"], 1.0);
+}
@@ -1,5 +1,6 @@
+/// One instruction. Chunks store instructions encoded as bytes: a tag byte
+/// followed by the operand, see `encode` and `decode_with`.
 #[allow(non_camel_case_types)]
-
 #[derive(Debug, Clone, Copy)]
 pub enum Opcode {
     OP_CONSTANT(usize),
@@ -31,5 +32,165 @@ pub enum Opcode {
     OP_JUMP(usize),
     OP_JUMP_IF_FALSE(usize),
     OP_LOOP(usize),
-    OP_CALL(u8)
+    OP_CALL(u8),
+    /// `return f(args)`: a call that takes over the caller's frame
+    OP_TAIL_CALL(u8),
+    // lists
+    OP_BUILD_LIST(usize),
+    OP_GET_INDEX,
+    OP_SET_INDEX,
+}
+
+/// The byte each encoded instruction starts with.
+pub mod tag {
+    pub const OP_CONSTANT: u8 = 0;
+    pub const OP_RETURN: u8 = 1;
+    pub const OP_NEGATE: u8 = 2;
+    pub const OP_ADD: u8 = 3;
+    pub const OP_SUBSTRACT: u8 = 4;
+    pub const OP_MULTIPLY: u8 = 5;
+    pub const OP_DIVIDE: u8 = 6;
+    pub const OP_MOD: u8 = 7;
+    pub const OP_TRUE: u8 = 8;
+    pub const OP_FALSE: u8 = 9;
+    pub const OP_NOT: u8 = 10;
+    pub const OP_NIL: u8 = 11;
+    pub const OP_EQ: u8 = 12;
+    pub const OP_GT: u8 = 13;
+    pub const OP_LT: u8 = 14;
+    pub const OP_DEFINE_GLOBAL: u8 = 15;
+    pub const OP_GET_GLOBAL: u8 = 16;
+    pub const OP_SET_GLOBAL: u8 = 17;
+    pub const OP_GET_LOCAL: u8 = 18;
+    pub const OP_SET_LOCAL: u8 = 19;
+    pub const OP_PRINT: u8 = 20;
+    pub const OP_POP: u8 = 21;
+    pub const OP_JUMP: u8 = 22;
+    pub const OP_JUMP_IF_FALSE: u8 = 23;
+    pub const OP_LOOP: u8 = 24;
+    pub const OP_CALL: u8 = 25;
+    pub const OP_BUILD_LIST: u8 = 26;
+    pub const OP_GET_INDEX: u8 = 27;
+    pub const OP_SET_INDEX: u8 = 28;
+    pub const OP_TAIL_CALL: u8 = 29;
+}
+
+/// Bytes taken by a jump offset. Jumps are fixed width so they can be
+/// patched once the code they jump over is known.
+pub const JUMP_OPERAND_LEN: usize = 4;
+
+impl Opcode {
+    /// Append the encoding of this instruction to `code`. Indices and counts
+    /// take one byte below 128 and grow by a byte per 7 bits after that.
+    pub fn encode(self, code: &mut Vec<u8>) {
+        match self {
+            Opcode::OP_CONSTANT(idx) => varint(code, tag::OP_CONSTANT, idx),
+            Opcode::OP_RETURN => code.push(tag::OP_RETURN),
+            Opcode::OP_NEGATE => code.push(tag::OP_NEGATE),
+            Opcode::OP_ADD => code.push(tag::OP_ADD),
+            Opcode::OP_SUBSTRACT => code.push(tag::OP_SUBSTRACT),
+            Opcode::OP_MULTIPLY => code.push(tag::OP_MULTIPLY),
+            Opcode::OP_DIVIDE => code.push(tag::OP_DIVIDE),
+            Opcode::OP_MOD => code.push(tag::OP_MOD),
+            Opcode::OP_TRUE => code.push(tag::OP_TRUE),
+            Opcode::OP_FALSE => code.push(tag::OP_FALSE),
+            Opcode::OP_NOT => code.push(tag::OP_NOT),
+            Opcode::OP_NIL => code.push(tag::OP_NIL),
+            Opcode::OP_EQ => code.push(tag::OP_EQ),
+            Opcode::OP_GT => code.push(tag::OP_GT),
+            Opcode::OP_LT => code.push(tag::OP_LT),
+            Opcode::OP_DEFINE_GLOBAL(idx) => varint(code, tag::OP_DEFINE_GLOBAL, idx),
+            Opcode::OP_GET_GLOBAL(idx) => varint(code, tag::OP_GET_GLOBAL, idx),
+            Opcode::OP_SET_GLOBAL(idx) => varint(code, tag::OP_SET_GLOBAL, idx),
+            Opcode::OP_GET_LOCAL(slot) => varint(code, tag::OP_GET_LOCAL, slot),
+            Opcode::OP_SET_LOCAL(slot) => varint(code, tag::OP_SET_LOCAL, slot),
+            Opcode::OP_PRINT => code.push(tag::OP_PRINT),
+            Opcode::OP_POP => code.push(tag::OP_POP),
+            Opcode::OP_JUMP(jump) => fixed(code, tag::OP_JUMP, jump),
+            Opcode::OP_JUMP_IF_FALSE(jump) => fixed(code, tag::OP_JUMP_IF_FALSE, jump),
+            Opcode::OP_LOOP(jump) => fixed(code, tag::OP_LOOP, jump),
+            Opcode::OP_CALL(args) => code.extend([tag::OP_CALL, args]),
+            Opcode::OP_TAIL_CALL(args) => code.extend([tag::OP_TAIL_CALL, args]),
+            Opcode::OP_BUILD_LIST(count) => varint(code, tag::OP_BUILD_LIST, count),
+            Opcode::OP_GET_INDEX => code.push(tag::OP_GET_INDEX),
+            Opcode::OP_SET_INDEX => code.push(tag::OP_SET_INDEX),
+        }
+    }
+
+    /// Decode one instruction, pulling its bytes from `next` in order. The VM
+    /// dispatches on the tag itself and reads operands with `read_varint` and
+    /// `read_jump`; everything else goes through `Chunk::instructions`.
+    #[inline(always)]
+    pub fn decode_with(mut next: impl FnMut() -> u8) -> Opcode {
+        match next() {
+            tag::OP_CONSTANT => Opcode::OP_CONSTANT(read_varint(&mut next)),
+            tag::OP_RETURN => Opcode::OP_RETURN,
+            tag::OP_NEGATE => Opcode::OP_NEGATE,
+            tag::OP_ADD => Opcode::OP_ADD,
+            tag::OP_SUBSTRACT => Opcode::OP_SUBSTRACT,
+            tag::OP_MULTIPLY => Opcode::OP_MULTIPLY,
+            tag::OP_DIVIDE => Opcode::OP_DIVIDE,
+            tag::OP_MOD => Opcode::OP_MOD,
+            tag::OP_TRUE => Opcode::OP_TRUE,
+            tag::OP_FALSE => Opcode::OP_FALSE,
+            tag::OP_NOT => Opcode::OP_NOT,
+            tag::OP_NIL => Opcode::OP_NIL,
+            tag::OP_EQ => Opcode::OP_EQ,
+            tag::OP_GT => Opcode::OP_GT,
+            tag::OP_LT => Opcode::OP_LT,
+            tag::OP_DEFINE_GLOBAL => Opcode::OP_DEFINE_GLOBAL(read_varint(&mut next)),
+            tag::OP_GET_GLOBAL => Opcode::OP_GET_GLOBAL(read_varint(&mut next)),
+            tag::OP_SET_GLOBAL => Opcode::OP_SET_GLOBAL(read_varint(&mut next)),
+            tag::OP_GET_LOCAL => Opcode::OP_GET_LOCAL(read_varint(&mut next)),
+            tag::OP_SET_LOCAL => Opcode::OP_SET_LOCAL(read_varint(&mut next)),
+            tag::OP_PRINT => Opcode::OP_PRINT,
+            tag::OP_POP => Opcode::OP_POP,
+            tag::OP_JUMP => Opcode::OP_JUMP(read_jump(&mut next)),
+            tag::OP_JUMP_IF_FALSE => Opcode::OP_JUMP_IF_FALSE(read_jump(&mut next)),
+            tag::OP_LOOP => Opcode::OP_LOOP(read_jump(&mut next)),
+            tag::OP_CALL => Opcode::OP_CALL(next()),
+            tag::OP_TAIL_CALL => Opcode::OP_TAIL_CALL(next()),
+            tag::OP_BUILD_LIST => Opcode::OP_BUILD_LIST(read_varint(&mut next)),
+            tag::OP_GET_INDEX => Opcode::OP_GET_INDEX,
+            tag::OP_SET_INDEX => Opcode::OP_SET_INDEX,
+            tag => panic!("invalid opcode {}", tag),
+        }
+    }
+}
+
+fn varint(code: &mut Vec<u8>, tag: u8, mut n: usize) {
+    code.push(tag);
+    while n >= 0x80 {
+        code.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    code.push(n as u8);
+}
+
+/// An index or count operand.
+#[inline(always)]
+pub fn read_varint(next: &mut impl FnMut() -> u8) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let byte = next();
+        n |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return n;
+        }
+        shift += 7;
+    }
+}
+
+fn fixed(code: &mut Vec<u8>, tag: u8, jump: usize) {
+    // `Chunk` refuses jumps this can't hold before they get here
+    let jump = u32::try_from(jump).expect("jump too far to encode");
+    code.push(tag);
+    code.extend(jump.to_le_bytes());
+}
+
+/// A jump operand, in bytes from the end of the jump instruction.
+#[inline(always)]
+pub fn read_jump(next: &mut impl FnMut() -> u8) -> usize {
+    u32::from_le_bytes([next(), next(), next(), next()]) as usize
 }
@@ -1,19 +1,44 @@
-use crate::{bytecode::Opcode, value::Value};
+use std::{mem::size_of, num::TryFromIntError};
+
+use crate::{
+    bytecode::{tag, Opcode, JUMP_OPERAND_LEN},
+    value::Value,
+};
 
 pub mod disassemble;
 #[derive(Debug, Clone, Copy)]
 pub struct Lineno(pub usize);
+
+/// Debug info for a named local: the stack slot it lives in and the range of
+/// instruction offsets `[start, end)` over which it is in scope.
+#[derive(Debug, Clone)]
+pub struct LocalVar {
+    pub name: String,
+    pub slot: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Clone)]
 pub struct Chunk {
-    pub code: Vec<(Opcode, Lineno)>,
+    /// encoded instructions, see `Opcode::encode`
+    pub code: Vec<u8>,
+    // run-length encoded lines: (offset where a run starts, its line)
+    lines: Vec<(usize, Lineno)>,
     pub constants: Vec<Value>,
+    pub locals: Vec<LocalVar>,
+    /// constant indices of the functions compiled from `test` blocks
+    pub tests: Vec<usize>,
 }
 
 impl Chunk {
     pub fn new() -> Chunk {
         Chunk {
-            code: Vec::<(Opcode, Lineno)>::new(),
+            code: Vec::new(),
+            lines: Vec::new(),
             constants: Vec::<Value>::new(),
+            locals: Vec::<LocalVar>::new(),
+            tests: Vec::new(),
         }
     }
 
@@ -23,8 +48,92 @@ impl Chunk {
     }
 
     pub fn write_chunk(&mut self, op: Opcode, lno: Lineno) {
-        self.code.push((op, lno));
+        if self.lines.last().map(|(_, line)| line.0) != Some(lno.0) {
+            self.lines.push((self.code.len(), lno));
+        }
+        op.encode(&mut self.code);
+    }
+
+    /// Write a forward jump to be pointed somewhere by `patch_jump`; returns
+    /// the offset of the jump.
+    pub fn write_jump(&mut self, op: Opcode, lno: Lineno) -> usize {
+        let offset = self.code.len();
+        self.write_chunk(op, lno);
+        offset
+    }
+
+    /// Make the jump at `offset` land on the next instruction written; fails,
+    /// leaving the jump alone, if that is further than an operand can hold.
+    pub fn patch_jump(&mut self, offset: usize) -> Result<(), TryFromIntError> {
+        let operand = offset + 1;
+        let jump = jump_operand(self.code.len() - (operand + JUMP_OPERAND_LEN))?;
+        self.code[operand..operand + JUMP_OPERAND_LEN].copy_from_slice(&jump.to_le_bytes());
+        Ok(())
+    }
+
+    /// Turn the call at `offset` into a tail call. Both take one operand byte.
+    pub fn make_tail_call(&mut self, offset: usize) {
+        debug_assert_eq!(self.code[offset], tag::OP_CALL);
+        self.code[offset] = tag::OP_TAIL_CALL;
     }
+
+    /// Write a jump back to `loop_start`; fails, writing nothing, if that is
+    /// further than an operand can hold.
+    pub fn write_loop(&mut self, loop_start: usize, lno: Lineno) -> Result<(), TryFromIntError> {
+        // the jump is taken from the end of the loop instruction itself
+        let jump = self.code.len() + 1 + JUMP_OPERAND_LEN - loop_start;
+        jump_operand(jump)?;
+        self.write_chunk(Opcode::OP_LOOP(jump), lno);
+        Ok(())
+    }
+
+    /// Bytes the chunk's buffers take up.
+    pub fn heap_size(&self) -> usize {
+        let locals: usize = self.locals.iter().map(|local| local.name.capacity()).sum();
+        self.code.capacity()
+            + self.lines.capacity() * size_of::<(usize, Lineno)>()
+            + self.constants.capacity() * size_of::<Value>()
+            + self.locals.capacity() * size_of::<LocalVar>()
+            + locals
+            + self.tests.capacity() * size_of::<usize>()
+    }
+
+    /// Line of the instruction covering byte `offset`.
+    pub fn line_at(&self, offset: usize) -> Lineno {
+        let run = self.lines.partition_point(|(start, _)| *start <= offset);
+        match run.checked_sub(1) {
+            Some(run) => self.lines[run].1,
+            None => Lineno(0),
+        }
+    }
+
+    /// Every instruction with its offset, decoded.
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, Opcode)> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset >= self.code.len() {
+                return None;
+            }
+            let start = offset;
+            let op = Opcode::decode_with(|| {
+                offset += 1;
+                self.code[offset - 1]
+            });
+            Some((start, op))
+        })
+    }
+
+    /// locals whose scope covers the instruction at `offset`, innermost last
+    pub fn locals_at(&self, offset: usize) -> impl Iterator<Item = &LocalVar> {
+        self.locals
+            .iter()
+            .filter(move |local| local.start <= offset && offset < local.end)
+    }
+}
+
+/// A jump of `distance` bytes as an operand, if it fits in one.
+fn jump_operand(distance: usize) -> Result<u32, TryFromIntError> {
+    u32::try_from(distance)
 }
 
 #[cfg(test)]
@@ -48,10 +157,54 @@ mod tests {
         chunk.write_chunk(Opcode::OP_TRUE, Lineno(7));
 
         assert_eq!(chunk.code.len(), 1);
-        match chunk.code[0].0 {
-            Opcode::OP_TRUE => {}
-            _ => panic!("expected OP_TRUE"),
-        }
-        assert_eq!(chunk.code[0].1.0, 7);
+        let ops: Vec<(usize, Opcode)> = chunk.instructions().collect();
+        assert!(matches!(ops[..], [(0, Opcode::OP_TRUE)]));
+        assert_eq!(chunk.line_at(0).0, 7);
+    }
+
+    #[test]
+    fn operands_take_as_many_bytes_as_they_need() {
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(Opcode::OP_CONSTANT(5), Lineno(1));
+        chunk.write_chunk(Opcode::OP_GET_LOCAL(300), Lineno(1));
+        chunk.write_chunk(Opcode::OP_CALL(2), Lineno(1));
+        chunk.write_chunk(Opcode::OP_DEFINE_GLOBAL(usize::MAX), Lineno(1));
+        assert_eq!(chunk.code[..7], [0, 5, 18, 0xac, 0x02, 25, 2]);
+
+        let ops: Vec<String> = chunk.instructions().map(|(offset, op)| format!("{} {:?}", offset, op)).collect();
+        let max = format!("7 OP_DEFINE_GLOBAL({})", usize::MAX);
+        assert_eq!(ops, ["0 OP_CONSTANT(5)", "2 OP_GET_LOCAL(300)", "5 OP_CALL(2)", max.as_str()]);
+    }
+
+    #[test]
+    fn lines_are_kept_per_run() {
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(Opcode::OP_NIL, Lineno(1));
+        chunk.write_chunk(Opcode::OP_CONSTANT(200), Lineno(1));
+        chunk.write_chunk(Opcode::OP_POP, Lineno(3));
+        chunk.write_chunk(Opcode::OP_POP, Lineno(3));
+        chunk.write_chunk(Opcode::OP_RETURN, Lineno(1));
+        assert_eq!(chunk.lines.len(), 3);
+        let lines: Vec<usize> = (0..chunk.code.len()).map(|offset| chunk.line_at(offset).0).collect();
+        assert_eq!(lines, [1, 1, 1, 1, 3, 3, 1]);
+    }
+
+    #[test]
+    fn jumps_are_patched_in_place() {
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(Opcode::OP_NIL, Lineno(1));
+        let jump = chunk.write_jump(Opcode::OP_JUMP_IF_FALSE(0), Lineno(1));
+        chunk.write_chunk(Opcode::OP_POP, Lineno(1));
+        chunk.patch_jump(jump).unwrap();
+        chunk.write_loop(0, Lineno(1)).unwrap();
+        let ops: Vec<String> = chunk.instructions().map(|(_, op)| format!("{:?}", op)).collect();
+        // jumps count bytes from the end of the jump instruction
+        assert_eq!(ops, ["OP_NIL", "OP_JUMP_IF_FALSE(1)", "OP_POP", "OP_LOOP(12)"]);
+    }
+
+    #[test]
+    fn jumps_too_far_to_encode_are_refused() {
+        assert_eq!(jump_operand(u32::MAX as usize), Ok(u32::MAX));
+        assert!(jump_operand(u32::MAX as usize + 1).is_err());
     }
 }
@@ -1,41 +1,98 @@
-use std::{cell::RefCell, rc::Rc};
+use serde_json::{json, Value as Json};
 
-use crate::{bytecode::Opcode, chunk::Chunk};
+use crate::{bytecode::Opcode, chunk::Chunk, globals::Globals, object::ObjFunction, value::Value};
 
-use super::Lineno;
+pub fn disassemble_chunk(chunk: &Chunk, name: &str, globals: &Globals) {
+    print!("{}", chunk_listing(chunk, name, globals));
+}
+
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, globals: &Globals) {
+    if let Some((offset, opcode)) = chunk.instructions().find(|(start, _)| *start == offset) {
+        print!("{}", instruction_listing(chunk, offset, opcode, globals));
+    }
+}
 
-pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    println!("== {name} ==");
-    for (offset, _) in chunk.code.iter().enumerate() {
-        disassemble_instruction(chunk, offset);
+/// The text `disassemble_chunk` prints. `globals` names the global slots the
+/// chunk was compiled against.
+pub fn chunk_listing(chunk: &Chunk, name: &str, globals: &Globals) -> String {
+    let mut out = format!("== {name} ==\n");
+    for (offset, opcode) in chunk.instructions() {
+        out.push_str(&instruction_listing(chunk, offset, opcode, globals));
     }
+    out
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) {
-    let (opcode, lineno) = chunk.code[offset];
-    print!("{:04?} ", offset);
-    print!("{:?} ", lineno);
+fn instruction_listing(chunk: &Chunk, offset: usize, opcode: Opcode, globals: &Globals) -> String {
+    let prefix = format!("{:04?} {:?} ", offset, chunk.line_at(offset));
     match opcode {
-        Opcode::OP_CONSTANT(idx) => {
-            constant_instruction("OP_CONSTANT", &chunk, idx, offset);
-        }
-        Opcode::OP_DEFINE_GLOBAL(idx) => {
-            constant_instruction("OP_DEFINE_GLOBAL", &chunk, idx, offset);
-        }
-        Opcode::OP_GET_GLOBAL(idx) => {
-            constant_instruction("OP_GET_GLOBAL", &chunk, idx, offset);
-        }
-        Opcode::OP_SET_GLOBAL(idx) => {
-            constant_instruction("OP_SET_GLOBAL", &chunk, idx, offset);
-        }
-        _ => {
-            println!("{:?}", opcode);
-        }
+        Opcode::OP_CONSTANT(idx) => constant_instruction(prefix, "OP_CONSTANT", chunk, idx),
+        Opcode::OP_DEFINE_GLOBAL(slot) => global_instruction(prefix, "OP_DEFINE_GLOBAL", globals, slot),
+        Opcode::OP_GET_GLOBAL(slot) => global_instruction(prefix, "OP_GET_GLOBAL", globals, slot),
+        Opcode::OP_SET_GLOBAL(slot) => global_instruction(prefix, "OP_SET_GLOBAL", globals, slot),
+        _ => format!("{prefix}{:?}\n", opcode),
     }
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, idx: usize, offset: usize) {
+fn constant_instruction(prefix: String, name: &str, chunk: &Chunk, idx: usize) -> String {
     let constant = &chunk.constants[idx];
-    println!("{name} {idx}");
-    println!("{}", constant);
+    format!("{prefix}{name} {idx}\n{}\n", constant)
+}
+
+fn global_instruction(prefix: String, name: &str, globals: &Globals, slot: usize) -> String {
+    format!("{prefix}{name} {slot}\n{}\n", globals.name(slot).s)
+}
+
+/// The global slot an instruction reads or writes.
+fn global_slot(opcode: Opcode) -> Option<usize> {
+    match opcode {
+        Opcode::OP_DEFINE_GLOBAL(slot) | Opcode::OP_GET_GLOBAL(slot) | Opcode::OP_SET_GLOBAL(slot) => Some(slot),
+        _ => None,
+    }
+}
+
+/// Listings for `function` followed by every function nested in it.
+pub fn function_listing(function: &ObjFunction, globals: &Globals) -> String {
+    let mut out = chunk_listing(&function.chunk, &function.name.s, globals);
+    for constant in &function.chunk.constants {
+        if let Value::FUNCTION(nested) = constant {
+            out.push('\n');
+            out.push_str(&function_listing(nested, globals));
+        }
+    }
+    out
+}
+
+/// `function` and its nested functions as JSON, for tools that consume bytecode.
+/// Instructions that use a global slot also carry the global's name.
+pub fn function_json(function: &ObjFunction, globals: &Globals) -> Json {
+    let chunk = &function.chunk;
+    let code: Vec<Json> = chunk
+        .instructions()
+        .map(|(offset, op)| {
+            let mut instruction = json!({ "offset": offset, "op": format!("{:?}", op), "line": chunk.line_at(offset).0 });
+            if let Some(slot) = global_slot(op) {
+                instruction["global"] = json!(globals.name(slot).s);
+            }
+            instruction
+        })
+        .collect();
+    let constants: Vec<Json> = function
+        .chunk
+        .constants
+        .iter()
+        .map(|constant| match constant {
+            Value::NUMBER(n) => json!(n),
+            Value::BOOL(b) => json!(b),
+            Value::STR(s) => json!(s.s),
+            Value::FUNCTION(nested) => function_json(nested, globals),
+            Value::NATIVE(_) | Value::LIST(_) => json!(constant.to_string()),
+            Value::NIL => Json::Null,
+        })
+        .collect();
+    json!({
+        "name": function.name.s,
+        "arity": function.arity,
+        "code": code,
+        "constants": constants,
+    })
 }
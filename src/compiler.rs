@@ -1,9 +1,10 @@
-use std::mem::{self, transmute};
+use std::mem;
 
 use crate::{
     bytecode::Opcode,
-    chunk::{disassemble::disassemble_chunk, Chunk, Lineno},
+    chunk::{disassemble::disassemble_chunk, Chunk, Lineno, LocalVar},
     gc::{Gc, GcRef},
+    globals::Globals,
     lexer::Lexer,
     object::{ObjFunction, ObjString},
     token::{Token, TokenType},
@@ -11,10 +12,15 @@ use crate::{
     vm::InterpretError,
 };
 
-use self::{parse_rule::RULES, precedence::Precedence};
+use self::{
+    analysis::{Analysis, Call, CompileError, Reference, Symbol, SymbolKind},
+    parse_rule::RULES,
+    precedence::Precedence,
+};
 
+pub mod analysis;
 mod parse_rule;
-mod precedence;
+pub(crate) mod precedence;
 
 use parse_rule::ParseRule;
 
@@ -38,6 +44,10 @@ trait Parsable {
     fn or(&mut self, _: bool);
 
     fn call(&mut self, _: bool);
+
+    fn list(&mut self, _: bool);
+
+    fn index(&mut self, _: bool);
     // fn apply_parse_fn(&mut self, parse_fn: Parse Fn);
 }
 pub struct Parser<'a> {
@@ -45,7 +55,15 @@ pub struct Parser<'a> {
     current: Token,
     lexer: Lexer,
     gc: &'a mut Gc,
+    globals: &'a mut Globals,
     compiler: Box<Compiler>,
+    errors: Vec<CompileError>,
+    panic_mode: bool,
+    analysis: Option<Analysis>,
+    // reference recorded for the last variable read, and the code length right after it
+    callee: Option<(usize, usize)>,
+    // offset of the last call written, for `return` to make it a tail call
+    last_call: Option<usize>,
 }
 
 impl Parsable for Parser<'_> {
@@ -62,12 +80,7 @@ impl Parsable for Parser<'_> {
     fn binary(&mut self, _: bool) {
         let operator_type = self.previous.type_;
         let rule = parse_rule::ParseRule::get_rule(operator_type);
-        if (rule.precedence as usize) < 11
-        /*variant count for Precedence; !todo - replace with variant_count::<Precedence>() once stabilized*/
-        {
-            let next_precedence = unsafe { transmute(rule.precedence as i8 + 1) };
-            self.parse_precedence(next_precedence);
-        }
+        self.parse_precedence(rule.precedence.next());
 
         match operator_type {
             TokenType::PLUS => self.emit_opcode(Opcode::OP_ADD),
@@ -139,13 +152,46 @@ impl Parsable for Parser<'_> {
     }
 
     fn call(&mut self, _: bool) {
+        // only a variable read immediately before '(' names the function being called
+        let code_len = self.chunk().code.len();
+        let callee = self.callee.take().filter(|(_, len)| *len == code_len);
         let count = self.arg_count();
+        if let (Some(analysis), Some((reference, _))) = (self.analysis.as_mut(), callee) {
+            analysis.calls.push(Call { reference, args: count });
+        }
+        self.last_call = Some(self.chunk().code.len());
         self.emit_opcode(Opcode::OP_CALL(count));
     }
+
+    fn list(&mut self, _: bool) {
+        let mut count = 0;
+        if !self.check_token_type(TokenType::RBRACKET) {
+            loop {
+                self.expression();
+                count += 1;
+                if !self.match_token(TokenType::COMMA) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RBRACKET, "Expected ']' after list elements");
+        self.emit_opcode(Opcode::OP_BUILD_LIST(count));
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RBRACKET, "Expected ']' after index");
+        if can_assign && self.match_token(TokenType::ASSIGN) {
+            self.expression();
+            self.emit_opcode(Opcode::OP_SET_INDEX);
+        } else {
+            self.emit_opcode(Opcode::OP_GET_INDEX);
+        }
+    }
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(lexer: Lexer, gc: &'a mut Gc) -> Parser<'a> {
+    pub fn new(lexer: Lexer, gc: &'a mut Gc, globals: &'a mut Globals) -> Parser<'a> {
         let current = Token::new_def();
         let previous = Token::new_def();
         let function_name = gc.intern("script".to_owned());
@@ -155,13 +201,49 @@ impl<'a> Parser<'a> {
             current,
             lexer,
             gc,
+            globals,
             compiler,
+            errors: Vec::new(),
+            panic_mode: false,
+            analysis: None,
+            callee: None,
+            last_call: None,
+        }
+    }
+
+    fn parse(&mut self) {
+        self.advance();
+
+        while !self.match_token(TokenType::EOF) {
+            self.declaration();
         }
+        // let function = parser.end_compiler();
+        // disassemble_chunk(chunk, "TEST");
+        self.emit_return();
     }
+    // a lone expression, optionally followed by ';', whose value the script returns
+    fn parse_expression(&mut self) {
+        self.advance();
+        self.expression();
+        self.match_token(TokenType::SEMICOLON);
+        self.consume(TokenType::EOF, "Expected end of expression");
+        self.emit_opcode(Opcode::OP_RETURN);
+    }
+
     /* ======================= plumbing ====================== */
     fn advance(&mut self) {
         self.previous = self.current.clone();
-        self.current = self.lexer.next_token();
+        loop {
+            self.current = self.lexer.next_token();
+            match self.current.type_ {
+                TokenType::COMMENT => continue,
+                TokenType::ILLEGAL => {
+                    let msg = self.current.literal.clone();
+                    self.error_at_current(&msg);
+                }
+                _ => break,
+            }
+        }
     }
 
     fn chunk(&mut self) -> &mut Chunk {
@@ -181,13 +263,15 @@ impl<'a> Parser<'a> {
     }
 
     fn emit_jump(&mut self, op: Opcode) -> usize {
-        self.emit_opcode(op);
-        self.chunk().code.len() - 1
+        let lineno = Lineno(self.previous.lineno);
+        self.chunk().write_jump(op, lineno)
     }
 
     fn emit_loop(&mut self, loop_start: usize) {
-        let jump = self.chunk().code.len() - loop_start + 1;
-        self.emit_opcode(Opcode::OP_LOOP(jump));
+        let lineno = Lineno(self.previous.lineno);
+        if self.chunk().write_loop(loop_start, lineno).is_err() {
+            self.error("Loop body too large");
+        }
     }
 
     fn emit_return(&mut self) {
@@ -199,14 +283,8 @@ impl<'a> Parser<'a> {
     }
 
     fn patch_jump(&mut self, offset: usize) {
-        let jump = self.chunk().code.len() - offset - 1;
-        // 0  1  *2  3  4  5  *6
-        // i1 i2 i3 i4 i5 i6  i7
-        // println!("{:?}", self.chunk().code[offset].0);
-        if let Opcode::OP_JUMP_IF_FALSE(ref mut x) = self.chunk().code[offset].0 {
-            *x = jump;
-        } else if let Opcode::OP_JUMP(ref mut x) = self.chunk().code[offset].0 {
-            *x = jump;
+        if self.chunk().patch_jump(offset).is_err() {
+            self.error("Too much code to jump over");
         }
     }
 
@@ -214,7 +292,7 @@ impl<'a> Parser<'a> {
         if self.current.type_ == type_ {
             self.advance();
         } else {
-            panic!("{}", err);
+            self.error_at_current(err);
         }
     }
 
@@ -230,7 +308,8 @@ impl<'a> Parser<'a> {
         if let Some(prefix_fn) = prefix_rule.prefix {
             prefix_fn(self, can_assign);
         } else {
-            panic!("error unexpected token");
+            self.error("Expected expression");
+            return;
         }
 
         while precedence <= ParseRule::get_rule(self.current.clone().type_).precedence {
@@ -241,7 +320,46 @@ impl<'a> Parser<'a> {
         }
 
         if can_assign && self.match_token(TokenType::ASSIGN) {
-            panic!("Invalid Assignment target");
+            self.error("Invalid Assignment target");
+        }
+    }
+
+    /* ====================== errors ========================= */
+    fn error_at(&mut self, token: Token, message: &str) {
+        // suppress cascading errors until the parser resynchronizes
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+        let error = CompileError::at(&token, message, self.lexer.source());
+        self.errors.push(error);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.error_at(self.previous.clone(), message);
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        self.error_at(self.current.clone(), message);
+    }
+
+    /// skip tokens until something that looks like a statement boundary
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+        while self.current.type_ != TokenType::EOF {
+            if self.previous.type_ == TokenType::SEMICOLON || self.at_test_block() {
+                return;
+            }
+            match self.current.type_ {
+                TokenType::FUNCTION
+                | TokenType::LET
+                | TokenType::FOR
+                | TokenType::IF
+                | TokenType::WHILE
+                | TokenType::PRINT
+                | TokenType::RETURN => return,
+                _ => self.advance(),
+            }
         }
     }
 
@@ -251,6 +369,14 @@ impl<'a> Parser<'a> {
         self.current.type_ == type_
     }
 
+    /// `test` is only a keyword when it starts a declaration like `test "name"`,
+    /// so scripts can still use it as a name.
+    fn at_test_block(&mut self) -> bool {
+        self.current.type_ == TokenType::IDENT
+            && self.current.literal == "test"
+            && self.lexer.peek_token().type_ == TokenType::STRING
+    }
+
     fn match_token(&mut self, type_: TokenType) -> bool {
         if self.check_token_type(type_) {
             self.advance();
@@ -363,13 +489,20 @@ impl<'a> Parser<'a> {
 
     fn return_statement(&mut self) {
         if let FunctionType::SCRIPT = self.compiler.f_type {
-            panic!("Cannot return from top-level code");
+            self.error("Cannot return from top-level code");
         }
         if self.match_token(TokenType::SEMICOLON) {
             self.emit_return();
         } else {
+            self.last_call = None;
             self.expression();
             self.consume(TokenType::SEMICOLON, "Expected ; after return statement");
+            // the value is whatever the call returns, so the callee can return it
+            // for us; a call is two bytes
+            let end = self.chunk().code.len();
+            if self.last_call == Some(end - 2) {
+                self.chunk().make_tail_call(end - 2);
+            }
             self.emit_opcode(Opcode::OP_RETURN);
         }
     }
@@ -384,20 +517,53 @@ impl<'a> Parser<'a> {
             self.function_declaration();
         } else if self.match_token(TokenType::LET) {
             self.variable_declaration();
+        } else if self.at_test_block() {
+            self.advance();
+            self.test_declaration();
         } else {
             self.statement();
         }
+
+        if self.panic_mode {
+            self.synchronize();
+        }
     }
 
     fn function_declaration(&mut self) {
         let global = self.parse_variable("Expected Function name");
+        self.record_definition(SymbolKind::Function);
         self.mark_initialized();
         self.function(FunctionType::FUNCTION);
         self.define_variable(global);
     }
 
+    /// `test "name" { ... }` becomes a function that only `lockhart test` calls.
+    fn test_declaration(&mut self) {
+        let keyword = self.previous.clone();
+        let nested = !matches!(self.compiler.f_type, FunctionType::SCRIPT) || self.compiler.scope_depth > 0;
+        self.consume(TokenType::STRING, "Expected test name");
+        self.push_compiler(FunctionType::FUNCTION);
+        self.begin_scope();
+        self.consume(TokenType::LBRACE, "Expected '{' before test body");
+        self.block();
+        let function = self.end_compiler();
+        let function = self.gc.alloc(function);
+        let idx = self.chunk().add_constant(Value::FUNCTION(function));
+        self.chunk().tests.push(idx);
+        if nested {
+            // the body parsed, so there's nothing to resynchronize after
+            let error = CompileError::at(&keyword, "Tests can only be declared at top level", self.lexer.source());
+            self.errors.push(error);
+        }
+    }
+
     fn variable_declaration(&mut self) {
         let global_idx = self.parse_variable("Expected variable name");
+        if self.compiler.scope_depth > 0 {
+            self.record_definition(SymbolKind::Local);
+        } else {
+            self.record_definition(SymbolKind::Global);
+        }
         if self.match_token(TokenType::ASSIGN) {
             self.expression();
         } else {
@@ -425,23 +591,34 @@ impl<'a> Parser<'a> {
         {
             self.emit_opcode(Opcode::OP_POP);
             self.compiler.total -= 1;
+            self.close_local(self.compiler.total);
+            self.record_scope_end(self.compiler.total);
         }
     }
 
     fn block(&mut self) {
+        let mut returned = false;
         while !self.check_token_type(TokenType::RBRACE) && !self.check_token_type(TokenType::EOF) {
+            if returned {
+                // report only the first statement that can never run
+                returned = false;
+                let offset = self.current.offset;
+                if let Some(analysis) = self.analysis.as_mut() {
+                    analysis.unreachable.push(offset);
+                }
+            }
+            if self.check_token_type(TokenType::RETURN) {
+                returned = true;
+            }
             self.declaration();
         }
 
         self.consume(TokenType::RBRACE, "Expected } at end of block");
     }
     /* ==================== variable ========================= */
-    fn identifier_constant(&mut self, token: Token) -> usize {
+    fn global_slot(&mut self, token: Token) -> usize {
         let identifier = self.gc.intern(token.literal);
-        self.compiler
-            .function
-            .chunk
-            .add_constant(Value::STR(identifier))
+        self.globals.slot(identifier)
     }
 
     fn parse_variable(&mut self, err: &str) -> usize {
@@ -451,7 +628,7 @@ impl<'a> Parser<'a> {
         if self.compiler.scope_depth > 0 {
             return 0;
         }
-        self.identifier_constant(self.previous.clone())
+        self.global_slot(self.previous.clone())
     }
 
     fn declare_variable(&mut self) {
@@ -459,23 +636,27 @@ impl<'a> Parser<'a> {
             return;
         }
         let var_token = &self.previous;
-        for local in &self.compiler.locals {
+        let mut exists = false;
+        for local in self.compiler.locals[..self.compiler.total].iter().rev() {
             if local.depth != -1 && local.depth < self.compiler.scope_depth {
                 break;
             }
             if local.name.literal == var_token.literal {
-                panic!(
-                    "Error: Variable with name {} already exists",
-                    var_token.literal
-                );
+                exists = true;
+                break;
             }
         }
+        if exists {
+            let msg = format!("Variable with name {} already exists", var_token.literal);
+            self.error(&msg);
+        }
         self.add_local(self.previous.clone());
     }
 
     fn add_local(&mut self, token: Token) {
         if self.compiler.total == STACK_SIZE {
-            panic!("Stack overflow; too many local variables");
+            self.error("Stack overflow; too many local variables");
+            return;
         }
         self.compiler.locals[self.compiler.total] = Local {
             name: token,
@@ -488,15 +669,40 @@ impl<'a> Parser<'a> {
         if self.compiler.scope_depth == 0 {
             return;
         }
-        self.compiler.locals[self.compiler.total - 1].depth = self.compiler.scope_depth;
+        let slot = self.compiler.total - 1;
+        if self.compiler.locals[slot].depth == -1 {
+            // record the local's name for debuggers
+            let name = self.compiler.locals[slot].name.literal.clone();
+            let start = self.chunk().code.len();
+            self.chunk().locals.push(LocalVar {
+                name,
+                slot,
+                start,
+                end: usize::MAX,
+            });
+        }
+        self.compiler.locals[slot].depth = self.compiler.scope_depth;
+    }
+
+    fn close_local(&mut self, slot: usize) {
+        let end = self.chunk().code.len();
+        if let Some(local) = self
+            .chunk()
+            .locals
+            .iter_mut()
+            .rev()
+            .find(|local| local.slot == slot && local.end == usize::MAX)
+        {
+            local.end = end;
+        }
     }
 
-    fn define_variable(&mut self, idx: usize) {
+    fn define_variable(&mut self, slot: usize) {
         if self.compiler.scope_depth > 0 {
             self.mark_initialized();
             return;
         }
-        self.emit_opcode(Opcode::OP_DEFINE_GLOBAL(idx));
+        self.emit_opcode(Opcode::OP_DEFINE_GLOBAL(slot));
     }
 
     fn arg_count(&mut self) -> u8 {
@@ -505,9 +711,11 @@ impl<'a> Parser<'a> {
             loop {
                 self.expression();
                 if count == u8::MAX {
-                    panic!("Cannot have more than {} arguments", count);
+                    let msg = format!("Cannot have more than {} arguments", count);
+                    self.error(&msg);
+                } else {
+                    count += 1;
                 }
-                count += 1;
                 if !self.match_token(TokenType::COMMA) {
                     break;
                 }
@@ -516,31 +724,39 @@ impl<'a> Parser<'a> {
         self.consume(TokenType::RPAREN, "Expected ')' after arguments.");
         count
     }
-    fn resolve_local(&self, token: &Token) -> Option<i8> {
-        for (i, local) in self.compiler.locals.iter().enumerate() {
-            if local.name.literal == token.literal {
-                if local.depth == -1 {
-                    panic!("Cannot read variable into its own initializer");
-                }
-                return Some(i as i8);
-            }
+    fn resolve_local(&mut self, token: &Token) -> Option<usize> {
+        let locals = &self.compiler.locals[..self.compiler.total];
+        let found = locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.literal == token.literal)
+            .map(|(i, local)| (i, local.depth));
+        let (slot, depth) = found?;
+        if depth == -1 {
+            self.error("Cannot read variable into its own initializer");
         }
-        None
+        Some(slot)
     }
 
     fn named_variable(&mut self, can_assign: bool) {
         let get_op: Opcode;
         let set_op: Opcode;
-        let slot = self.resolve_local(&self.previous);
+        let name = self.previous.clone();
+        let assign = can_assign && self.check_token_type(TokenType::ASSIGN);
+        let slot = self.resolve_local(&name);
         match slot {
             Some(slot_index) => {
-                get_op = Opcode::OP_GET_LOCAL(slot_index.try_into().unwrap());
-                set_op = Opcode::OP_SET_LOCAL(slot_index.try_into().unwrap());
+                let declared = self.compiler.locals[slot_index].name.offset;
+                self.record_reference(&name, Some(declared), assign);
+                get_op = Opcode::OP_GET_LOCAL(slot_index);
+                set_op = Opcode::OP_SET_LOCAL(slot_index);
             }
             _ => {
-                let idx = self.identifier_constant(self.previous.clone());
-                get_op = Opcode::OP_GET_GLOBAL(idx);
-                set_op = Opcode::OP_SET_GLOBAL(idx);
+                self.record_reference(&name, None, assign);
+                let slot = self.global_slot(self.previous.clone());
+                get_op = Opcode::OP_GET_GLOBAL(slot);
+                set_op = Opcode::OP_SET_GLOBAL(slot);
             }
         }
         if can_assign && self.match_token(TokenType::ASSIGN) {
@@ -548,6 +764,79 @@ impl<'a> Parser<'a> {
             self.emit_opcode(set_op);
         } else {
             self.emit_opcode(get_op);
+            if let Some(analysis) = self.analysis.as_ref() {
+                let reference = analysis.references.len() - 1;
+                self.callee = Some((reference, self.chunk().code.len()));
+            }
+        }
+    }
+
+    /* ==================== analysis ======================== */
+    fn record_definition(&mut self, kind: SymbolKind) {
+        let analysis = match self.analysis.as_mut() {
+            Some(analysis) => analysis,
+            None => return,
+        };
+        let name = self.previous.literal.clone();
+        // an earlier local of this function with the same name, the new local was added last
+        let shadows = if self.compiler.scope_depth > 0 {
+            self.compiler.locals[..self.compiler.total.saturating_sub(1)]
+                .iter()
+                .rev()
+                .find(|local| local.name.literal == name)
+                .and_then(|local| analysis.symbol_at(local.name.offset))
+        } else {
+            None
+        };
+        analysis.symbols.push(Symbol {
+            name,
+            kind,
+            offset: self.previous.offset,
+            depth: self.compiler.scope_depth,
+            scope_end: None,
+            params: Vec::new(),
+            shadows,
+        });
+    }
+
+    /// `local` is the offset of the declaring token if the name resolved to a local
+    fn record_reference(&mut self, token: &Token, local: Option<usize>, assign: bool) {
+        if let Some(analysis) = self.analysis.as_mut() {
+            let symbol = local.and_then(|offset| analysis.symbol_at(offset));
+            analysis.references.push(Reference {
+                name: token.literal.clone(),
+                offset: token.offset,
+                symbol,
+                assign,
+            });
+        }
+    }
+
+    fn record_scope_end(&mut self, slot: usize) {
+        let end = self.previous.offset + self.previous.literal.len();
+        let offset = self.compiler.locals[slot].name.offset;
+        if let Some(analysis) = self.analysis.as_mut() {
+            if let Some(idx) = analysis.symbol_at(offset) {
+                analysis.symbols[idx].scope_end.get_or_insert(end);
+            }
+        }
+    }
+
+    // parameters are the only locals of a function when its body starts
+    fn record_params(&mut self) {
+        let params: Vec<String> = self.compiler.locals[1..self.compiler.total]
+            .iter()
+            .map(|local| local.name.literal.clone())
+            .collect();
+        if let Some(analysis) = self.analysis.as_mut() {
+            let function = analysis
+                .symbols
+                .iter_mut()
+                .rev()
+                .find(|s| s.kind == SymbolKind::Function);
+            if let Some(function) = function {
+                function.params = params;
+            }
         }
     }
 
@@ -557,11 +846,14 @@ impl<'a> Parser<'a> {
         self.consume(TokenType::LPAREN, "Expected '(' after function name");
         if !self.check_token_type(TokenType::RPAREN) {
             loop {
-                self.compiler.function.arity += 1;
-                if self.compiler.function.arity > u8::MAX {
-                    panic!("Cannot have more than {} parameters", u8::MAX);
+                if self.compiler.function.arity == u8::MAX {
+                    let msg = format!("Cannot have more than {} parameters", u8::MAX);
+                    self.error_at_current(&msg);
+                } else {
+                    self.compiler.function.arity += 1;
                 }
                 let constant = self.parse_variable("Expected parameter name");
+                self.record_definition(SymbolKind::Parameter);
                 self.define_variable(constant);
                 if !self.match_token(TokenType::COMMA) {
                     break;
@@ -569,6 +861,7 @@ impl<'a> Parser<'a> {
             }
         }
         self.consume(TokenType::RPAREN, "Expected ')' after parameters");
+        self.record_params();
         self.consume(TokenType::LBRACE, "Expected '{' before function body");
         self.block();
         let function = self.end_compiler();
@@ -585,6 +878,9 @@ impl<'a> Parser<'a> {
 
     fn end_compiler(&mut self) -> ObjFunction {
         self.emit_return();
+        for slot in (1..self.compiler.total).rev() {
+            self.record_scope_end(slot);
+        }
         if let Some(enclosing) = self.compiler.enclosing.take() {
             let compiler = mem::replace(&mut self.compiler, enclosing);
             return compiler.function;
@@ -600,7 +896,7 @@ struct Local {
     depth: i8,
 }
 
-const STACK_SIZE: usize = 50000;
+pub(crate) const STACK_SIZE: usize = 50000;
 struct Compiler {
     enclosing: Option<Box<Compiler>>,
     function: ObjFunction,
@@ -610,14 +906,17 @@ struct Compiler {
     total: usize,
 }
 
-enum FunctionType {
+pub(crate) enum FunctionType {
     FUNCTION,
     SCRIPT,
 }
 
 impl Compiler {
     fn new(function_name: GcRef<ObjString>, f_type: FunctionType) -> Box<Compiler> {
-        let function = ObjFunction::new(function_name);
+        let function = match f_type {
+            FunctionType::SCRIPT => ObjFunction::script(function_name),
+            FunctionType::FUNCTION => ObjFunction::new(function_name),
+        };
         let array_repeat_value: Local = Local {
             name: Token::new_def(),
             depth: -1,
@@ -635,16 +934,45 @@ impl Compiler {
     }
 }
 
-pub fn compile(source: String, gc: &mut Gc) -> Result<GcRef<ObjFunction>, InterpretError> {
+/// Compile `source` into a script function. Globals get their slots from
+/// `globals`, which has to be the table of the VM that will run the script.
+pub fn compile(source: String, gc: &mut Gc, globals: &mut Globals) -> Result<GcRef<ObjFunction>, InterpretError> {
     let lexer = Lexer::new(source);
-    let mut parser = Parser::new(lexer, gc);
-    parser.advance();
+    let mut parser = Parser::new(lexer, gc, globals);
+    parser.parse();
+    if !parser.errors.is_empty() {
+        let messages: Vec<String> = parser.errors.iter().map(|e| e.to_string()).collect();
+        return Err(InterpretError::InterpretCompileError(messages.join("\n")));
+    }
+    Ok(parser.gc.alloc(parser.compiler.function))
+}
 
-    while !parser.match_token(TokenType::EOF) {
-        parser.declaration();
+/// Compile `source` as a single expression; the resulting script returns its value.
+pub fn compile_expression(
+    source: String,
+    gc: &mut Gc,
+    globals: &mut Globals,
+) -> Result<GcRef<ObjFunction>, InterpretError> {
+    let lexer = Lexer::new(source);
+    let mut parser = Parser::new(lexer, gc, globals);
+    parser.parse_expression();
+    if !parser.errors.is_empty() {
+        let messages: Vec<String> = parser.errors.iter().map(|e| e.to_string()).collect();
+        return Err(InterpretError::InterpretCompileError(messages.join("\n")));
     }
-    // let function = parser.end_compiler();
-    // disassemble_chunk(chunk, "TEST");
-    parser.emit_return();
     Ok(parser.gc.alloc(parser.compiler.function))
 }
+
+/// Parse `source` for tooling: collects every error instead of stopping at the
+/// first one, along with declared symbols and resolved references.
+pub fn analyze(source: String, gc: &mut Gc) -> Analysis {
+    let lexer = Lexer::new(source);
+    let mut globals = Globals::new();
+    let mut parser = Parser::new(lexer, gc, &mut globals);
+    parser.analysis = Some(Analysis::default());
+    parser.parse();
+    let mut analysis = parser.analysis.take().unwrap_or_default();
+    analysis.errors = parser.errors;
+    analysis.resolve_globals();
+    analysis
+}
@@ -0,0 +1,148 @@
+use std::fmt::Display;
+
+use crate::token::{Token, TokenType};
+
+/// A compile error with enough position info for editors to underline it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub line: usize,
+    pub offset: usize,
+    pub len: usize,
+    pub lexeme: Option<String>, // None when the error is at the end of input
+}
+
+impl CompileError {
+    /// An error reported at `token`. Illegal tokens carry the lexer's message
+    /// instead of their text, so their lexeme is taken from `source`.
+    pub fn at(token: &Token, message: &str, source: &str) -> CompileError {
+        let lexeme = match token.type_ {
+            TokenType::EOF => None,
+            TokenType::ILLEGAL => {
+                let rest = source.get(token.offset..).unwrap_or("");
+                Some(rest.chars().take(1).collect())
+            }
+            _ => Some(token.literal.clone()),
+        };
+        CompileError {
+            message: message.to_string(),
+            line: token.lineno,
+            offset: token.offset,
+            len: lexeme.as_ref().map_or(0, |l: &String| l.len().max(1)),
+            lexeme,
+        }
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.lexeme {
+            Some(lexeme) => write!(f, "[line {}] Error at '{}': {}", self.line, lexeme, self.message),
+            None => write!(f, "[line {}] Error at end: {}", self.line, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Global,
+    Local,
+    Function,
+    Parameter,
+}
+
+/// A name introduced by a declaration, as seen by the parser.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub offset: usize,
+    pub depth: i8,
+    /// end offset of the enclosing scope, None for top-level declarations
+    pub scope_end: Option<usize>,
+    /// parameter names for functions
+    pub params: Vec<String>,
+    /// an outer declaration with the same name that this one hides
+    pub shadows: Option<usize>,
+}
+
+impl Symbol {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_visible_at(&self, offset: usize) -> bool {
+        match self.scope_end {
+            None => true,
+            Some(end) => self.offset <= offset && offset <= end,
+        }
+    }
+}
+
+/// A use of a name. `symbol` indexes `Analysis::symbols`, None if the name
+/// never resolves to a declaration (an undefined global).
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub name: String,
+    pub offset: usize,
+    pub symbol: Option<usize>,
+    pub assign: bool,
+}
+
+/// A call whose callee is a plain variable, so its target may be known statically.
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub reference: usize, // index into `Analysis::references`
+    pub args: u8,
+}
+
+/// Everything the parser learned about a source file besides its bytecode.
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+    pub calls: Vec<Call>,
+    /// offsets of the first statement following a `return` in the same block
+    pub unreachable: Vec<usize>,
+    pub errors: Vec<CompileError>,
+}
+
+impl Analysis {
+    /// symbol declared by the token starting at `offset`
+    pub fn symbol_at(&self, offset: usize) -> Option<usize> {
+        self.symbols.iter().position(|s| s.offset == offset)
+    }
+
+    /// symbol named or referenced by the identifier covering `offset`
+    pub fn lookup(&self, offset: usize) -> Option<&Symbol> {
+        let covers = |start: usize, name: &str| start <= offset && offset <= start + name.len();
+        if let Some(symbol) = self.symbols.iter().find(|s| covers(s.offset, &s.name)) {
+            return Some(symbol);
+        }
+        self.references
+            .iter()
+            .find(|r| covers(r.offset, &r.name))
+            .and_then(|r| r.symbol)
+            .map(|idx| &self.symbols[idx])
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|s| s.kind == SymbolKind::Function)
+    }
+
+    /// bind references to globals, which may be declared after their use
+    pub(super) fn resolve_globals(&mut self) {
+        for reference in self.references.iter_mut().filter(|r| r.symbol.is_none()) {
+            reference.symbol = global(&self.symbols, &reference.name);
+        }
+        for idx in 0..self.symbols.len() {
+            if self.symbols[idx].depth > 0 && self.symbols[idx].shadows.is_none() {
+                self.symbols[idx].shadows = global(&self.symbols, &self.symbols[idx].name);
+            }
+        }
+    }
+}
+
+fn global(symbols: &[Symbol], name: &str) -> Option<usize> {
+    symbols.iter().position(|s| s.depth == 0 && s.name == name)
+}
@@ -29,12 +29,12 @@ impl ParseRule {
     }
 }
 
-pub static RULES: [ParseRule; 36] = {
+pub static RULES: [ParseRule; 39] = {
     let mut a = [ParseRule {
         prefix: None,
         infix: None,
         precedence: PrecNone,
-    }; 36];
+    }; 39];
     rule!(a, IDENT, Some(|x, y| x.variable(y)), None, PrecNone);
     rule!(a, NUM, Some(|x, y| x.number(y)), None, PrecNone);
     rule!(a, STRING, Some(|x, y| x.string(y)), None, PrecNone);
@@ -69,6 +69,9 @@ pub static RULES: [ParseRule; 36] = {
     rule!(a, RBRACE, Some(|x, y| x.grouping(y)), None, PrecNone);
     rule!(a, LPAREN, Some(|x, y| x.grouping(y)), Some(|x, y| x.call(y)), PrecCall);
     rule!(a, RPAREN, Some(|x, y| x.grouping(y)), None, PrecNone);
+    rule!(a, LBRACKET, Some(|x, y| x.list(y)), Some(|x, y| x.index(y)), PrecCall);
+    rule!(a, RBRACKET, None, None, PrecNone);
+    rule!(a, COMMENT, None, None, PrecNone);
     rule!(a, ILLEGAL, None, None, PrecNone);
     rule!(a, EOF, None, None, PrecNone);
 
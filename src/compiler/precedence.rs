@@ -11,4 +11,21 @@ pub enum Precedence {
     PrecUnary,
     PrecCall,
     PrecPrimary,
-}
\ No newline at end of file
+}
+impl Precedence {
+    /// the next tighter level, used for the right operand of left-associative operators
+    pub fn next(self) -> Precedence {
+        match self {
+            Precedence::PrecNone => Precedence::PrecAssignment,
+            Precedence::PrecAssignment => Precedence::PrecOr,
+            Precedence::PrecOr => Precedence::PrecAnd,
+            Precedence::PrecAnd => Precedence::PrecEquality,
+            Precedence::PrecEquality => Precedence::PrecComparison,
+            Precedence::PrecComparison => Precedence::PrecTerm,
+            Precedence::PrecTerm => Precedence::PrecFactor,
+            Precedence::PrecFactor => Precedence::PrecUnary,
+            Precedence::PrecUnary => Precedence::PrecCall,
+            Precedence::PrecCall | Precedence::PrecPrimary => Precedence::PrecPrimary,
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    natives,
+    protocol::{read_message, write_message},
+    value::Value,
+    vm::{
+        hook::{HookAction, VmHook},
+        InterpretError, Vm,
+    },
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Debug Adapter Protocol server. Speaks DAP over any reader/writer pair; the
+/// script runs on the calling thread and requests are only serviced while the
+/// vm is stopped (or before launch / after termination).
+pub struct Server<R, W> {
+    reader: R,
+    writer: W,
+    seq: u64,
+    program: Option<String>,
+    source: Option<String>,
+    stop_on_entry: bool,
+    no_debug: bool,
+    breakpoints: HashSet<usize>,
+    step: Step,
+    started: bool,
+    disconnected: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    None,
+    In,
+    Over(usize),
+    Out(usize),
+}
+
+// the vm only ever runs a single thread
+const THREAD_ID: u64 = 1;
+// variablesReference for the globals scope; frame locals use FRAME_REF_BASE + frame index
+const GLOBALS_REF: u64 = 1;
+const FRAME_REF_BASE: u64 = 2;
+
+pub fn start() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut server = Server::new(stdin.lock(), stdout.lock());
+    server.serve()
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    pub fn new(reader: R, writer: W) -> Server<R, W> {
+        Server {
+            reader,
+            writer,
+            seq: 0,
+            program: None,
+            source: None,
+            stop_on_entry: false,
+            no_debug: false,
+            breakpoints: HashSet::new(),
+            step: Step::None,
+            started: false,
+            disconnected: false,
+        }
+    }
+
+    /// handle requests until the client disconnects or closes the stream
+    pub fn serve(&mut self) -> io::Result<()> {
+        while !self.disconnected {
+            let request = match self.read_message()? {
+                Some(request) => request,
+                None => break,
+            };
+            match command(&request) {
+                "initialize" => {
+                    let capabilities = json!({
+                        "supportsConfigurationDoneRequest": true,
+                    });
+                    self.respond(&request, capabilities)?;
+                    self.event("initialized", json!({}))?;
+                }
+                "launch" => self.launch(&request)?,
+                "configurationDone" => {
+                    self.respond(&request, json!({}))?;
+                    if self.source.is_some() && !self.started {
+                        self.started = true;
+                        self.execute()?;
+                    }
+                }
+                _ => {
+                    self.common_request(&request, None)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn launch(&mut self, request: &Json) -> io::Result<()> {
+        let args = &request["arguments"];
+        let program = match args["program"].as_str() {
+            Some(program) => program.to_string(),
+            None => return self.respond_err(request, "Missing 'program' argument"),
+        };
+        match fs::read_to_string(&program) {
+            Ok(source) => {
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                self.no_debug = args["noDebug"].as_bool().unwrap_or(false);
+                self.program = Some(program);
+                self.source = Some(source);
+                self.respond(request, json!({}))
+            }
+            Err(err) => {
+                let msg = format!("Could not open file {}: {}", program, err);
+                self.respond_err(request, &msg)
+            }
+        }
+    }
+
+    fn execute(&mut self) -> io::Result<()> {
+        let source = self.source.clone().unwrap_or_default();
+        let mut vm = Vm::init_vm();
+        let exit_code = match vm.debug(source, self) {
+            Ok(_) => 0,
+            Err(InterpretError::InterpretExit(code)) => code,
+            Err(err) => {
+                if !self.disconnected {
+                    let text = format!("Error: {:?}\n", err);
+                    self.event("output", json!({ "category": "stderr", "output": text }))?;
+                }
+                1
+            }
+        };
+        if !self.disconnected {
+            self.event("exited", json!({ "exitCode": exit_code }))?;
+            self.event("terminated", json!({}))?;
+        }
+        Ok(())
+    }
+
+    /// Requests valid at any time. `vm` is present only while the script is
+    /// stopped; returns true if the request resumes execution.
+    fn common_request(&mut self, request: &Json, vm: Option<(&Vm, usize)>) -> io::Result<bool> {
+        match command(request) {
+            "setBreakpoints" => {
+                let lines: Vec<usize> = request["arguments"]["breakpoints"]
+                    .as_array()
+                    .map(|bps| {
+                        bps.iter()
+                            .filter_map(|bp| bp["line"].as_u64())
+                            .map(|line| line as usize)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                self.breakpoints = lines.iter().copied().collect();
+                let verified: Vec<Json> = lines
+                    .iter()
+                    .map(|line| json!({ "verified": true, "line": line }))
+                    .collect();
+                self.respond(request, json!({ "breakpoints": verified }))?;
+            }
+            "setExceptionBreakpoints" => self.respond(request, json!({}))?,
+            "threads" => {
+                let threads = json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] });
+                self.respond(request, threads)?;
+            }
+            "disconnect" | "terminate" => {
+                self.disconnected = true;
+                self.respond(request, json!({}))?;
+                return Ok(true);
+            }
+            "stackTrace" | "scopes" | "variables" | "continue" | "next" | "stepIn"
+            | "stepOut" | "pause" => match vm {
+                Some((vm, depth)) => return self.stopped_request(request, vm, depth),
+                None => self.respond_err(request, "Program is not stopped")?,
+            },
+            other => {
+                let msg = format!("Unsupported request '{}'", other);
+                self.respond_err(request, &msg)?;
+            }
+        }
+        Ok(false)
+    }
+
+    fn stopped_request(&mut self, request: &Json, vm: &Vm, depth: usize) -> io::Result<bool> {
+        match command(request) {
+            "stackTrace" => {
+                let path = self.program.clone().unwrap_or_default();
+                let count = vm.frames().len();
+                let frames: Vec<Json> = vm
+                    .frames()
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .flat_map(|(id, frame)| {
+                        let mut shown = vec![json!({
+                            "id": id,
+                            "name": frame.function().name.s,
+                            "line": frame.line(),
+                            "column": 1,
+                            "source": { "path": path },
+                        })];
+                        // the calls tail calls replaced sat between this frame and its caller
+                        if frame.tail_calls() > 0 {
+                            let plural = if frame.tail_calls() == 1 { "" } else { "s" };
+                            shown.push(json!({
+                                "id": count + id,
+                                "name": format!("[{} frame{} elided by tail calls]", frame.tail_calls(), plural),
+                                "line": 0,
+                                "column": 0,
+                                "presentationHint": "label",
+                            }));
+                        }
+                        shown
+                    })
+                    .collect();
+                let total = frames.len();
+                self.respond(request, json!({ "stackFrames": frames, "totalFrames": total }))?;
+            }
+            "scopes" => {
+                let frame_id = request["arguments"]["frameId"].as_u64().unwrap_or(0);
+                let scopes = json!({ "scopes": [
+                    {
+                        "name": "Locals",
+                        "variablesReference": FRAME_REF_BASE + frame_id,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Globals",
+                        "variablesReference": GLOBALS_REF,
+                        "expensive": false,
+                    },
+                ]});
+                self.respond(request, scopes)?;
+            }
+            "variables" => {
+                let reference = request["arguments"]["variablesReference"].as_u64().unwrap_or(0);
+                let mut variables: Vec<(String, Value)> = if reference == GLOBALS_REF {
+                    vm.globals()
+                        .filter(|(name, value)| !natives::is_builtin(&name.s, value))
+                        .map(|(name, value)| (name.s.clone(), value))
+                        .collect()
+                } else {
+                    let index = reference.saturating_sub(FRAME_REF_BASE) as usize;
+                    match vm.frames().get(index) {
+                        Some(frame) => vm.frame_locals(frame),
+                        None => Vec::new(),
+                    }
+                };
+                if reference == GLOBALS_REF {
+                    variables.sort_by(|a, b| a.0.cmp(&b.0));
+                }
+                let variables: Vec<Json> = variables
+                    .iter()
+                    .map(|(name, value)| {
+                        json!({
+                            "name": name,
+                            "value": value.repr(),
+                            "type": type_name(value),
+                            "variablesReference": 0,
+                        })
+                    })
+                    .collect();
+                self.respond(request, json!({ "variables": variables }))?;
+            }
+            "pause" => self.respond(request, json!({}))?,
+            resume => {
+                self.step = match resume {
+                    "next" => Step::Over(depth),
+                    "stepIn" => Step::In,
+                    "stepOut" => Step::Out(depth),
+                    _ => Step::None,
+                };
+                self.respond(request, json!({ "allThreadsContinued": true }))?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn stop_reason(&mut self, line: usize, depth: usize) -> Option<&'static str> {
+        if self.stop_on_entry {
+            self.stop_on_entry = false;
+            return Some("entry");
+        }
+        if self.no_debug {
+            return None;
+        }
+        if self.breakpoints.contains(&line) {
+            return Some("breakpoint");
+        }
+        match self.step {
+            Step::In => Some("step"),
+            Step::Over(d) if depth <= d => Some("step"),
+            Step::Out(d) if depth < d => Some("step"),
+            _ => None,
+        }
+    }
+
+    /// block on the client while the vm is stopped
+    fn pause(&mut self, vm: &Vm, depth: usize, reason: &str) -> io::Result<HookAction> {
+        self.step = Step::None;
+        let body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        self.event("stopped", body)?;
+        loop {
+            let request = match self.read_message()? {
+                Some(request) => request,
+                None => {
+                    self.disconnected = true;
+                    return Ok(HookAction::Abort);
+                }
+            };
+            if self.common_request(&request, Some((vm, depth)))? {
+                break;
+            }
+        }
+        if self.disconnected {
+            Ok(HookAction::Abort)
+        } else {
+            Ok(HookAction::Continue)
+        }
+    }
+
+    /* ===================== wire format ===================== */
+    fn read_message(&mut self) -> io::Result<Option<Json>> {
+        read_message(&mut self.reader)
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &message)
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn respond_err(&mut self, request: &Json, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+impl<R: BufRead, W: Write> VmHook for Server<R, W> {
+    fn on_line(&mut self, vm: &Vm, line: usize, depth: usize) -> HookAction {
+        match self.stop_reason(line, depth) {
+            Some(reason) => self.pause(vm, depth, reason).unwrap_or(HookAction::Abort),
+            None => HookAction::Continue,
+        }
+    }
+
+    fn on_print(&mut self, text: &str, _output: &mut dyn Write) -> io::Result<()> {
+        let body = json!({ "category": "stdout", "output": format!("{}\n", text) });
+        if self.event("output", body).is_err() {
+            self.disconnected = true;
+        }
+        Ok(())
+    }
+}
+
+fn command(request: &Json) -> &str {
+    request["command"].as_str().unwrap_or("")
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::NUMBER(_) => "number",
+        Value::BOOL(_) => "bool",
+        Value::STR(_) => "string",
+        Value::FUNCTION(_) => "function",
+        Value::NATIVE(_) => "native",
+        Value::LIST(_) => "list",
+        Value::NIL => "nil",
+    }
+}
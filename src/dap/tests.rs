@@ -0,0 +1,269 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value as Json};
+
+use super::Server;
+
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("lockhart_dap_{}_{}.lh", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn frame(requests: &[Json]) -> String {
+    let mut input = String::new();
+    for (seq, request) in requests.iter().enumerate() {
+        let mut request = request.clone();
+        request["seq"] = json!(seq + 1);
+        request["type"] = json!("request");
+        let body = request.to_string();
+        input.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    }
+    input
+}
+
+fn exchange(requests: &[Json]) -> Vec<Json> {
+    let input = frame(requests);
+    let mut output = Vec::new();
+    Server::new(input.as_bytes(), &mut output).serve().unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    output
+        .split("Content-Length: ")
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| {
+            let body = chunk.split_once("\r\n\r\n").unwrap().1;
+            serde_json::from_str(body).unwrap()
+        })
+        .collect()
+}
+
+fn launch(path: &Path, stop_on_entry: bool) -> Vec<Json> {
+    vec![
+        json!({ "command": "initialize", "arguments": { "adapterID": "lockhart" } }),
+        json!({
+            "command": "launch",
+            "arguments": { "program": path.to_str().unwrap(), "stopOnEntry": stop_on_entry },
+        }),
+    ]
+}
+
+fn events<'a>(messages: &'a [Json], name: &str) -> Vec<&'a Json> {
+    messages
+        .iter()
+        .filter(|m| m["type"] == "event" && m["event"] == name)
+        .collect()
+}
+
+fn response<'a>(messages: &'a [Json], command: &str) -> Vec<&'a Json> {
+    messages
+        .iter()
+        .filter(|m| m["type"] == "response" && m["command"] == command)
+        .collect()
+}
+
+fn output(messages: &[Json]) -> String {
+    events(messages, "output")
+        .iter()
+        .filter(|e| e["body"]["category"] == "stdout")
+        .map(|e| e["body"]["output"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn launch_runs_to_completion() {
+    let path = script("run", "print 1 + 2;\nprint \"done\";\n");
+    let mut requests = launch(&path, false);
+    requests.push(json!({ "command": "configurationDone" }));
+    requests.push(json!({ "command": "disconnect" }));
+    let messages = exchange(&requests);
+
+    assert_eq!(events(&messages, "initialized").len(), 1);
+    assert_eq!(output(&messages), "3\ndone\n");
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+    assert_eq!(response(&messages, "disconnect")[0]["success"], true);
+}
+
+#[test]
+fn launch_missing_program_fails() {
+    let requests = vec![json!({
+        "command": "launch",
+        "arguments": { "program": "/nonexistent/lockhart.lh" },
+    })];
+    let messages = exchange(&requests);
+    assert_eq!(response(&messages, "launch")[0]["success"], false);
+}
+
+#[test]
+fn breakpoint_stops_with_stack_trace_and_locals() {
+    let source = "fn add(a, b) {\n  let c = a + b;\n  return c;\n}\nlet x = add(2, 3);\nprint x;\n";
+    let path = script("bp", source);
+    let mut requests = launch(&path, false);
+    requests.push(json!({
+        "command": "setBreakpoints",
+        "arguments": { "source": { "path": path.to_str().unwrap() }, "breakpoints": [{ "line": 3 }] },
+    }));
+    requests.push(json!({ "command": "configurationDone" }));
+    requests.push(json!({ "command": "threads" }));
+    requests.push(json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }));
+    requests.push(json!({ "command": "scopes", "arguments": { "frameId": 1 } }));
+    requests.push(json!({ "command": "variables", "arguments": { "variablesReference": 3 } }));
+    requests.push(json!({ "command": "continue", "arguments": { "threadId": 1 } }));
+    requests.push(json!({ "command": "disconnect" }));
+    let messages = exchange(&requests);
+
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.len(), 1);
+    assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+
+    let trace = &response(&messages, "stackTrace")[0]["body"]["stackFrames"];
+    assert_eq!(trace[0]["name"], "add");
+    assert_eq!(trace[0]["line"], 3);
+    assert_eq!(trace[1]["name"], "script");
+    assert_eq!(trace[1]["line"], 5);
+
+    let variables = &response(&messages, "variables")[0]["body"]["variables"];
+    let names: Vec<&str> = variables
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["a", "b", "c"]);
+    assert_eq!(variables[2]["value"], "5");
+    assert_eq!(output(&messages), "5\n");
+}
+
+#[test]
+fn stack_trace_notes_frames_elided_by_tail_calls() {
+    let source = "fn c() {\n  return 1;\n}\nfn b() { return c(); }\nfn a() { return b(); }\nprint a();\n";
+    let path = script("tail", source);
+    let mut requests = launch(&path, false);
+    requests.push(json!({
+        "command": "setBreakpoints",
+        "arguments": { "source": { "path": path.to_str().unwrap() }, "breakpoints": [{ "line": 2 }] },
+    }));
+    requests.push(json!({ "command": "configurationDone" }));
+    requests.push(json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }));
+    requests.push(json!({ "command": "continue", "arguments": { "threadId": 1 } }));
+    requests.push(json!({ "command": "disconnect" }));
+    let messages = exchange(&requests);
+
+    let body = &response(&messages, "stackTrace")[0]["body"];
+    let trace = body["stackFrames"].as_array().unwrap();
+    let names: Vec<&str> = trace.iter().map(|frame| frame["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["c", "[2 frames elided by tail calls]", "script"]);
+    assert_eq!(trace[1]["presentationHint"], "label");
+    assert_eq!(body["totalFrames"], 3);
+    assert_eq!(output(&messages), "1\n");
+}
+
+#[test]
+fn globals_scope_lists_defined_globals() {
+    let path = script("globals", "let name = \"lh\";\nlet n = 1;\nprint n;\n");
+    let mut requests = launch(&path, false);
+    requests.push(json!({
+        "command": "setBreakpoints",
+        "arguments": { "source": { "path": path.to_str().unwrap() }, "breakpoints": [{ "line": 3 }] },
+    }));
+    requests.push(json!({ "command": "configurationDone" }));
+    requests.push(json!({ "command": "variables", "arguments": { "variablesReference": 1 } }));
+    requests.push(json!({ "command": "continue" }));
+    requests.push(json!({ "command": "disconnect" }));
+    let messages = exchange(&requests);
+
+    let variables = &response(&messages, "variables")[0]["body"]["variables"];
+    assert_eq!(variables[0]["name"], "n");
+    assert_eq!(variables[1]["name"], "name");
+    assert_eq!(variables[1]["value"], "\"lh\"");
+    assert_eq!(variables[1]["type"], "string");
+}
+
+#[test]
+fn stepping_over_in_and_out() {
+    let source = "fn f() {\n  print 1;\n  print 2;\n}\nf();\nprint 3;\n";
+    let path = script("step", source);
+    let mut requests = launch(&path, true);
+    requests.push(json!({ "command": "configurationDone" }));
+    // stopped on entry at line 4 (function definition)
+    requests.push(json!({ "command": "next" }));
+    // line 5: f();
+    requests.push(json!({ "command": "stepIn" }));
+    // line 2 inside f
+    requests.push(json!({ "command": "stackTrace" }));
+    requests.push(json!({ "command": "stepOut" }));
+    // back in the script
+    requests.push(json!({ "command": "stackTrace" }));
+    requests.push(json!({ "command": "continue" }));
+    requests.push(json!({ "command": "disconnect" }));
+    let messages = exchange(&requests);
+
+    let reasons: Vec<&Json> = events(&messages, "stopped")
+        .iter()
+        .map(|e| &e["body"]["reason"])
+        .collect();
+    assert_eq!(reasons, vec!["entry", "step", "step", "step"]);
+
+    let traces = response(&messages, "stackTrace");
+    assert_eq!(traces[0]["body"]["stackFrames"][0]["name"], "f");
+    assert_eq!(traces[0]["body"]["stackFrames"][0]["line"], 2);
+    assert_eq!(traces[1]["body"]["totalFrames"], 1);
+    assert_eq!(output(&messages), "1\n2\n3\n");
+}
+
+#[test]
+fn stepping_over_a_call_stops_on_the_next_line() {
+    let source = "fn f(a) {\n  return a;\n}\nlet x = f(1);\nprint x;\n";
+    let path = script("step_call", source);
+    let mut requests = launch(&path, true);
+    requests.push(json!({ "command": "configurationDone" }));
+    // stopped on entry at line 3 (function definition)
+    requests.push(json!({ "command": "next" }));
+    // line 4: let x = f(1);
+    requests.push(json!({ "command": "next" }));
+    // line 5: print x;
+    requests.push(json!({ "command": "stackTrace" }));
+    requests.push(json!({ "command": "continue" }));
+    requests.push(json!({ "command": "disconnect" }));
+    let messages = exchange(&requests);
+
+    assert_eq!(events(&messages, "stopped").len(), 3);
+    let trace = &response(&messages, "stackTrace")[0]["body"]["stackFrames"];
+    assert_eq!(trace[0]["line"], 5);
+    assert_eq!(output(&messages), "1\n");
+}
+
+#[test]
+fn breakpoint_on_a_call_stops_once() {
+    let source = "fn f(a) {\n  return a;\n}\nlet x = f(1);\nprint x;\n";
+    let path = script("bp_call", source);
+    let mut requests = launch(&path, false);
+    requests.push(json!({
+        "command": "setBreakpoints",
+        "arguments": { "source": { "path": path.to_str().unwrap() }, "breakpoints": [{ "line": 4 }] },
+    }));
+    requests.push(json!({ "command": "configurationDone" }));
+    requests.push(json!({ "command": "continue" }));
+    requests.push(json!({ "command": "continue" }));
+    requests.push(json!({ "command": "disconnect" }));
+    let messages = exchange(&requests);
+
+    assert_eq!(events(&messages, "stopped").len(), 1);
+    assert_eq!(output(&messages), "1\n");
+}
+
+#[test]
+fn disconnect_while_stopped_aborts_script() {
+    let path = script("abort", "print 1;\nprint 2;\n");
+    let mut requests = launch(&path, true);
+    requests.push(json!({ "command": "configurationDone" }));
+    requests.push(json!({ "command": "disconnect" }));
+    let messages = exchange(&requests);
+
+    assert_eq!(output(&messages), "");
+    assert!(events(&messages, "terminated").is_empty());
+}
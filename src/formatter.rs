@@ -0,0 +1,235 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    compiler::analyze,
+    source::{collect_files, unreadable},
+    gc::Gc,
+    lexer::Lexer,
+    token::{Token, TokenType},
+};
+
+#[cfg(test)]
+mod tests;
+
+const INDENT: &str = "  ";
+
+/// Reformat `source` into the canonical style. Works on the token stream so
+/// comments survive; refuses to touch sources that don't parse.
+pub fn format_source(source: &str) -> Result<String, String> {
+    let errors = analyze(source.to_string(), &mut Gc::new()).errors;
+    if !errors.is_empty() {
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        return Err(messages.join("\n"));
+    }
+
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut lexer = Lexer::new(source.to_string());
+    loop {
+        let token = lexer.next_token();
+        if token.type_ == TokenType::EOF {
+            break;
+        }
+        tokens.push(token);
+    }
+
+    let mut printer = Printer::new();
+    for (idx, token) in tokens.iter().enumerate() {
+        printer.token(token, tokens.get(idx + 1));
+    }
+    Ok(printer.finish())
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+    parens: usize,
+    line_start: bool,
+    // source line of the last token written
+    last_line: usize,
+    previous: Option<TokenType>,
+    previous_unary: bool,
+}
+
+impl Printer {
+    fn new() -> Printer {
+        Printer {
+            out: String::new(),
+            indent: 0,
+            parens: 0,
+            line_start: true,
+            last_line: 0,
+            previous: None,
+            previous_unary: false,
+        }
+    }
+
+    fn token(&mut self, token: &Token, next: Option<&Token>) {
+        match token.type_ {
+            TokenType::COMMENT => self.comment(token),
+            TokenType::RBRACE => {
+                self.indent = self.indent.saturating_sub(1);
+                if self.previous != Some(TokenType::LBRACE) {
+                    self.newline();
+                }
+                self.write(token, "}".to_string());
+                let stays = matches!(
+                    next.map(|t| t.type_),
+                    Some(TokenType::ELSE) | Some(TokenType::SEMICOLON) | Some(TokenType::RPAREN)
+                );
+                if !stays && !self.trailing_comment(token, next) {
+                    self.newline();
+                }
+            }
+            TokenType::LBRACE => {
+                self.write(token, "{".to_string());
+                self.indent += 1;
+                let empty = next.map(|t| t.type_) == Some(TokenType::RBRACE);
+                if !empty && !self.trailing_comment(token, next) {
+                    self.newline();
+                }
+            }
+            TokenType::SEMICOLON => {
+                self.write(token, ";".to_string());
+                // semicolons inside a for header separate clauses
+                if self.parens == 0 && !self.trailing_comment(token, next) {
+                    self.newline();
+                }
+            }
+            TokenType::STRING => self.write(token, format!("\"{}\"", token.literal)),
+            _ => {
+                if token.type_ == TokenType::LPAREN {
+                    self.parens += 1;
+                } else if token.type_ == TokenType::RPAREN {
+                    self.parens = self.parens.saturating_sub(1);
+                }
+                self.write(token, token.literal.clone());
+            }
+        }
+    }
+
+    fn comment(&mut self, token: &Token) {
+        if !self.line_start && token.lineno == self.last_line {
+            // trailing comment stays on its line
+            self.out.push(' ');
+            self.out.push_str(&token.literal);
+            self.last_line = token.lineno;
+            self.newline();
+        } else {
+            self.newline();
+            self.start_line(token);
+            self.out.push_str(&token.literal);
+            self.last_line = token.lineno;
+            self.newline();
+        }
+    }
+
+    // a comment on the same line as the token before it belongs to that line
+    fn trailing_comment(&self, token: &Token, next: Option<&Token>) -> bool {
+        matches!(next, Some(next) if next.type_ == TokenType::COMMENT && next.lineno == token.lineno)
+    }
+
+    fn write(&mut self, token: &Token, text: String) {
+        if self.line_start {
+            self.start_line(token);
+        } else if self.space_before(token.type_) {
+            self.out.push(' ');
+        }
+        self.out.push_str(&text);
+        self.line_start = false;
+        self.last_line = token.lineno;
+        self.previous_unary = self.is_unary(token.type_);
+        self.previous = Some(token.type_);
+    }
+
+    fn start_line(&mut self, token: &Token) {
+        // keep at most one blank line from the source, but never at the top of a block
+        let after_open = self.previous == Some(TokenType::LBRACE) || self.previous.is_none();
+        if !after_open && token.type_ != TokenType::RBRACE && token.lineno > self.last_line + 1 {
+            self.out.push('\n');
+        }
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.line_start = false;
+    }
+
+    fn newline(&mut self) {
+        if !self.line_start {
+            self.out.push('\n');
+            self.line_start = true;
+        }
+    }
+
+    // whether `-`/`!` at this point negates rather than subtracts
+    fn is_unary(&self, type_: TokenType) -> bool {
+        match type_ {
+            TokenType::NOT => true,
+            TokenType::MINUS => !matches!(
+                self.previous,
+                Some(TokenType::IDENT)
+                    | Some(TokenType::NUM)
+                    | Some(TokenType::STRING)
+                    | Some(TokenType::TRUE)
+                    | Some(TokenType::FALSE)
+                    | Some(TokenType::NIL)
+                    | Some(TokenType::RPAREN)
+                    | Some(TokenType::RBRACKET)
+            ),
+            _ => false,
+        }
+    }
+
+    fn space_before(&self, type_: TokenType) -> bool {
+        if self.previous_unary || matches!(self.previous, Some(TokenType::LPAREN) | Some(TokenType::LBRACKET)) {
+            return false;
+        }
+        match type_ {
+            TokenType::SEMICOLON | TokenType::COMMA | TokenType::RPAREN | TokenType::RBRACKET => false,
+            // calls and indexing hug their callee, control flow keywords and list literals don't
+            TokenType::LPAREN | TokenType::LBRACKET => !matches!(
+                self.previous,
+                Some(TokenType::IDENT) | Some(TokenType::RPAREN) | Some(TokenType::RBRACKET)
+            ),
+            TokenType::RBRACE => self.previous != Some(TokenType::LBRACE),
+            _ => true,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.newline();
+        self.out
+    }
+}
+
+/// `lockhart fmt [--check] <paths>`; returns false if any file failed to
+/// format, or with `--check`, if any file isn't formatted.
+pub fn run(args: &[String]) -> io::Result<bool> {
+    let check = args.iter().any(|arg| arg == "--check");
+    let mut files = Vec::new();
+    for arg in args.iter().filter(|arg| *arg != "--check") {
+        collect_files(Path::new(arg), &mut files)?;
+    }
+
+    let mut ok = true;
+    for file in files {
+        // read as is, not through `open_source_file`, so a shebang is written back
+        let source = fs::read_to_string(&file).map_err(|err| unreadable(&file, err))?;
+        match format_source(&source) {
+            Ok(formatted) if formatted == source => {}
+            Ok(formatted) => {
+                if check {
+                    println!("{} is not formatted", file.display());
+                    ok = false;
+                } else {
+                    fs::write(&file, formatted)?;
+                    println!("formatted {}", file.display());
+                }
+            }
+            Err(err) => {
+                eprintln!("{}: could not format\n{}", file.display(), err);
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
@@ -0,0 +1,58 @@
+use super::format_source;
+
+fn assert_formats(source: &str, expected: &str) {
+    let formatted = format_source(source).unwrap();
+    assert_eq!(formatted, expected);
+    // formatting is idempotent
+    assert_eq!(format_source(&formatted).unwrap(), expected);
+}
+
+#[test]
+fn spaces_around_operators() {
+    assert_formats("let x=1+2*-3;let y=!x==false;", "let x = 1 + 2 * -3;\nlet y = !x == false;\n");
+    assert_formats("let z = a>=b and c!=d or -(e);", "let z = a >= b and c != d or -(e);\n");
+}
+
+#[test]
+fn lists_and_indexing() {
+    assert_formats("let xs=[ 1,[2 ],[]];xs [0]=f(xs)[1][0]-1;", "let xs = [1, [2], []];\nxs[0] = f(xs)[1][0] - 1;\n");
+}
+
+#[test]
+fn indents_blocks_and_places_braces() {
+    let source = "fn add(a,b){\nreturn a+b;}\nif(x){print 1;}else{print 2;}";
+    let expected = "fn add(a, b) {\n  return a + b;\n}\nif (x) {\n  print 1;\n} else {\n  print 2;\n}\n";
+    assert_formats(source, expected);
+}
+
+#[test]
+fn nested_blocks_and_for_headers() {
+    let source = "for(let i=0;i<3;i=i+1){while(true){f(i,\"s\");}}";
+    let expected = "for (let i = 0; i < 3; i = i + 1) {\n  while (true) {\n    f(i, \"s\");\n  }\n}\n";
+    assert_formats(source, expected);
+}
+
+#[test]
+fn empty_blocks_stay_on_one_line() {
+    assert_formats("fn noop( ) {\n\n}", "fn noop() {}\n");
+}
+
+#[test]
+fn preserves_comments() {
+    let source = "// header\nlet x = 1; // trailing\n{ // open\n// inside\nprint x;\n} // close\n";
+    let expected = "// header\nlet x = 1; // trailing\n{ // open\n  // inside\n  print x;\n} // close\n";
+    assert_formats(source, expected);
+}
+
+#[test]
+fn collapses_blank_lines() {
+    let source = "let a = 1;\n\n\n\nlet b = 2;\nlet c = 3;\n{\n\nprint a;\n\n}\n";
+    let expected = "let a = 1;\n\nlet b = 2;\nlet c = 3;\n{\n  print a;\n}\n";
+    assert_formats(source, expected);
+}
+
+#[test]
+fn refuses_invalid_source() {
+    let err = format_source("let = ;").unwrap_err();
+    assert!(err.contains("Expected variable name"), "{}", err);
+}
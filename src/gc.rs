@@ -1,31 +1,35 @@
 use std::{
     cell::Cell,
-    mem::size_of,
-    ops::Deref,
+    ops::{Deref, DerefMut},
     ptr::NonNull,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    object::{ObjFunction, ObjString, ObjectType},
+    object::{ObjFunction, ObjList, ObjNative, ObjString, ObjectType},
     table::Table,
     value::Value,
 };
 
+#[cfg(test)]
+mod tests;
+
 #[repr(C)]
 pub struct GcObject {
     marked: Cell<bool>,
     next: Cell<Option<NonNull<GcObject>>>,
     obj_type: ObjectType,
-    size: usize,
+    // what the object counted for in `bytes_allocated` when it was last measured
+    size: Cell<usize>,
 }
 
 impl GcObject {
-    pub fn new(obj_type: ObjectType, size: usize) -> GcObject {
+    pub fn new(obj_type: ObjectType) -> GcObject {
         GcObject {
             marked: Cell::new(false),
             next: Cell::new(None),
             obj_type,
-            size,
+            size: Cell::new(0),
         }
     }
 }
@@ -35,6 +39,8 @@ pub struct GcRef<T> {
 
 pub trait GcManaged {
     fn header(&self) -> &GcObject;
+    /// Bytes the object takes up: itself and the buffers it owns.
+    fn size(&self) -> usize;
 }
 
 impl<T> GcRef<T> {
@@ -43,6 +49,22 @@ impl<T> GcRef<T> {
             pointer: NonNull::dangling(),
         }
     }
+
+    /// The address of the object, for packing into a `NanBox`.
+    #[cfg(feature = "nan-boxing")]
+    pub fn as_ptr(self) -> *mut T {
+        self.pointer.as_ptr()
+    }
+
+    /// # Safety
+    /// `pointer` must have come from `as_ptr` on an object the collector
+    /// still owns.
+    #[cfg(feature = "nan-boxing")]
+    pub unsafe fn from_ptr(pointer: *mut T) -> GcRef<T> {
+        GcRef {
+            pointer: NonNull::new_unchecked(pointer),
+        }
+    }
 }
 
 impl<T> Deref for GcRef<T> {
@@ -53,6 +75,12 @@ impl<T> Deref for GcRef<T> {
     }
 }
 
+impl<T> DerefMut for GcRef<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.pointer.as_mut() }
+    }
+}
+
 impl<T> Clone for GcRef<T> {
     fn clone(&self) -> Self {
         Self {
@@ -71,43 +99,181 @@ impl<T> PartialEq for GcRef<T> {
 
 impl<T> Eq for GcRef<T> {}
 
+/// A snapshot of the heap, for diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcStats {
+    pub bytes_allocated: usize,
+    pub next_gc: usize,
+    pub objects: usize,
+    /// collections started, incremental or full
+    pub cycles: usize,
+    /// times the collector stopped the program, one per increment or full collection
+    pub pauses: usize,
+    pub last_pause: Duration,
+    pub max_pause: Duration,
+    pub total_pause: Duration,
+}
+
+impl std::fmt::Display for GcStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} collections in {} pauses, longest {:.3?}, {:.3?} in all",
+            self.cycles, self.pauses, self.max_pause, self.total_pause
+        )
+    }
+}
+
+/// When collections start. The first starts once the heap reaches
+/// `threshold` bytes, and each later one once the heap has grown to
+/// `growth_factor` times what the last one left, but never below `threshold`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    pub threshold: usize,
+    pub growth_factor: f64,
+}
+
+impl GcConfig {
+    /// Whether the heap can grow by `factor` and still come round to another
+    /// collection: infinite or NaN factors would put it off for good.
+    pub fn valid_growth_factor(factor: f64) -> bool {
+        factor.is_finite() && factor >= 1.0
+    }
+}
+
+impl Default for GcConfig {
+    fn default() -> GcConfig {
+        GcConfig {
+            threshold: 1024 * 1024,
+            growth_factor: 2.0,
+        }
+    }
+}
+
+/// Where the collector is in a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Mark,
+    Sweep,
+}
+
+/// An incremental tri-colour mark/sweep collector. Unmarked objects are
+/// white, marked ones on `grey_stack` are grey and the other marked ones are
+/// black. A collection runs in `step`s of bounded work between instructions,
+/// so objects can change while it marks: `write_barrier` marks whatever is
+/// stored into the heap, objects allocated while marking start out grey, and
+/// the roots are marked again before marking finishes.
 pub struct Gc {
+    config: GcConfig,
     bytes_allocated: usize,
     next_gc: usize,
+    /// `bytes_allocated` at which `should_collect` next says yes
+    next_step: usize,
     first: Option<NonNull<GcObject>>,
     strings: Table,
-    grey_stack: Vec<NonNull<GcObject>>,
+    /// objects to scan, and the index of the first list item not scanned yet
+    grey_stack: Vec<(NonNull<GcObject>, usize)>,
+    phase: Phase,
+    /// the last object the sweep kept, and the next one it looks at
+    sweep_previous: Option<NonNull<GcObject>>,
+    sweep_current: Option<NonNull<GcObject>>,
+    cycles: usize,
+    pauses: usize,
+    last_pause: Duration,
+    max_pause: Duration,
+    total_pause: Duration,
 }
 
 impl Gc {
-    const HEAP_GROW_FACTOR: usize = 2;
+    /// Objects and list items one increment marks, or objects it sweeps.
+    const STEP_WORK: usize = 2048;
+    /// Bytes the program allocates between increments.
+    const STEP_BYTES: usize = 16 * 1024;
+
     pub fn new() -> Gc {
+        let config = GcConfig::default();
         Gc {
+            config,
             bytes_allocated: 0,
-            next_gc: 1024 * 1024,
+            next_gc: config.threshold,
+            next_step: config.threshold,
             first: None,
             strings: Table::new(),
             grey_stack: Vec::new(),
+            phase: Phase::Idle,
+            sweep_previous: None,
+            sweep_current: None,
+            cycles: 0,
+            pauses: 0,
+            last_pause: Duration::ZERO,
+            max_pause: Duration::ZERO,
+            total_pause: Duration::ZERO,
+        }
+    }
+
+    /// Change when collections start. A collection under way carries on,
+    /// the next one starts by the new rules.
+    /// Panics if `config.growth_factor` isn't a finite number of at least 1.
+    pub fn configure(&mut self, config: GcConfig) {
+        assert!(
+            GcConfig::valid_growth_factor(config.growth_factor),
+            "invalid gc growth factor {}",
+            config.growth_factor
+        );
+        self.config = config;
+        if self.phase == Phase::Idle {
+            self.next_gc = self.config.threshold;
+            self.next_step = self.next_gc;
         }
     }
 
     pub fn alloc<T: GcManaged>(&mut self, object: T) -> GcRef<T> {
         unsafe {
-            self.bytes_allocated += size_of::<T>();
+            let size = object.size();
+            self.bytes_allocated += size;
             let boxed_o = Box::new(object);
             let header_ref = boxed_o.as_ref().header();
+            header_ref.size.set(size);
             header_ref.next.set(self.first.take());
             let header = NonNull::from(header_ref);
             let ptr = NonNull::new_unchecked(Box::into_raw(boxed_o));
             self.first = Some(header);
+            match self.phase {
+                Phase::Idle => {}
+                // it may be handed objects nothing else points to any more
+                Phase::Mark => {
+                    header.as_ref().marked.set(true);
+                    self.grey_stack.push((header, 0));
+                }
+                // it goes in ahead of the sweep, which mustn't unlink it along with the old first object
+                Phase::Sweep => {
+                    if self.sweep_previous.is_none() {
+                        self.sweep_previous = Some(header);
+                    }
+                }
+            }
             GcRef { pointer: ptr }
         }
     }
 
+    /// Measure `object` again after it grew or shrank, e.g. a list that was
+    /// pushed to.
+    pub fn resize<T: GcManaged>(&mut self, object: GcRef<T>) {
+        let header = object.header();
+        let size = object.size();
+        self.bytes_allocated = self.bytes_allocated - header.size.get() + size;
+        header.size.set(size);
+    }
+
     // check if string is already interned, if not then allocate and return reference
     pub fn intern(&mut self, s: String) -> GcRef<ObjString> {
         let o_string = ObjString::from_string(s);
         if let Some(value) = self.strings.find_string(&o_string.s, o_string.hash) {
+            // unmarked strings stay in the table until marking ends, and this one is in use again
+            if self.phase == Phase::Mark {
+                self.mark_object(value);
+            }
             value
         } else {
             let reference = self.alloc(o_string);
@@ -116,8 +282,37 @@ impl Gc {
         }
     }
 
+    pub fn stats(&self) -> GcStats {
+        let mut objects = 0;
+        let mut current = self.first;
+        while let Some(object) = current {
+            objects += 1;
+            current = unsafe { object.as_ref().next.get() };
+        }
+        GcStats {
+            bytes_allocated: self.bytes_allocated,
+            next_gc: self.next_gc,
+            objects,
+            cycles: self.cycles,
+            pauses: self.pauses,
+            last_pause: self.last_pause,
+            max_pause: self.max_pause,
+            total_pause: self.total_pause,
+        }
+    }
+
+    /// Whether to run a `step`: the heap outgrew `next_gc`, or a collection
+    /// is under way and the program has allocated enough since the last step.
     pub fn should_collect(&self) -> bool {
-        self.bytes_allocated > self.next_gc
+        self.bytes_allocated > self.next_step
+    }
+
+    /// Keep a marked object from pointing at an unmarked one: call it with
+    /// every `value` stored into an object already on the heap.
+    pub fn write_barrier(&mut self, value: &Value) {
+        if self.phase == Phase::Mark {
+            self.mark_value(value);
+        }
     }
 
     pub fn mark_object<T: GcManaged>(&mut self, reference: GcRef<T>) {
@@ -127,15 +322,17 @@ impl Gc {
                 return;
             }
             object.as_ref().marked.set(true);
-            self.grey_stack.push(object);
+            self.grey_stack.push((object, 0));
         }
     }
 
     pub fn mark_value(&mut self, value: &Value) {
-        if let Value::STR(s) = value {
-            self.mark_object(*s);
-        } else if let Value::FUNCTION(f) = value {
-            self.mark_object(*f);
+        match value {
+            Value::STR(s) => self.mark_object(*s),
+            Value::FUNCTION(f) => self.mark_object(*f),
+            Value::NATIVE(n) => self.mark_object(*n),
+            Value::LIST(l) => self.mark_object(*l),
+            Value::NUMBER(_) | Value::BOOL(_) | Value::NIL => {}
         }
     }
 
@@ -146,31 +343,135 @@ impl Gc {
         }
     }
 
-    pub fn collect_garbage(&mut self) {
-        self.trace_references();
+    /// One increment of a collection, starting one if none is under way.
+    /// `mark_roots` marks what the program holds outside the heap; it runs
+    /// when marking starts and again when it finishes.
+    pub fn step(&mut self, mark_roots: impl FnOnce(&mut Gc)) {
+        let started = Instant::now();
+        match self.phase {
+            Phase::Idle => {
+                self.cycles += 1;
+                self.phase = Phase::Mark;
+                mark_roots(self);
+                self.trace_references(Gc::STEP_WORK);
+            }
+            Phase::Mark => {
+                self.trace_references(Gc::STEP_WORK);
+                if self.grey_stack.is_empty() {
+                    // the roots change without barriers, so look at them again and finish in one go
+                    mark_roots(self);
+                    self.trace_references(usize::MAX);
+                    self.remove_white_strings();
+                    self.start_sweep();
+                }
+            }
+            Phase::Sweep => {
+                if self.sweep(Gc::STEP_WORK) {
+                    self.finish_cycle();
+                }
+            }
+        }
+        if self.phase != Phase::Idle {
+            self.next_step = self.bytes_allocated + Gc::STEP_BYTES;
+        }
+        self.record_pause(started.elapsed());
+    }
+
+    /// A whole collection in one pause, finishing any that is under way.
+    pub fn collect_garbage(&mut self, mark_roots: impl FnOnce(&mut Gc)) {
+        let started = Instant::now();
+        match self.phase {
+            Phase::Idle => self.cycles += 1,
+            // start marking over: objects allocated since it began were taken to be live
+            Phase::Mark => self.unmark_all(),
+            Phase::Sweep => {
+                self.sweep(usize::MAX);
+                self.cycles += 1;
+            }
+        }
+        self.phase = Phase::Mark;
+        mark_roots(self);
+        self.trace_references(usize::MAX);
         self.remove_white_strings();
-        self.sweep();
-        self.next_gc = (self.bytes_allocated * Gc::HEAP_GROW_FACTOR).max(1024 * 1024);
+        self.start_sweep();
+        self.sweep(usize::MAX);
+        self.finish_cycle();
+        self.record_pause(started.elapsed());
     }
 
-    fn trace_references(&mut self) {
-        while let Some(object) = self.grey_stack.pop() {
-            self.blacken_object(object);
+    fn unmark_all(&mut self) {
+        self.grey_stack.clear();
+        let mut current = self.first;
+        while let Some(object) = current {
+            unsafe {
+                object.as_ref().marked.set(false);
+                current = object.as_ref().next.get();
+            }
         }
     }
 
-    fn blacken_object(&mut self, object: NonNull<GcObject>) {
+    fn record_pause(&mut self, pause: Duration) {
+        self.pauses += 1;
+        self.last_pause = pause;
+        self.max_pause = self.max_pause.max(pause);
+        self.total_pause += pause;
+    }
+
+    fn start_sweep(&mut self) {
+        self.phase = Phase::Sweep;
+        self.sweep_previous = None;
+        self.sweep_current = self.first;
+    }
+
+    fn finish_cycle(&mut self) {
+        self.phase = Phase::Idle;
+        let grown = self.bytes_allocated as f64 * self.config.growth_factor;
+        self.next_gc = (grown as usize).max(self.config.threshold);
+        self.next_step = self.next_gc;
+    }
+
+    /// Scan grey objects until `budget` objects and list items are done.
+    fn trace_references(&mut self, budget: usize) {
+        let mut work = 0;
+        while work < budget {
+            let Some((object, from)) = self.grey_stack.pop() else {
+                break;
+            };
+            work += self.blacken_object(object, from, budget - work);
+        }
+    }
+
+    /// Mark what `object` points to, returning the work done. A long list is
+    /// scanned `budget` items at a time and goes back on the grey stack
+    /// until it's done.
+    fn blacken_object(&mut self, object: NonNull<GcObject>, from: usize, budget: usize) -> usize {
         unsafe {
             match object.as_ref().obj_type {
-                ObjectType::STRING => {}
+                ObjectType::STRING => 1,
                 ObjectType::FUNCTION => {
                     let function = object.cast::<ObjFunction>();
                     self.mark_object(function.as_ref().name);
                     for value in &function.as_ref().chunk.constants {
                         self.mark_value(value);
                     }
+                    1 + function.as_ref().chunk.constants.len()
                 }
-                ObjectType::CLASS => {}
+                ObjectType::NATIVE => 1,
+                ObjectType::LIST => {
+                    let items = &object.cast::<ObjList>().as_ref().items;
+                    // pop may have shortened the list since it was last looked at
+                    let from = from.min(items.len());
+                    let to = items.len().min(from.saturating_add(budget));
+                    // the rest waits under the items, which keeps the grey stack short
+                    if to < items.len() {
+                        self.grey_stack.push((object, to));
+                    }
+                    for value in &items[from..to] {
+                        self.mark_value(value);
+                    }
+                    1 + to - from
+                }
+                ObjectType::CLASS => 1,
             }
         }
     }
@@ -194,30 +495,32 @@ impl Gc {
         }
     }
 
-    fn sweep(&mut self) {
+    /// Sweep up to `budget` objects, returning whether the sweep is done.
+    fn sweep(&mut self, budget: usize) -> bool {
         unsafe {
-            let mut previous: Option<NonNull<GcObject>> = None;
-            let mut current = self.first;
-
-            while let Some(object) = current {
+            let mut work = 0;
+            while let Some(object) = self.sweep_current {
+                if work == budget {
+                    return false;
+                }
+                work += 1;
+                let next = object.as_ref().next.get();
                 if object.as_ref().marked.get() {
                     object.as_ref().marked.set(false);
-                    previous = Some(object);
-                    current = object.as_ref().next.get();
+                    self.sweep_previous = Some(object);
                 } else {
-                    let unreached = object;
-                    current = object.as_ref().next.get();
-
-                    if let Some(prev) = previous {
-                        prev.as_ref().next.set(current);
+                    if let Some(prev) = self.sweep_previous {
+                        prev.as_ref().next.set(next);
                     } else {
-                        self.first = current;
+                        self.first = next;
                     }
 
-                    self.bytes_allocated = self.bytes_allocated.saturating_sub(unreached.as_ref().size);
-                    self.free_object(unreached);
+                    self.bytes_allocated = self.bytes_allocated.saturating_sub(object.as_ref().size.get());
+                    self.free_object(object);
                 }
+                self.sweep_current = next;
             }
+            true
         }
     }
 
@@ -230,6 +533,12 @@ impl Gc {
                 ObjectType::FUNCTION => {
                     drop(Box::from_raw(object.cast::<ObjFunction>().as_ptr()));
                 }
+                ObjectType::NATIVE => {
+                    drop(Box::from_raw(object.cast::<ObjNative>().as_ptr()));
+                }
+                ObjectType::LIST => {
+                    drop(Box::from_raw(object.cast::<ObjList>().as_ptr()));
+                }
                 ObjectType::CLASS => {}
             }
         }
@@ -0,0 +1,202 @@
+use std::mem::size_of;
+
+use crate::{
+    bytecode::Opcode,
+    chunk::Lineno,
+    object::{ObjFunction, ObjList, ObjString},
+    value::Value,
+};
+
+use super::{Gc, GcConfig, GcRef, Phase};
+
+/// A list of `len` numbers, then `last`.
+fn long_list(gc: &mut Gc, len: usize, last: Value) -> GcRef<ObjList> {
+    let mut items: Vec<Value> = (0..len).map(|n| Value::NUMBER(n as f64)).collect();
+    items.push(last);
+    gc.alloc(ObjList::new(items))
+}
+
+/// Step until the collection under way is over.
+fn finish(gc: &mut Gc, roots: &[GcRef<ObjList>]) {
+    while gc.phase != Phase::Idle {
+        gc.step(|gc| roots.iter().for_each(|root| gc.mark_object(*root)));
+    }
+}
+
+#[test]
+fn marking_and_sweeping_take_many_steps() {
+    let mut gc = Gc::new();
+    let kept = long_list(&mut gc, 3 * Gc::STEP_WORK, Value::NIL);
+    for _ in 0..2 * Gc::STEP_WORK {
+        gc.alloc(ObjList::new(Vec::new()));
+    }
+    gc.step(|gc| gc.mark_object(kept));
+    finish(&mut gc, &[kept]);
+
+    let stats = gc.stats();
+    assert_eq!(stats.objects, 1);
+    assert_eq!(stats.cycles, 1);
+    // the long list takes a few steps to mark, the objects a few to sweep
+    assert!(stats.pauses >= 5, "{:?}", stats);
+    assert!(stats.max_pause <= stats.total_pause);
+}
+
+#[test]
+fn items_moved_into_a_scanned_list_are_kept() {
+    let mut gc = Gc::new();
+    let moved = gc.alloc(ObjList::new(Vec::new()));
+    let mut from = long_list(&mut gc, 2 * Gc::STEP_WORK, Value::LIST(moved));
+    let mut to = gc.alloc(ObjList::new(Vec::new()));
+    // `to` is marked last, so it is scanned first, and `from` only partly
+    gc.step(|gc| {
+        gc.mark_object(from);
+        gc.mark_object(to);
+    });
+    assert_eq!(gc.phase, Phase::Mark);
+
+    let item = from.items.pop().unwrap();
+    gc.write_barrier(&item);
+    to.items.push(item);
+    finish(&mut gc, &[from, to]);
+    assert_eq!(gc.stats().objects, 3);
+}
+
+#[test]
+fn objects_allocated_while_marking_are_kept() {
+    let mut gc = Gc::new();
+    let mut root = long_list(&mut gc, 2 * Gc::STEP_WORK, Value::NIL);
+    gc.step(|gc| gc.mark_object(root));
+    assert_eq!(gc.phase, Phase::Mark);
+
+    // stored without a barrier: the new objects are marked already
+    let inner = gc.alloc(ObjList::new(Vec::new()));
+    let outer = gc.alloc(ObjList::new(vec![Value::LIST(inner)]));
+    root.items[0] = Value::LIST(outer);
+    finish(&mut gc, &[root]);
+    assert_eq!(gc.stats().objects, 3);
+}
+
+#[test]
+fn interning_an_unmarked_string_while_marking_keeps_it() {
+    let mut gc = Gc::new();
+    let mut root = long_list(&mut gc, 2 * Gc::STEP_WORK, Value::NIL);
+    let word = gc.intern("word".to_string());
+    gc.step(|gc| gc.mark_object(root));
+    assert_eq!(gc.phase, Phase::Mark);
+
+    assert!(gc.intern("word".to_string()) == word);
+    root.items[0] = Value::STR(word);
+    finish(&mut gc, &[root]);
+    assert_eq!(gc.stats().objects, 2);
+}
+
+#[test]
+fn objects_allocated_while_sweeping_wait_for_the_next_collection() {
+    let mut gc = Gc::new();
+    let root = gc.alloc(ObjList::new(Vec::new()));
+    for _ in 0..2 * Gc::STEP_WORK {
+        gc.alloc(ObjList::new(Vec::new()));
+    }
+    while gc.phase != Phase::Sweep {
+        gc.step(|gc| gc.mark_object(root));
+    }
+    // one ahead of a sweep that hasn't kept anything yet, one after it has
+    gc.alloc(ObjList::new(Vec::new()));
+    gc.step(|gc| gc.mark_object(root));
+    let late = gc.alloc(ObjList::new(Vec::new()));
+    finish(&mut gc, &[root]);
+    assert_eq!(gc.stats().objects, 3);
+
+    gc.collect_garbage(|gc| {
+        gc.mark_object(root);
+        gc.mark_object(late);
+    });
+    assert_eq!(gc.stats().objects, 2);
+}
+
+#[test]
+fn a_full_collection_finishes_the_one_under_way() {
+    let mut gc = Gc::new();
+    let root = long_list(&mut gc, 2 * Gc::STEP_WORK, Value::NIL);
+    gc.alloc(ObjList::new(Vec::new()));
+    gc.step(|gc| gc.mark_object(root));
+    assert_eq!(gc.phase, Phase::Mark);
+
+    gc.collect_garbage(|gc| gc.mark_object(root));
+    assert_eq!(gc.phase, Phase::Idle);
+    let stats = gc.stats();
+    assert_eq!((stats.objects, stats.cycles, stats.pauses), (1, 1, 2));
+}
+
+#[test]
+fn roots_are_marked_again_before_marking_ends() {
+    let mut gc = Gc::new();
+    let root = long_list(&mut gc, 2 * Gc::STEP_WORK, Value::NIL);
+    let picked_up = gc.alloc(ObjList::new(Vec::new()));
+    gc.step(|gc| gc.mark_object(root));
+    assert_eq!(gc.phase, Phase::Mark);
+
+    // the program got hold of it from somewhere the collector already looked
+    finish(&mut gc, &[root, picked_up]);
+    assert_eq!(gc.stats().objects, 2);
+}
+
+#[test]
+fn objects_count_the_buffers_they_own() {
+    let mut gc = Gc::new();
+    gc.intern("x".repeat(10_000));
+    assert!(gc.stats().bytes_allocated >= size_of::<ObjString>() + 10_000);
+
+    let before = gc.stats().bytes_allocated;
+    gc.alloc(ObjList::new(Vec::with_capacity(1000)));
+    assert_eq!(gc.stats().bytes_allocated - before, size_of::<ObjList>() + 1000 * size_of::<Value>());
+
+    let name = gc.intern("f".to_string());
+    let mut function = ObjFunction::new(name);
+    for _ in 0..1000 {
+        function.chunk.write_chunk(Opcode::OP_NIL, Lineno(1));
+    }
+    let before = gc.stats().bytes_allocated;
+    gc.alloc(function);
+    assert!(gc.stats().bytes_allocated - before >= size_of::<ObjFunction>() + 1000);
+}
+
+#[test]
+fn resized_objects_are_counted_and_freed_at_their_new_size() {
+    let mut gc = Gc::new();
+    let mut list = gc.alloc(ObjList::new(Vec::new()));
+    list.items.extend((0..1000).map(|n| Value::NUMBER(n as f64)));
+    gc.resize(list);
+    assert_eq!(gc.stats().bytes_allocated, size_of::<ObjList>() + list.items.capacity() * size_of::<Value>());
+
+    gc.collect_garbage(|_| {});
+    assert_eq!(gc.stats().bytes_allocated, 0);
+}
+
+#[test]
+fn the_config_decides_when_collections_start() {
+    let mut gc = Gc::new();
+    gc.configure(GcConfig {
+        threshold: 4096,
+        growth_factor: 1.5,
+    });
+    assert_eq!(gc.stats().next_gc, 4096);
+    let kept = gc.alloc(ObjList::new(vec![Value::NIL; 1000]));
+    gc.alloc(ObjList::new(vec![Value::NIL; 1000]));
+    assert!(gc.should_collect());
+
+    gc.collect_garbage(|gc| gc.mark_object(kept));
+    let live = gc.stats().bytes_allocated;
+    assert_eq!(gc.stats().next_gc, (live as f64 * 1.5) as usize);
+    gc.collect_garbage(|_| {});
+    assert_eq!(gc.stats().next_gc, 4096);
+}
+
+#[test]
+#[should_panic(expected = "invalid gc growth factor inf")]
+fn a_growth_factor_that_would_stop_collection_is_refused() {
+    Gc::new().configure(GcConfig {
+        threshold: 4096,
+        growth_factor: f64::INFINITY,
+    });
+}
@@ -0,0 +1,97 @@
+use crate::{
+    gc::{Gc, GcRef},
+    object::ObjString,
+    table::Table,
+    value::Value,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Global variables. The compiler gives each name a slot the first time it
+/// sees it and emits the slot, so the VM reads and writes globals by index.
+/// Slots outlive the script that made them: a REPL line can refer to a
+/// global a later line defines, and redefining a name reuses its slot.
+pub struct Globals {
+    // name -> slot number
+    slots: Table,
+    names: Vec<GcRef<ObjString>>,
+    // None until the global is defined
+    values: Vec<Option<Value>>,
+}
+
+impl Globals {
+    pub fn new() -> Globals {
+        Globals {
+            slots: Table::new(),
+            names: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// The slot for `name`, given a new one if it doesn't have one yet.
+    pub fn slot(&mut self, name: GcRef<ObjString>) -> usize {
+        if let Some(slot) = self.lookup(name) {
+            return slot;
+        }
+        let slot = self.names.len();
+        self.slots.set(name, Value::NUMBER(slot as f64));
+        self.names.push(name);
+        self.values.push(None);
+        slot
+    }
+
+    pub fn lookup(&self, name: GcRef<ObjString>) -> Option<usize> {
+        match self.slots.get(name) {
+            Some(Value::NUMBER(slot)) => Some(slot as usize),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self, slot: usize) -> GcRef<ObjString> {
+        self.names[slot]
+    }
+
+    /// The value in `slot`, or None if it was never defined.
+    #[inline]
+    pub fn get(&self, slot: usize) -> Option<&Value> {
+        self.values[slot].as_ref()
+    }
+
+    pub fn define(&mut self, slot: usize, value: Value) {
+        self.values[slot] = Some(value);
+    }
+
+    /// Assign to a global that was defined already; false if it wasn't.
+    #[inline]
+    pub fn assign(&mut self, slot: usize, value: Value) -> bool {
+        match &mut self.values[slot] {
+            Some(current) => {
+                *current = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Defined globals and their values, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (GcRef<ObjString>, Value)> + '_ {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| value.as_ref().map(|value| (*name, value.clone())))
+    }
+
+    pub fn mark(&self, gc: &mut Gc) {
+        gc.mark_table(&self.slots);
+        for value in self.values.iter().flatten() {
+            gc.mark_value(value);
+        }
+    }
+}
+
+impl Default for Globals {
+    fn default() -> Globals {
+        Globals::new()
+    }
+}
@@ -0,0 +1,34 @@
+use crate::{gc::Gc, value::Value};
+
+use super::Globals;
+
+#[test]
+fn names_keep_their_slots() {
+    let mut gc = Gc::new();
+    let mut globals = Globals::new();
+    let a = gc.intern("a".to_string());
+    let b = gc.intern("b".to_string());
+    assert_eq!(globals.slot(a), 0);
+    assert_eq!(globals.slot(b), 1);
+    assert_eq!(globals.slot(a), 0);
+    assert_eq!(globals.lookup(b), Some(1));
+    assert_eq!(globals.lookup(gc.intern("c".to_string())), None);
+    assert_eq!(globals.name(1).s, "b");
+}
+
+#[test]
+fn slots_are_undefined_until_defined() {
+    let mut gc = Gc::new();
+    let mut globals = Globals::new();
+    let a = globals.slot(gc.intern("a".to_string()));
+    let b = globals.slot(gc.intern("b".to_string()));
+    assert!(globals.get(a).is_none());
+    assert!(!globals.assign(a, Value::NUMBER(1.0)));
+    assert!(globals.get(a).is_none());
+
+    globals.define(b, Value::NUMBER(2.0));
+    assert!(globals.assign(b, Value::NUMBER(3.0)));
+    assert_eq!(globals.get(b).and_then(|value| value.get_number()), Some(3.0));
+    let defined: Vec<String> = globals.iter().map(|(name, _)| name.s.clone()).collect();
+    assert_eq!(defined, ["b"]);
+}
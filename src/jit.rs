@@ -0,0 +1,173 @@
+use crate::{
+    gc::GcRef,
+    globals::Globals,
+    object::ObjFunction,
+    value::{from_slot, to_slot, Slot, Value},
+};
+
+use self::{
+    codegen::{Codegen, Compiled},
+    plan::{plan, Ty},
+};
+
+mod codegen;
+mod plan;
+#[cfg(test)]
+mod tests;
+
+/// Times a function has to be called, or a loop has to come round, before
+/// its code is compiled.
+pub const DEFAULT_THRESHOLD: u32 = 1000;
+
+// compiled code whose guards keep failing is thrown away
+const GUARD_EXIT_LIMIT: u32 = 100;
+
+/// The places a function is entered from, its start and its loops, and what
+/// the JIT did with each.
+#[derive(Default)]
+pub struct FunctionJit {
+    entries: Vec<EntryPoint>,
+}
+
+struct EntryPoint {
+    offset: usize,
+    hits: u32,
+    code: Code,
+}
+
+enum Code {
+    Cold,
+    Compiled { compiled: Compiled, guard_exits: u32 },
+    // the types don't settle, or the guards keep failing
+    Rejected,
+}
+
+/// How much the JIT has done.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JitStats {
+    pub compiled: usize,
+    pub rejected: usize,
+    // times compiled code ran
+    pub entered: usize,
+}
+
+/// Compiles hot functions and loops to native code with Cranelift. Only the
+/// numeric part of the instruction set is compiled, specialised for the
+/// number and boolean slots the frame had when it got hot; at anything else
+/// compiled code writes the frame back and the interpreter carries on from
+/// that instruction.
+pub struct Jit {
+    threshold: u32,
+    // made the first time something gets hot
+    codegen: Option<Codegen>,
+    // the frame's slots while compiled code runs
+    state: Vec<f64>,
+    stats: JitStats,
+}
+
+impl Jit {
+    pub fn new(threshold: u32) -> Jit {
+        Jit {
+            threshold,
+            codegen: None,
+            state: Vec::new(),
+            stats: JitStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> JitStats {
+        self.stats
+    }
+
+    /// `function`'s frame, `stack[base..top]`, reached `offset`: the start of
+    /// the function or of a loop. Runs compiled code from there if there is
+    /// some for what the frame holds now, and returns the offset the
+    /// interpreter carries on from and the new top of the stack.
+    pub fn enter(
+        &mut self,
+        mut function: GcRef<ObjFunction>,
+        offset: usize,
+        stack: &mut Vec<Slot>,
+        base: usize,
+        top: usize,
+        globals: &mut Globals,
+    ) -> Option<(usize, usize)> {
+        let function = &mut *function;
+        let entries = &mut function.jit.entries;
+        let idx = match entries.iter().position(|entry| entry.offset == offset) {
+            Some(idx) => idx,
+            None => {
+                entries.push(EntryPoint { offset, hits: 0, code: Code::Cold });
+                entries.len() - 1
+            }
+        };
+        let entry = &mut entries[idx];
+
+        if let Code::Cold = entry.code {
+            entry.hits += 1;
+            if entry.hits < self.threshold {
+                return None;
+            }
+            let types = stack[base..top].iter().map(|slot| Ty::of(&from_slot(slot))).collect();
+            if self.codegen.is_none() {
+                self.codegen = Codegen::new().ok();
+            }
+            entry.code = match (&mut self.codegen, plan(&function.chunk, offset, types)) {
+                (Some(codegen), Ok(plan)) => codegen
+                    .compile(&plan, &function.chunk)
+                    .map_or(Code::Rejected, |compiled| Code::Compiled { compiled, guard_exits: 0 }),
+                _ => Code::Rejected,
+            };
+            match entry.code {
+                Code::Rejected => self.stats.rejected += 1,
+                _ => self.stats.compiled += 1,
+            }
+        }
+        let (compiled, guard_exits) = match &mut entry.code {
+            Code::Compiled { compiled, guard_exits } => (compiled, guard_exits),
+            _ => return None,
+        };
+        let frame = &stack[base..top];
+        if frame.len() != compiled.entry_types.len()
+            || frame.iter().zip(&compiled.entry_types).any(|(slot, ty)| Ty::of(&from_slot(slot)) != *ty)
+        {
+            return None;
+        }
+
+        self.state.clear();
+        self.state.resize(compiled.max_height, 0.0);
+        for (slot, value) in frame.iter().zip(self.state.iter_mut()) {
+            *value = match from_slot(slot) {
+                Value::NUMBER(n) => n,
+                Value::BOOL(b) => f64::from(u8::from(b)),
+                _ => 0.0,
+            };
+        }
+        self.stats.entered += 1;
+        // SAFETY: `state` has room for every slot the code uses, and nothing
+        // else touches the globals while it runs
+        let exit = unsafe { (compiled.code)(self.state.as_mut_ptr(), globals) } as usize;
+
+        let exit = &compiled.exits[exit];
+        let height = exit.types.len();
+        if stack.len() < base + height {
+            stack.resize(base + height, to_slot(Value::NIL));
+        }
+        for (idx, ty) in exit.types.iter().enumerate() {
+            match ty {
+                Ty::Number => stack[base + idx] = to_slot(Value::NUMBER(self.state[idx])),
+                Ty::Bool => stack[base + idx] = to_slot(Value::BOOL(self.state[idx] != 0.0)),
+                // compiled code never moves these
+                Ty::Other => {}
+            }
+        }
+        let resume = (exit.resume, base + height);
+        if exit.guard {
+            *guard_exits += 1;
+            if *guard_exits > GUARD_EXIT_LIMIT {
+                entry.code = Code::Rejected;
+            }
+        }
+        Some(resume)
+    }
+}
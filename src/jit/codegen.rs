@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+
+use cranelift_codegen::{
+    ir::{condcodes::FloatCC, types, AbiParam, Block, InstBuilder, MemFlags, Signature, StackSlotData, StackSlotKind},
+    settings::{self, Configurable},
+    Context,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+use crate::{bytecode::Opcode, chunk::Chunk, globals::Globals, value::Value};
+
+use super::plan::{Plan, Ty};
+
+/// Compiled code for one entry point. It takes the frame's slots as doubles,
+/// runs until it reaches something it can't do, writes the slots back and
+/// returns the index of the exit it took.
+pub type EntryFn = unsafe extern "C" fn(state: *mut f64, globals: *mut Globals) -> u32;
+
+/// Where compiled code hands back to the interpreter.
+#[derive(Debug)]
+pub struct Exit {
+    // offset of the instruction the interpreter runs next
+    pub resume: usize,
+    // the frame's slots at that point
+    pub types: Vec<Ty>,
+    // a global wasn't a number or wasn't defined, as opposed to an
+    // instruction compiled code never runs
+    pub guard: bool,
+}
+
+pub struct Compiled {
+    pub code: EntryFn,
+    pub entry_types: Vec<Ty>,
+    pub exits: Vec<Exit>,
+    pub max_height: usize,
+}
+
+// functions compiled code calls for what it can't inline
+struct Helpers {
+    get_global: FuncId,
+    set_global: FuncId,
+    define_global: FuncId,
+    fmod: FuncId,
+}
+
+pub struct Codegen {
+    module: JITModule,
+    ctx: Context,
+    builder_ctx: FunctionBuilderContext,
+    helpers: Helpers,
+}
+
+impl Codegen {
+    pub fn new() -> Result<Codegen, String> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").map_err(|err| err.to_string())?;
+        flags.set("use_colocated_libcalls", "false").map_err(|err| err.to_string())?;
+        flags.set("is_pic", "false").map_err(|err| err.to_string())?;
+        let isa = cranelift_native::builder()?
+            .finish(settings::Flags::new(flags))
+            .map_err(|err| err.to_string())?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("lockhart_get_global", get_global as *const u8);
+        builder.symbol("lockhart_set_global", set_global as *const u8);
+        builder.symbol("lockhart_define_global", define_global as *const u8);
+        builder.symbol("lockhart_fmod", fmod as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let ptr = module.target_config().pointer_type();
+        let mut declare = |name: &str, params: &[types::Type], ret: types::Type| {
+            let mut sig = module.make_signature();
+            sig.params.extend(params.iter().map(|ty| AbiParam::new(*ty)));
+            sig.returns.push(AbiParam::new(ret));
+            module.declare_function(name, Linkage::Import, &sig).map_err(|err| err.to_string())
+        };
+        let helpers = Helpers {
+            get_global: declare("lockhart_get_global", &[ptr, ptr, ptr], types::I32)?,
+            set_global: declare("lockhart_set_global", &[ptr, ptr, types::F64, types::I32], types::I32)?,
+            define_global: declare("lockhart_define_global", &[ptr, ptr, types::F64, types::I32], types::I32)?,
+            fmod: declare("lockhart_fmod", &[types::F64, types::F64], types::F64)?,
+        };
+        Ok(Codegen {
+            ctx: module.make_context(),
+            module,
+            builder_ctx: FunctionBuilderContext::new(),
+            helpers,
+        })
+    }
+
+    pub fn compile(&mut self, plan: &Plan, chunk: &Chunk) -> Result<Compiled, String> {
+        let Codegen { module, ctx, builder_ctx, helpers } = self;
+        let ptr = module.target_config().pointer_type();
+        let mut sig = Signature::new(module.isa().default_call_conv());
+        sig.params.push(AbiParam::new(ptr));
+        sig.params.push(AbiParam::new(ptr));
+        sig.returns.push(AbiParam::new(types::I32));
+        ctx.func.signature = sig.clone();
+
+        // one exit for every instruction compiled code stops at
+        let mut exits = Vec::new();
+        let mut exit_at = BTreeMap::new();
+        for (&offset, node) in &plan.nodes {
+            let guard = matches!(node.op, Opcode::OP_GET_GLOBAL(_) | Opcode::OP_SET_GLOBAL(_));
+            if node.exit || guard {
+                exit_at.insert(offset, exits.len());
+                exits.push(Exit { resume: offset, types: node.types.clone(), guard });
+            }
+        }
+
+        let mut b = FunctionBuilder::new(&mut ctx.func, builder_ctx);
+        let get_global = module.declare_func_in_func(helpers.get_global, b.func);
+        let set_global = module.declare_func_in_func(helpers.set_global, b.func);
+        let define_global = module.declare_func_in_func(helpers.define_global, b.func);
+        let fmod = module.declare_func_in_func(helpers.fmod, b.func);
+
+        let var = |slot: usize| Variable::from_u32(slot as u32);
+        for slot in 0..plan.max_height {
+            b.declare_var(var(slot), types::F64);
+        }
+        let start = b.create_block();
+        b.append_block_params_for_function_params(start);
+        let blocks: BTreeMap<usize, Block> = plan.nodes.keys().map(|&offset| (offset, b.create_block())).collect();
+        let exit_blocks: Vec<Block> = exits.iter().map(|_| b.create_block()).collect();
+
+        b.switch_to_block(start);
+        let state = b.block_params(start)[0];
+        let globals = b.block_params(start)[1];
+        let entry_height = plan.entry_types().len();
+        for slot in 0..plan.max_height {
+            let value = if slot < entry_height {
+                b.ins().load(types::F64, MemFlags::trusted(), state, (slot * 8) as i32)
+            } else {
+                b.ins().f64const(0.0)
+            };
+            b.def_var(var(slot), value);
+        }
+        b.ins().jump(blocks[&plan.entry], &[]);
+        // globals are read through here
+        let scratch = b.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8, 3));
+
+        for (&offset, node) in &plan.nodes {
+            b.switch_to_block(blocks[&offset]);
+            let height = node.types.len();
+            if node.exit {
+                b.ins().jump(exit_blocks[exit_at[&offset]], &[]);
+                continue;
+            }
+            // the value `n` slots below the top
+            macro_rules! top {
+                ($n: expr) => {
+                    b.use_var(var(height - 1 - $n))
+                };
+            }
+            match node.op {
+                Opcode::OP_CONSTANT(idx) => {
+                    let n = match chunk.constants[idx] {
+                        Value::NUMBER(n) => n,
+                        _ => unreachable!("plan only compiles number constants"),
+                    };
+                    let value = b.ins().f64const(n);
+                    b.def_var(var(height), value);
+                }
+                Opcode::OP_NEGATE => {
+                    let x = top!(0);
+                    let negated = b.ins().fneg(x);
+                    b.def_var(var(height - 1), negated);
+                }
+                Opcode::OP_ADD | Opcode::OP_SUBSTRACT | Opcode::OP_MULTIPLY | Opcode::OP_DIVIDE | Opcode::OP_MOD => {
+                    let (left, right) = (top!(1), top!(0));
+                    let result = match node.op {
+                        Opcode::OP_ADD => b.ins().fadd(left, right),
+                        Opcode::OP_SUBSTRACT => b.ins().fsub(left, right),
+                        Opcode::OP_MULTIPLY => b.ins().fmul(left, right),
+                        Opcode::OP_DIVIDE => b.ins().fdiv(left, right),
+                        _ => {
+                            let call = b.ins().call(fmod, &[left, right]);
+                            b.inst_results(call)[0]
+                        }
+                    };
+                    b.def_var(var(height - 2), result);
+                }
+                Opcode::OP_GT | Opcode::OP_LT | Opcode::OP_EQ => {
+                    let (left, right) = (top!(1), top!(0));
+                    let (left_ty, right_ty) = (node.types[height - 2], node.types[height - 1]);
+                    let result = if matches!(node.op, Opcode::OP_EQ) && left_ty != right_ty {
+                        // a number never equals a boolean
+                        b.ins().f64const(0.0)
+                    } else {
+                        let cc = match node.op {
+                            Opcode::OP_GT => FloatCC::GreaterThan,
+                            Opcode::OP_LT => FloatCC::LessThan,
+                            _ => FloatCC::Equal,
+                        };
+                        let cmp = b.ins().fcmp(cc, left, right);
+                        bool_value(&mut b, cmp)
+                    };
+                    b.def_var(var(height - 2), result);
+                }
+                Opcode::OP_TRUE | Opcode::OP_FALSE => {
+                    let value = b.ins().f64const(if matches!(node.op, Opcode::OP_TRUE) { 1.0 } else { 0.0 });
+                    b.def_var(var(height), value);
+                }
+                Opcode::OP_NOT => {
+                    let result = if node.types[height - 1] == Ty::Other {
+                        b.ins().f64const(0.0)
+                    } else {
+                        let x = top!(0);
+                        let zero = b.ins().f64const(0.0);
+                        let cmp = b.ins().fcmp(FloatCC::Equal, x, zero);
+                        bool_value(&mut b, cmp)
+                    };
+                    b.def_var(var(height - 1), result);
+                }
+                Opcode::OP_GET_LOCAL(slot) => {
+                    let value = b.use_var(var(slot));
+                    b.def_var(var(height), value);
+                }
+                Opcode::OP_SET_LOCAL(slot) => {
+                    let value = top!(0);
+                    b.def_var(var(slot), value);
+                }
+                Opcode::OP_GET_GLOBAL(slot) => {
+                    let slot = b.ins().iconst(ptr, slot as i64);
+                    let out = b.ins().stack_addr(ptr, scratch, 0);
+                    let call = b.ins().call(get_global, &[globals, slot, out]);
+                    let found = b.inst_results(call)[0];
+                    let ok = b.create_block();
+                    b.ins().brif(found, ok, &[], exit_blocks[exit_at[&offset]], &[]);
+                    b.switch_to_block(ok);
+                    let value = b.ins().stack_load(types::F64, scratch, 0);
+                    b.def_var(var(height), value);
+                }
+                Opcode::OP_SET_GLOBAL(slot) | Opcode::OP_DEFINE_GLOBAL(slot) => {
+                    let value = top!(0);
+                    let slot = b.ins().iconst(ptr, slot as i64);
+                    let is_bool = b.ins().iconst(types::I32, (node.types[height - 1] == Ty::Bool) as i64);
+                    let helper = if let Opcode::OP_SET_GLOBAL(_) = node.op { set_global } else { define_global };
+                    let call = b.ins().call(helper, &[globals, slot, value, is_bool]);
+                    if let Opcode::OP_SET_GLOBAL(_) = node.op {
+                        let assigned = b.inst_results(call)[0];
+                        let ok = b.create_block();
+                        b.ins().brif(assigned, ok, &[], exit_blocks[exit_at[&offset]], &[]);
+                        b.switch_to_block(ok);
+                    }
+                }
+                Opcode::OP_POP => {}
+                Opcode::OP_JUMP(jump) => {
+                    b.ins().jump(blocks[&(node.next + jump)], &[]);
+                    continue;
+                }
+                Opcode::OP_LOOP(jump) => {
+                    b.ins().jump(blocks[&(node.next - jump)], &[]);
+                    continue;
+                }
+                Opcode::OP_JUMP_IF_FALSE(jump) => {
+                    // zero and false are both falsey
+                    let x = top!(0);
+                    let zero = b.ins().f64const(0.0);
+                    let falsey = b.ins().fcmp(FloatCC::Equal, x, zero);
+                    b.ins().brif(falsey, blocks[&(node.next + jump)], &[], blocks[&node.next], &[]);
+                    continue;
+                }
+                other => unreachable!("plan doesn't compile {:?}", other),
+            }
+            b.ins().jump(blocks[&node.next], &[]);
+        }
+
+        for (exit, &block) in exits.iter().zip(&exit_blocks) {
+            b.switch_to_block(block);
+            for (slot, ty) in exit.types.iter().enumerate() {
+                if *ty != Ty::Other {
+                    let value = b.use_var(var(slot));
+                    b.ins().store(MemFlags::trusted(), value, state, (slot * 8) as i32);
+                }
+            }
+            let idx = b.ins().iconst(types::I32, exit_at[&exit.resume] as i64);
+            b.ins().return_(&[idx]);
+        }
+        b.seal_all_blocks();
+        b.finalize();
+
+        let id = module.declare_anonymous_function(&sig).map_err(|err| err.to_string())?;
+        let defined = module.define_function(id, ctx).map_err(|err| err.to_string());
+        module.clear_context(ctx);
+        defined?;
+        module.finalize_definitions().map_err(|err| err.to_string())?;
+        // SAFETY: the function was declared with this signature
+        let code = unsafe { std::mem::transmute::<*const u8, EntryFn>(module.get_finalized_function(id)) };
+        Ok(Compiled {
+            code,
+            entry_types: plan.entry_types().to_vec(),
+            exits,
+            max_height: plan.max_height,
+        })
+    }
+}
+
+// booleans are 1.0 and 0.0
+fn bool_value(b: &mut FunctionBuilder, cmp: cranelift_codegen::ir::Value) -> cranelift_codegen::ir::Value {
+    let one = b.ins().f64const(1.0);
+    let zero = b.ins().f64const(0.0);
+    b.ins().select(cmp, one, zero)
+}
+
+fn scalar(value: f64, is_bool: u32) -> Value {
+    if is_bool != 0 {
+        Value::BOOL(value != 0.0)
+    } else {
+        Value::NUMBER(value)
+    }
+}
+
+// 1 and the number in `out` if the global is defined and a number, 0 otherwise
+extern "C" fn get_global(globals: *mut Globals, slot: usize, out: *mut f64) -> u32 {
+    // SAFETY: compiled code passes the VM's globals and its own stack slot
+    unsafe {
+        match (*globals).get(slot) {
+            Some(Value::NUMBER(n)) => {
+                *out = *n;
+                1
+            }
+            _ => 0,
+        }
+    }
+}
+
+// 0 if the global isn't defined, leaving it to the interpreter to say so
+extern "C" fn set_global(globals: *mut Globals, slot: usize, value: f64, is_bool: u32) -> u32 {
+    // SAFETY: compiled code passes the VM's globals
+    unsafe { (*globals).assign(slot, scalar(value, is_bool)) as u32 }
+}
+
+extern "C" fn define_global(globals: *mut Globals, slot: usize, value: f64, is_bool: u32) -> u32 {
+    // SAFETY: compiled code passes the VM's globals
+    unsafe { (*globals).define(slot, scalar(value, is_bool)) };
+    1
+}
+
+extern "C" fn fmod(x: f64, y: f64) -> f64 {
+    x % y
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use crate::{bytecode::Opcode, chunk::Chunk, value::Value};
+
+/// What compiled code knows about a stack slot. Numbers and booleans live in
+/// registers as doubles, booleans as 0 or 1; anything else stays where it was
+/// in the VM's stack and compiled code never touches it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ty {
+    Number,
+    Bool,
+    Other,
+}
+
+impl Ty {
+    pub fn of(value: &Value) -> Ty {
+        match value {
+            Value::NUMBER(_) => Ty::Number,
+            Value::BOOL(_) => Ty::Bool,
+            _ => Ty::Other,
+        }
+    }
+}
+
+/// One instruction reachable from the entry point, with the types of the
+/// frame's slots just before it runs.
+#[derive(Debug)]
+pub struct Node {
+    pub op: Opcode,
+    // offset of the instruction after this one
+    pub next: usize,
+    pub types: Vec<Ty>,
+    // compiled code can't run this one: it returns to the interpreter instead
+    pub exit: bool,
+}
+
+/// The instructions to compile for one entry point, specialised for the
+/// types the frame's slots had when it got hot.
+#[derive(Debug)]
+pub struct Plan {
+    pub entry: usize,
+    pub nodes: BTreeMap<usize, Node>,
+    // most slots the frame uses at once
+    pub max_height: usize,
+}
+
+impl Plan {
+    pub fn entry_types(&self) -> &[Ty] {
+        &self.nodes[&self.entry].types
+    }
+}
+
+/// Follow `chunk` from `entry` with the frame's slots holding `types`, and
+/// work out the types before every instruction. Where control flow joins
+/// with different types the code can't be specialised, and the offset of
+/// the join is returned instead.
+pub fn plan(chunk: &Chunk, entry: usize, types: Vec<Ty>) -> Result<Plan, usize> {
+    let mut decoded = BTreeMap::new();
+    let mut instructions = chunk.instructions().peekable();
+    while let Some((offset, op)) = instructions.next() {
+        let next = instructions.peek().map_or(chunk.code.len(), |(next, _)| *next);
+        decoded.insert(offset, (op, next));
+    }
+
+    let mut max_height = types.len();
+    let mut states: BTreeMap<usize, Vec<Ty>> = BTreeMap::new();
+    let mut pending = vec![entry];
+    states.insert(entry, types);
+    let mut nodes = BTreeMap::new();
+    while let Some(offset) = pending.pop() {
+        let (op, next) = decoded[&offset];
+        let before = states[&offset].clone();
+        let (after, targets) = match step(op, next, &before, &chunk.constants) {
+            Some(step) => step,
+            None => {
+                nodes.insert(offset, Node { op, next, types: before, exit: true });
+                continue;
+            }
+        };
+        max_height = max_height.max(after.len());
+        for target in targets {
+            match states.get(&target) {
+                Some(known) if *known == after => {}
+                Some(_) => return Err(target),
+                None => {
+                    states.insert(target, after.clone());
+                    pending.push(target);
+                }
+            }
+        }
+        nodes.insert(offset, Node { op, next, types: before, exit: false });
+    }
+    Ok(Plan { entry, nodes, max_height })
+}
+
+/// The types after `op` and where control goes next, or None if compiled
+/// code has to hand `op` to the interpreter.
+fn step(op: Opcode, next: usize, before: &[Ty], constants: &[Value]) -> Option<(Vec<Ty>, Vec<usize>)> {
+    let mut types = before.to_vec();
+    let top = |n: usize| before.len().checked_sub(n + 1).map(|idx| before[idx]);
+    let scalar = |ty: Option<Ty>| matches!(ty, Some(Ty::Number | Ty::Bool));
+    match op {
+        Opcode::OP_CONSTANT(idx) if matches!(constants[idx], Value::NUMBER(_)) => types.push(Ty::Number),
+        Opcode::OP_NEGATE if top(0) == Some(Ty::Number) => {}
+        Opcode::OP_ADD | Opcode::OP_SUBSTRACT | Opcode::OP_MULTIPLY | Opcode::OP_DIVIDE | Opcode::OP_MOD
+            if top(0) == Some(Ty::Number) && top(1) == Some(Ty::Number) =>
+        {
+            types.pop();
+        }
+        Opcode::OP_GT | Opcode::OP_LT if top(0) == Some(Ty::Number) && top(1) == Some(Ty::Number) => {
+            types.pop();
+            types.pop();
+            types.push(Ty::Bool);
+        }
+        Opcode::OP_EQ if scalar(top(0)) && scalar(top(1)) => {
+            types.pop();
+            types.pop();
+            types.push(Ty::Bool);
+        }
+        Opcode::OP_TRUE | Opcode::OP_FALSE => types.push(Ty::Bool),
+        // anything that isn't a number or a boolean negates to false
+        Opcode::OP_NOT if top(0).is_some() => {
+            types.pop();
+            types.push(Ty::Bool);
+        }
+        Opcode::OP_GET_LOCAL(slot) if scalar(before.get(slot).copied()) => types.push(before[slot]),
+        Opcode::OP_SET_LOCAL(slot) if scalar(top(0)) && slot < before.len() => types[slot] = before[before.len() - 1],
+        // only numbers are read from globals; anything else fails a guard
+        Opcode::OP_GET_GLOBAL(_) => types.push(Ty::Number),
+        Opcode::OP_SET_GLOBAL(_) if scalar(top(0)) => {}
+        Opcode::OP_DEFINE_GLOBAL(_) if scalar(top(0)) => {
+            types.pop();
+        }
+        Opcode::OP_POP if top(0).is_some() => {
+            types.pop();
+        }
+        Opcode::OP_JUMP(jump) => return Some((types, vec![next + jump])),
+        Opcode::OP_LOOP(jump) => return Some((types, vec![next - jump])),
+        Opcode::OP_JUMP_IF_FALSE(jump) if scalar(top(0)) => return Some((types, vec![next, next + jump])),
+        _ => return None,
+    }
+    Some((types, vec![next]))
+}
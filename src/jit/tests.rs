@@ -0,0 +1,149 @@
+use crate::{
+    compiler::compile,
+    gc::{Gc, GcRef},
+    globals::Globals,
+    object::ObjFunction,
+    value::Value,
+    vm::{output::MemoryOutput, InterpretError, Vm},
+};
+
+use super::{
+    plan::{plan, Ty},
+    JitStats,
+};
+
+/// What the script printed, or its error, and what the JIT did along the way.
+fn run(source: &str, threshold: Option<u32>) -> (String, JitStats) {
+    let out = MemoryOutput::new();
+    let mut vm = Vm::builder().output(out.clone()).jit_threshold(threshold).build();
+    let printed = match vm.interpret(source.to_string()) {
+        Ok(()) => out.contents(),
+        Err(InterpretError::InterpretCompileError(msg)) => panic!("{}", msg),
+        Err(err) => format!("{}{}", out.contents(), err),
+    };
+    (printed, vm.jit_stats())
+}
+
+/// Run with every loop and call compiled straight away, and check the script
+/// does just what it does in the interpreter.
+fn same_as_interpreter(source: &str) -> JitStats {
+    let (interpreted, _) = run(source, None);
+    let (compiled, stats) = run(source, Some(1));
+    assert_eq!(compiled, interpreted, "for {}", source);
+    stats
+}
+
+fn function(script: GcRef<ObjFunction>, name: &str) -> GcRef<ObjFunction> {
+    script
+        .chunk
+        .constants
+        .iter()
+        .find_map(|constant| match constant {
+            Value::FUNCTION(function) if function.name.s == name => Some(*function),
+            _ => None,
+        })
+        .unwrap_or_else(|| panic!("no function {}", name))
+}
+
+#[test]
+fn numeric_global_loops_are_compiled() {
+    let stats = same_as_interpreter(
+        "let sum = 0; let i = 0;
+         while (i < 1000) { sum = sum + i * 2 - i / 4; i = i + 1; }
+         print sum; print -sum; print sum > 10 == true;",
+    );
+    assert_eq!(stats.compiled, 1);
+    assert!(stats.entered > 0);
+}
+
+#[test]
+fn functions_are_compiled_from_their_start_and_their_loops() {
+    let stats = same_as_interpreter(
+        "fn count(n) { let even = true; let i = 0; while (i < n) { even = !even; i = i + 1; } return even; }
+         let all = true;
+         for (let n = 0; n < 50; n = n + 1) { if (!count(n)) all = false; }
+         print all;",
+    );
+    // the script's loop, and `count` from its start: its own loop then runs
+    // in compiled code without ever reaching the interpreter
+    assert_eq!(stats.compiled, 2, "{:?}", stats);
+}
+
+#[test]
+fn printing_in_a_loop_goes_back_to_the_interpreter_each_time() {
+    let stats = same_as_interpreter("for (let i = 0; i < 5; i = i + 1) { print i * i; }");
+    assert!(stats.entered >= 5, "{:?}", stats);
+}
+
+#[test]
+fn globals_that_stop_being_numbers_fail_the_guard() {
+    same_as_interpreter(
+        "let x = 1; let n = 0;
+         for (let i = 0; i < 300; i = i + 1) { if (i == 150) x = \"x\"; if (x == 1) n = n + 1; }
+         print n; print x;",
+    );
+}
+
+#[test]
+fn runtime_errors_are_left_to_the_interpreter() {
+    same_as_interpreter("let i = 0; while (i < 10) { i = i + 1; } print nope;");
+    same_as_interpreter("let i = 0; while (i < 10) { i = i + 1; nope = i; }");
+    same_as_interpreter("let i = 0; while (i < 10) { i = i + true; }");
+}
+
+#[test]
+fn recursion_and_odd_numbers_match() {
+    same_as_interpreter("fn fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(15);");
+    same_as_interpreter("let i = 0; while (i < 3) { print 1 / 0; print -(0 / 1); i = i + 1; }");
+}
+
+#[test]
+fn locals_that_are_not_numbers_are_kept() {
+    same_as_interpreter(
+        "fn f() { let name = \"loop\"; let xs = [1, 2]; let i = 0; while (i < 20) { i = i + 1; } return name + \" \" + len(xs); }
+         for (let n = 0; n < 3; n = n + 1) print f();",
+    );
+}
+
+#[test]
+fn turning_the_jit_off_runs_nothing_compiled() {
+    let (_, stats) = run("let i = 0; while (i < 100) i = i + 1;", None);
+    assert_eq!(stats, JitStats::default());
+}
+
+#[test]
+fn plans_specialise_on_slot_types() {
+    let mut gc = Gc::new();
+    let mut globals = Globals::new();
+    let script = compile(
+        "fn f(n) { let i = 0; while (i < n) { print i; i = i + 1; } return i; }".to_string(),
+        &mut gc,
+        &mut globals,
+    )
+    .unwrap();
+    let f = function(script, "f");
+    let numbers = plan(&f.chunk, 0, vec![Ty::Other, Ty::Number]).unwrap();
+    assert_eq!(numbers.entry_types(), [Ty::Other, Ty::Number]);
+    let exits: Vec<String> = numbers.nodes.values().filter(|node| node.exit).map(|node| format!("{:?}", node.op)).collect();
+    assert_eq!(exits, ["OP_PRINT", "OP_RETURN"]);
+    assert!(numbers.nodes.values().all(|node| node.types.len() < 3 || node.types[2] == Ty::Number));
+
+    // compiled code can't compare a string argument with a number
+    let other = plan(&f.chunk, 0, vec![Ty::Other, Ty::Other]).unwrap();
+    let first_exit = other.nodes.values().find(|node| node.exit).unwrap();
+    assert_eq!(format!("{:?}", first_exit.op), "OP_GET_LOCAL(1)");
+}
+
+#[test]
+fn joins_with_different_types_are_rejected() {
+    let mut gc = Gc::new();
+    let mut globals = Globals::new();
+    let script = compile(
+        "fn f(n) { let x = 1; if (n > 0) { x = true; } return x; }".to_string(),
+        &mut gc,
+        &mut globals,
+    )
+    .unwrap();
+    let f = function(script, "f");
+    assert!(plan(&f.chunk, 0, vec![Ty::Other, Ty::Number]).is_err());
+}
@@ -23,15 +23,20 @@ impl Lexer {
         l
     }
 
+    pub fn source(&self) -> &str {
+        &self.input
+    }
+
     fn read_char(&mut self) {
+        // lineno is the line of the current char, so a newline only counts once we move past it
+        if self.ch as char == '\n' {
+            self.lineno += 1;
+        }
         if self.read_position >= self.input.len() {
             self.ch = 0;
         } else {
             let inp = self.input.as_bytes();
             self.ch = inp[self.read_position];
-            if self.ch as char == '\n' {
-                self.lineno += 1;
-            }
         }
 
         self.position = self.read_position;
@@ -46,6 +51,11 @@ impl Lexer {
         ch.is_ascii_digit()
     }
 
+    // identifiers start with a letter but may go on with digits, like `log10`
+    fn is_identifier(ch: u8) -> bool {
+        Lexer::is_letter(ch) || Lexer::is_number(ch)
+    }
+
     fn peek_ahead(&self) -> Option<u8> {
         if self.read_position >= self.input.len() {
             return None;
@@ -55,9 +65,6 @@ impl Lexer {
 
     fn skip_whitespace(&mut self) {
         while self.ch.is_ascii_whitespace() {
-            if self.ch == '\n' as u8 {
-                self.lineno += 1;
-            }
             self.read_char();
         }
     }
@@ -73,16 +80,44 @@ impl Lexer {
         self.input[position..self.position].to_string()
     }
 
-    fn read_literal(&mut self) -> String {
+    /// reads a string literal, or returns None if the input ends before the closing quote
+    fn read_literal(&mut self) -> Option<String> {
         self.read_char();
         let position = self.position;
         while self.ch as char != '\"' {
+            if self.at_end() {
+                return None;
+            }
             self.read_char();
         }
-        self.input[position..self.position].to_string()
+        Some(self.input[position..self.position].to_string())
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.input.len()
     }
+
+    /// The token `next_token` would return, skipping comments, without
+    /// consuming it.
+    pub fn peek_token(&mut self) -> Token {
+        let saved = (self.position, self.read_position, self.ch, self.lineno);
+        let mut token = self.next_token();
+        while token.type_ == TokenType::COMMENT {
+            token = self.next_token();
+        }
+        (self.position, self.read_position, self.ch, self.lineno) = saved;
+        token
+    }
+
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
+        let start = self.position.min(self.input.len());
+        let mut token = self.scan_token();
+        token.offset = start;
+        token
+    }
+
+    fn scan_token(&mut self) -> Token {
         if self.read_position > self.input.len() {
             return Token::new(TokenType::EOF, "".to_string(), self.lineno)
         }
@@ -117,9 +152,12 @@ impl Lexer {
                 TokenType::DIV => {
                     // Check for comment
                     if self.peek_ahead() == Some('/' as u8) {
-                        while self.ch != '\n' as u8 {
+                        let position = self.position;
+                        while self.ch != '\n' as u8 && !self.at_end() {
                             self.read_char();
                         }
+                        let comment = self.input[position..self.position].to_string();
+                        token = Token::new(TokenType::COMMENT, comment, self.lineno);
                     } else {
                         token = Token::new(tok.clone(), current_char, self.lineno);
                     }
@@ -130,25 +168,42 @@ impl Lexer {
             }
         } else if current_char == "\"" {
             // string literal
-            let str = Lexer::read_literal(self);
-            token = Token::new(TokenType::STRING, str, self.lineno)
+            token = match Lexer::read_literal(self) {
+                Some(str) => Token::new(TokenType::STRING, str, self.lineno),
+                None => {
+                    let msg = "Unterminated string".to_string();
+                    return Token::new(TokenType::ILLEGAL, msg, self.lineno);
+                }
+            }
         } else if let Some(tok) = token::DELIMITERS.get(&current_char) {
             // delimiter
             token = Token::new(tok.clone(), current_char, self.lineno);
         } else {
             if Lexer::is_letter(self.ch) {
                 // identifier
-                let literal = Lexer::read_identifier(self, Lexer::is_letter);
+                let literal = Lexer::read_identifier(self, Lexer::is_identifier);
                 let tok = Token::check_keyword(&literal);
                 token = Token::new(tok, literal, self.lineno);
                 return token;
             } else if Lexer::is_number(self.ch) {
                 // number literal
-                let literal = Lexer::read_identifier(self, Lexer::is_number);
+                let mut literal = Lexer::read_identifier(self, Lexer::is_number);
+                // a fraction needs digits after the dot
+                if self.ch == b'.' && self.peek_ahead().is_some_and(Lexer::is_number) {
+                    self.read_char();
+                    literal.push('.');
+                    literal.push_str(&Lexer::read_identifier(self, Lexer::is_number));
+                }
                 token = Token::new(TokenType::NUM, literal, self.lineno);
                 return token;
             } else {
-                panic!("illegal identifier");
+                // decode the whole character so one token covers all of its bytes
+                let ch = self.input[self.position..].chars().next().unwrap_or('\0');
+                for _ in 1..ch.len_utf8() {
+                    self.read_char();
+                }
+                let msg = format!("Unexpected character '{}'", ch);
+                token = Token::new(TokenType::ILLEGAL, msg, self.lineno);
             }
         }
 
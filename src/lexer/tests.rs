@@ -12,24 +12,28 @@ fn test_token_next() {
         type_: super::token::TokenType::FUNCTION,
         literal: "fn".to_string(),
         lineno: 1,
+        offset: 0,
     };
     let rhs1 = lexer.next_token();
     let lhs1 = super::token::Token {
         type_: super::token::TokenType::IDENT,
         literal: "x".to_string(),
         lineno: 1,
+        offset: 3,
     };
     let rhs2 = lexer.next_token();
     let lhs2 = super::token::Token {
         type_: super::token::TokenType::ASSIGN,
         literal: "=".to_string(),
         lineno: 1,
+        offset: 5,
     };
     let rhs3 = lexer.next_token();
     let lhs3 = super::token::Token {
         type_: super::token::TokenType::NUM,
         literal: "10".to_string(),
         lineno: 1,
+        offset: 7,
     };
 
     assert_eq!(lhs, rhs);
@@ -48,6 +52,7 @@ fn test_comments() {
         type_: TokenType::NUM,
         literal: "10".to_string(),
         lineno: 2,
+        offset: 5,
     };
 
     assert_eq!(lhs, rhs);
@@ -59,3 +64,63 @@ fn test_error() {
     let rh1 = lexer.next_token();
     println!("{}", rh1.literal);
 }
+
+#[test]
+fn test_line_numbers() {
+    let input = "let x = 1;\n\nprint x\n;".to_string();
+    let lines: Vec<usize> = Lexer::new(input).map(|t| t.lineno).collect();
+    assert_eq!(lines, vec![1, 1, 1, 1, 1, 3, 3, 4]);
+}
+
+#[test]
+fn test_comment_token_and_offsets() {
+    let input = "x // note\n\"s\"".to_string();
+    let tokens: Vec<Token> = Lexer::new(input).collect();
+    assert_eq!(tokens[1].type_, TokenType::COMMENT);
+    assert_eq!(tokens[1].literal, "// note");
+    assert_eq!(tokens[1].offset, 2);
+    assert_eq!(tokens[2].type_, TokenType::STRING);
+    assert_eq!(tokens[2].offset, 10);
+    assert_eq!(tokens[2].lineno, 2);
+}
+
+#[test]
+fn test_lexical_errors_are_illegal_tokens() {
+    let mut lexer = Lexer::new("@ \"open".to_string());
+    let bad_char = lexer.next_token();
+    assert_eq!(bad_char.type_, TokenType::ILLEGAL);
+    assert_eq!(bad_char.literal, "Unexpected character '@'");
+    let unterminated = lexer.next_token();
+    assert_eq!(unterminated.type_, TokenType::ILLEGAL);
+    assert_eq!(unterminated.literal, "Unterminated string");
+    assert_eq!(lexer.next_token().type_, TokenType::EOF);
+}
+
+#[test]
+fn test_non_ascii_characters_are_one_illegal_token() {
+    let mut lexer = Lexer::new("é = 2;".to_string());
+    let bad_char = lexer.next_token();
+    assert_eq!(bad_char.type_, TokenType::ILLEGAL);
+    assert_eq!(bad_char.literal, "Unexpected character 'é'");
+    let assign = lexer.next_token();
+    assert_eq!(assign.type_, TokenType::ASSIGN);
+    assert_eq!(assign.offset, 3);
+}
+
+#[test]
+fn test_decimals_and_digits_in_identifiers() {
+    let mut lexer = Lexer::new("log10(2.5) 3 x1".to_string());
+    let expected = [
+        (TokenType::IDENT, "log10"),
+        (TokenType::LPAREN, "("),
+        (TokenType::NUM, "2.5"),
+        (TokenType::RPAREN, ")"),
+        (TokenType::NUM, "3"),
+        (TokenType::IDENT, "x1"),
+        (TokenType::EOF, ""),
+    ];
+    for (type_, literal) in expected {
+        let token = lexer.next_token();
+        assert_eq!((token.type_, token.literal.as_str()), (type_, literal));
+    }
+}
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, io, path::Path};
+
+use crate::{
+    compiler::{
+        analysis::{Analysis, CompileError, SymbolKind},
+        analyze,
+    },
+    gc::Gc,
+    source::{open_source_file, unreadable},
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UnusedLocal,
+    UndeclaredAssignment,
+    ShadowedVariable,
+    UnreachableCode,
+    WrongArity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::UnusedLocal,
+        Rule::UndeclaredAssignment,
+        Rule::ShadowedVariable,
+        Rule::UnreachableCode,
+        Rule::WrongArity,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedLocal => "unused-local",
+            Rule::UndeclaredAssignment => "undeclared-assignment",
+            Rule::ShadowedVariable => "shadowed-variable",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::WrongArity => "wrong-arity",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub rule: Rule,
+    pub level: Level,
+    pub line: usize,
+    pub offset: usize,
+    pub message: String,
+}
+
+/// Which rules run and how loudly. Every rule warns unless configured otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    levels: HashMap<Rule, Level>,
+}
+
+impl Config {
+    pub fn set(&mut self, rule: Rule, level: Level) {
+        self.levels.insert(rule, level);
+    }
+
+    pub fn level(&self, rule: Rule) -> Level {
+        self.levels.get(&rule).copied().unwrap_or(Level::Warn)
+    }
+}
+
+/// Run every enabled rule over `source`. Sources that don't compile are
+/// returned as errors since the rules rely on a complete parse.
+pub fn lint(source: &str, config: &Config) -> Result<Vec<Lint>, Vec<CompileError>> {
+    let analysis = analyze(source.to_string(), &mut Gc::new());
+    if !analysis.errors.is_empty() {
+        return Err(analysis.errors);
+    }
+
+    let mut found = Vec::new();
+    unused_locals(&analysis, &mut found);
+    undeclared_assignments(&analysis, &mut found);
+    shadowed_variables(&analysis, &mut found);
+    unreachable_code(&analysis, &mut found);
+    wrong_arity(&analysis, &mut found);
+
+    let mut lints: Vec<Lint> = found
+        .into_iter()
+        .filter_map(|(rule, offset, message)| {
+            let level = config.level(rule);
+            if level == Level::Allow {
+                return None;
+            }
+            let line = line_of(source, offset);
+            Some(Lint { rule, level, line, offset, message })
+        })
+        .collect();
+    lints.sort_by_key(|lint| lint.offset);
+    Ok(lints)
+}
+
+type Found = Vec<(Rule, usize, String)>;
+
+fn unused_locals(analysis: &Analysis, found: &mut Found) {
+    for (idx, symbol) in analysis.symbols.iter().enumerate() {
+        let local = symbol.kind == SymbolKind::Local
+            || (symbol.kind == SymbolKind::Function && symbol.depth > 0);
+        if !local || symbol.name.starts_with('_') {
+            continue;
+        }
+        let read = analysis
+            .references
+            .iter()
+            .any(|r| r.symbol == Some(idx) && !r.assign);
+        if !read {
+            let msg = format!("local '{}' is never read", symbol.name);
+            found.push((Rule::UnusedLocal, symbol.offset, msg));
+        }
+    }
+}
+
+fn undeclared_assignments(analysis: &Analysis, found: &mut Found) {
+    for reference in analysis.references.iter().filter(|r| r.assign && r.symbol.is_none()) {
+        let msg = format!("assignment to undeclared variable '{}'", reference.name);
+        found.push((Rule::UndeclaredAssignment, reference.offset, msg));
+    }
+}
+
+fn shadowed_variables(analysis: &Analysis, found: &mut Found) {
+    for symbol in &analysis.symbols {
+        if let Some(outer) = symbol.shadows {
+            let scope = if analysis.symbols[outer].depth == 0 { "global" } else { "outer" };
+            let msg = format!("'{}' shadows {} variable of the same name", symbol.name, scope);
+            found.push((Rule::ShadowedVariable, symbol.offset, msg));
+        }
+    }
+}
+
+fn unreachable_code(analysis: &Analysis, found: &mut Found) {
+    for offset in &analysis.unreachable {
+        found.push((Rule::UnreachableCode, *offset, "unreachable code after return".to_string()));
+    }
+}
+
+fn wrong_arity(analysis: &Analysis, found: &mut Found) {
+    for call in &analysis.calls {
+        let reference = &analysis.references[call.reference];
+        let idx = match reference.symbol {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let function = &analysis.symbols[idx];
+        // a function name that gets reassigned could be anything at runtime
+        let reassigned = analysis.references.iter().any(|r| r.symbol == Some(idx) && r.assign);
+        if function.kind != SymbolKind::Function || reassigned {
+            continue;
+        }
+        if function.arity() != call.args as usize {
+            let msg = format!(
+                "'{}' expects {} arguments but is called with {}",
+                function.name,
+                function.arity(),
+                call.args
+            );
+            found.push((Rule::WrongArity, reference.offset, msg));
+        }
+    }
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    let end = offset.min(source.len());
+    source.as_bytes()[..end].iter().filter(|b| **b == b'\n').count() + 1
+}
+
+/// `lockhart lint [--allow|--warn|--deny <rule>]... <files>`; returns false if
+/// any file fails to compile or trips a denied rule.
+pub fn run(args: &[String]) -> io::Result<bool> {
+    let mut config = Config::default();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let level = match arg.as_str() {
+            "--allow" => Level::Allow,
+            "--warn" => Level::Warn,
+            "--deny" => Level::Deny,
+            _ => {
+                files.push(arg);
+                continue;
+            }
+        };
+        let name = args.next().map(|s| s.as_str()).unwrap_or("");
+        if name == "all" {
+            for rule in Rule::ALL {
+                config.set(rule, level);
+            }
+        } else if let Some(rule) = Rule::from_name(name) {
+            config.set(rule, level);
+        } else {
+            eprintln!("unknown lint rule '{}'", name);
+            return Ok(false);
+        }
+    }
+
+    let mut ok = true;
+    for file in files {
+        let source = open_source_file(file).map_err(|err| unreadable(Path::new(file), err))?;
+        match lint(&source, &config) {
+            Ok(lints) => {
+                for lint in lints {
+                    let level = if lint.level == Level::Deny { "error" } else { "warning" };
+                    println!("{}:{}: {}[{}]: {}", file, lint.line, level, lint.rule.name(), lint.message);
+                    ok &= lint.level != Level::Deny;
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}: {}", file, error);
+                }
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
@@ -0,0 +1,87 @@
+use super::{lint, Config, Level, Lint, Rule};
+
+fn lints(source: &str) -> Vec<Lint> {
+    lint(source, &Config::default()).unwrap()
+}
+
+fn rules(source: &str) -> Vec<(Rule, usize)> {
+    lints(source).iter().map(|l| (l.rule, l.line)).collect()
+}
+
+#[test]
+fn clean_source_has_no_lints() {
+    let source = "let total = 0;\nfn add(a, b) {\n  let sum = a + b;\n  return sum;\n}\ntotal = add(1, 2);\n";
+    assert!(lints(source).is_empty(), "{:?}", lints(source));
+}
+
+#[test]
+fn unused_local() {
+    let source = "fn f() {\n  let used = 1;\n  let unused = 2;\n  let _ignored = 3;\n  unused = used;\n}\n";
+    let found = lints(source);
+    assert_eq!(rules(source), vec![(Rule::UnusedLocal, 3)]);
+    assert_eq!(found[0].message, "local 'unused' is never read");
+}
+
+#[test]
+fn undeclared_assignment() {
+    let source = "let a = 1;\na = 2;\nb = 3;\nfn f() { c = 4; }\n";
+    assert_eq!(
+        rules(source),
+        vec![(Rule::UndeclaredAssignment, 3), (Rule::UndeclaredAssignment, 4)]
+    );
+}
+
+#[test]
+fn assignment_to_global_declared_later_is_fine() {
+    assert!(lints("fn f() { g = 1; }\nlet g = 0;\n").is_empty());
+}
+
+#[test]
+fn shadowed_variables() {
+    let source = "let x = 1;\n{\n  let x = 2;\n  {\n    let y = x;\n    let x = y;\n    print x;\n  }\n}\n";
+    let found = lints(source);
+    assert_eq!(
+        rules(source),
+        vec![(Rule::ShadowedVariable, 3), (Rule::ShadowedVariable, 6)]
+    );
+    assert_eq!(found[0].message, "'x' shadows global variable of the same name");
+    assert_eq!(found[1].message, "'x' shadows outer variable of the same name");
+}
+
+#[test]
+fn unreachable_after_return() {
+    let source = "fn f() {\n  return 1;\n  print 2;\n  print 3;\n}\nfn g(x) {\n  if (x) { return 1; }\n  return 2;\n}\n";
+    assert_eq!(rules(source), vec![(Rule::UnreachableCode, 3)]);
+}
+
+#[test]
+fn wrong_arity_for_known_functions() {
+    let source = "fn one(a) { return a; }\nprint one(1);\nprint one(1, 2);\nprint one();\nprint undefined(1);\n";
+    let found = lints(source);
+    assert_eq!(rules(source), vec![(Rule::WrongArity, 3), (Rule::WrongArity, 4)]);
+    assert_eq!(found[0].message, "'one' expects 1 arguments but is called with 2");
+}
+
+#[test]
+fn wrong_arity_skips_reassigned_functions_and_indirect_calls() {
+    let source = "fn one(a) { return a; }\nfn two(a, b) { return a; }\nlet f = one;\nf = two;\nprint f(1, 2);\nprint one(1)(2);\n";
+    assert!(lints(source).is_empty(), "{:?}", lints(source));
+}
+
+#[test]
+fn rules_are_configurable() {
+    let source = "fn f() { let x = 1; return 0; print 2; }\n";
+    let mut config = Config::default();
+    config.set(Rule::UnusedLocal, Level::Allow);
+    config.set(Rule::UnreachableCode, Level::Deny);
+    let found = lint(source, &config).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].rule, Rule::UnreachableCode);
+    assert_eq!(found[0].level, Level::Deny);
+}
+
+#[test]
+fn compile_errors_are_returned() {
+    let errors = lint("let = 1;", &Config::default()).unwrap_err();
+    assert_eq!(errors[0].message, "Expected variable name");
+}
@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    compiler::{
+        analysis::{Analysis, Symbol, SymbolKind},
+        analyze,
+    },
+    gc::Gc,
+    protocol::{read_message, write_message},
+    token::KEYWORDS,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Language server speaking LSP over any reader/writer pair. Documents are
+/// re-analyzed with the real parser on every request, which is cheap at the
+/// sizes scripts are written in.
+pub struct Server<R, W> {
+    reader: R,
+    writer: W,
+    documents: HashMap<String, String>,
+}
+
+// LSP enum values
+const SEVERITY_ERROR: u64 = 1;
+const SYMBOL_FUNCTION: u64 = 12;
+const COMPLETION_FUNCTION: u64 = 3;
+const COMPLETION_VARIABLE: u64 = 6;
+const COMPLETION_KEYWORD: u64 = 14;
+const SYNC_FULL: u64 = 1;
+
+pub fn start() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut server = Server::new(stdin.lock(), stdout.lock());
+    server.serve()
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    pub fn new(reader: R, writer: W) -> Server<R, W> {
+        Server {
+            reader,
+            writer,
+            documents: HashMap::new(),
+        }
+    }
+
+    /// handle messages until `exit` or the stream closes
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(message) = read_message(&mut self.reader)? {
+            let method = message["method"].as_str().unwrap_or("");
+            let params = &message["params"];
+            match method {
+                "initialize" => {
+                    let capabilities = json!({
+                        "capabilities": {
+                            "textDocumentSync": {
+                                "openClose": true,
+                                "change": SYNC_FULL,
+                                "save": { "includeText": true },
+                            },
+                            "definitionProvider": true,
+                            "hoverProvider": true,
+                            "documentSymbolProvider": true,
+                            "completionProvider": {},
+                        },
+                        "serverInfo": { "name": "lockhart" },
+                    });
+                    self.respond(&message, capabilities)?;
+                }
+                "initialized" => {}
+                "shutdown" => self.respond(&message, Json::Null)?,
+                "exit" => break,
+                "textDocument/didOpen" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                    let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                    self.documents.insert(uri.to_string(), text.to_string());
+                    self.publish_diagnostics(uri)?;
+                }
+                "textDocument/didChange" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                    // full sync: the last change holds the whole document
+                    let changes = params["contentChanges"].as_array();
+                    if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                        self.documents.insert(uri.to_string(), text.to_string());
+                    }
+                }
+                "textDocument/didSave" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                    if let Some(text) = params["text"].as_str() {
+                        self.documents.insert(uri.to_string(), text.to_string());
+                    }
+                    self.publish_diagnostics(uri)?;
+                }
+                "textDocument/didClose" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                    self.documents.remove(uri);
+                    let body = json!({ "uri": uri, "diagnostics": [] });
+                    self.notify("textDocument/publishDiagnostics", body)?;
+                }
+                "textDocument/definition" => {
+                    let result = self.definition(params);
+                    self.respond(&message, result)?;
+                }
+                "textDocument/hover" => {
+                    let result = self.hover(params);
+                    self.respond(&message, result)?;
+                }
+                "textDocument/documentSymbol" => {
+                    let result = self.document_symbols(params);
+                    self.respond(&message, result)?;
+                }
+                "textDocument/completion" => {
+                    let result = self.completion(params);
+                    self.respond(&message, result)?;
+                }
+                _ => {
+                    // requests carry an id and need an answer, notifications don't
+                    if !message["id"].is_null() {
+                        let msg = format!("Unsupported method '{}'", method);
+                        self.respond_err(&message, -32601, &msg)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the document text, its analysis and the byte offset of the request position
+    fn locate(&self, params: &Json) -> Option<(&str, Analysis, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let text = self.documents.get(uri)?;
+        let position = &params["position"];
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let offset = position_to_offset(text, line, character);
+        Some((text, analyze_text(text), offset))
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let diagnostics: Vec<Json> = analyze_text(text)
+            .errors
+            .iter()
+            .map(|error| {
+                json!({
+                    "range": range(text, error.offset, error.len),
+                    "severity": SEVERITY_ERROR,
+                    "source": "lockhart",
+                    "message": error.message,
+                })
+            })
+            .collect();
+        let body = json!({ "uri": uri, "diagnostics": diagnostics });
+        self.notify("textDocument/publishDiagnostics", body)
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let uri = &params["textDocument"]["uri"];
+        match self.locate(params) {
+            Some((text, analysis, offset)) => match analysis.lookup(offset) {
+                Some(symbol) => json!({
+                    "uri": uri,
+                    "range": range(text, symbol.offset, symbol.name.len()),
+                }),
+                None => Json::Null,
+            },
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        match self.locate(params) {
+            Some((text, analysis, offset)) => match analysis.lookup(offset) {
+                Some(symbol) => json!({
+                    "contents": { "kind": "markdown", "value": describe(symbol) },
+                    "range": range(text, symbol_use_start(&analysis, offset, symbol), symbol.name.len()),
+                }),
+                None => Json::Null,
+            },
+            None => Json::Null,
+        }
+    }
+
+    fn document_symbols(&self, params: &Json) -> Json {
+        let uri = &params["textDocument"]["uri"];
+        let text = match uri.as_str().and_then(|uri| self.documents.get(uri)) {
+            Some(text) => text,
+            None => return Json::Null,
+        };
+        let symbols: Vec<Json> = analyze_text(text)
+            .functions()
+            .map(|function| {
+                json!({
+                    "name": function.name,
+                    "kind": SYMBOL_FUNCTION,
+                    "location": {
+                        "uri": uri,
+                        "range": range(text, function.offset, function.name.len()),
+                    },
+                })
+            })
+            .collect();
+        json!(symbols)
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let mut items: Vec<Json> = KEYWORDS
+            .keys()
+            .map(|keyword| json!({ "label": keyword, "kind": COMPLETION_KEYWORD }))
+            .collect();
+        if let Some((_, analysis, offset)) = self.locate(params) {
+            let mut seen = Vec::new();
+            // innermost declarations first so shadowing names aren't listed twice
+            for symbol in analysis.symbols.iter().rev() {
+                if !symbol.is_visible_at(offset) || seen.contains(&&symbol.name) {
+                    continue;
+                }
+                seen.push(&symbol.name);
+                let kind = match symbol.kind {
+                    SymbolKind::Function => COMPLETION_FUNCTION,
+                    _ => COMPLETION_VARIABLE,
+                };
+                items.push(json!({ "label": symbol.name, "kind": kind, "detail": describe(symbol) }));
+            }
+        }
+        json!(items)
+    }
+
+    fn respond(&mut self, request: &Json, result: Json) -> io::Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+        write_message(&mut self.writer, &message)
+    }
+
+    fn respond_err(&mut self, request: &Json, code: i64, message: &str) -> io::Result<()> {
+        let error = json!({ "code": code, "message": message });
+        let message = json!({ "jsonrpc": "2.0", "id": request["id"], "error": error });
+        write_message(&mut self.writer, &message)
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> io::Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&mut self.writer, &message)
+    }
+}
+
+fn analyze_text(text: &str) -> Analysis {
+    let mut gc = Gc::new();
+    analyze(text.to_string(), &mut gc)
+}
+
+fn describe(symbol: &Symbol) -> String {
+    match symbol.kind {
+        SymbolKind::Function => format!(
+            "```lockhart\nfn {}({})\n```\narity: {}",
+            symbol.name,
+            symbol.params.join(", "),
+            symbol.arity()
+        ),
+        SymbolKind::Global => format!("```lockhart\nlet {}\n```\nglobal variable", symbol.name),
+        SymbolKind::Local => format!("```lockhart\nlet {}\n```\nlocal variable", symbol.name),
+        SymbolKind::Parameter => format!("```lockhart\n{}\n```\nparameter", symbol.name),
+    }
+}
+
+// start of the identifier under the cursor, which is either the declaration or a use of it
+fn symbol_use_start(analysis: &Analysis, offset: usize, symbol: &Symbol) -> usize {
+    analysis
+        .references
+        .iter()
+        .find(|r| r.name == symbol.name && r.offset <= offset && offset <= r.offset + r.name.len())
+        .map_or(symbol.offset, |r| r.offset)
+}
+
+fn range(text: &str, offset: usize, len: usize) -> Json {
+    json!({
+        "start": offset_to_position(text, offset),
+        "end": offset_to_position(text, offset + len),
+    })
+}
+
+/// LSP positions count UTF-16 code units within a line
+fn offset_to_position(text: &str, offset: usize) -> Json {
+    let mut line = 0;
+    let mut character = 0;
+    for (idx, ch) in text.char_indices() {
+        if idx >= offset {
+            break;
+        }
+        if ch == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += ch.len_utf16();
+        }
+    }
+    json!({ "line": line, "character": character })
+}
+
+fn position_to_offset(text: &str, line: usize, character: usize) -> usize {
+    let mut current_line = 0;
+    let mut current_character = 0;
+    for (idx, ch) in text.char_indices() {
+        if current_line == line && current_character >= character {
+            return idx;
+        }
+        if ch == '\n' {
+            if current_line == line {
+                return idx;
+            }
+            current_line += 1;
+            current_character = 0;
+        } else {
+            current_character += ch.len_utf16();
+        }
+    }
+    text.len()
+}
@@ -0,0 +1,198 @@
+use serde_json::{json, Value as Json};
+
+use super::{offset_to_position, position_to_offset, Server};
+
+const URI: &str = "file:///test.lh";
+
+fn exchange(messages: &[Json]) -> Vec<Json> {
+    let mut input = String::new();
+    for message in messages {
+        let mut message = message.clone();
+        message["jsonrpc"] = json!("2.0");
+        let body = message.to_string();
+        input.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    }
+    let mut output = Vec::new();
+    Server::new(input.as_bytes(), &mut output).serve().unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    output
+        .split("Content-Length: ")
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| serde_json::from_str(chunk.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect()
+}
+
+fn open(text: &str) -> Json {
+    json!({
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": { "uri": URI, "languageId": "lockhart", "version": 1, "text": text } },
+    })
+}
+
+fn request(id: u64, method: &str, line: u64, character: u64) -> Json {
+    json!({
+        "id": id,
+        "method": method,
+        "params": {
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        },
+    })
+}
+
+fn result(messages: &[Json], id: u64) -> &Json {
+    &messages.iter().find(|m| m["id"] == id).unwrap()["result"]
+}
+
+fn diagnostics(messages: &[Json]) -> Vec<&Json> {
+    messages
+        .iter()
+        .filter(|m| m["method"] == "textDocument/publishDiagnostics")
+        .map(|m| &m["params"]["diagnostics"])
+        .collect()
+}
+
+#[test]
+fn initialize_advertises_capabilities() {
+    let messages = exchange(&[
+        json!({ "id": 1, "method": "initialize", "params": {} }),
+        json!({ "id": 2, "method": "shutdown" }),
+        json!({ "method": "exit" }),
+    ]);
+    let capabilities = &result(&messages, 1)["capabilities"];
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(capabilities["textDocumentSync"]["save"]["includeText"], true);
+    assert_eq!(result(&messages, 2), &Json::Null);
+}
+
+#[test]
+fn diagnostics_published_on_open_and_save() {
+    let messages = exchange(&[
+        open("let x = ;\nprint x;\n"),
+        json!({
+            "method": "textDocument/didSave",
+            "params": { "textDocument": { "uri": URI }, "text": "let x = 1;\nprint x;\n" },
+        }),
+    ]);
+    let published = diagnostics(&messages);
+    assert_eq!(published.len(), 2);
+    assert_eq!(published[0][0]["message"], "Expected expression");
+    assert_eq!(published[0][0]["range"]["start"], json!({ "line": 0, "character": 8 }));
+    assert_eq!(published[0][0]["range"]["end"], json!({ "line": 0, "character": 9 }));
+    assert_eq!(published[1], &json!([]));
+}
+
+#[test]
+fn diagnostics_report_every_statement_with_errors() {
+    let messages = exchange(&[open("let = 1;\nprint 2;\nreturn 3;\n")]);
+    let published = diagnostics(&messages);
+    let messages: Vec<&Json> = published[0].as_array().unwrap().iter().map(|d| &d["message"]).collect();
+    assert_eq!(messages, vec!["Expected variable name", "Cannot return from top-level code"]);
+}
+
+#[test]
+fn definition_of_globals_and_locals() {
+    let text = "let g = 1;\nfn f(a) {\n  let b = a + g;\n  return b;\n}\nf(g);\n";
+    let messages = exchange(&[
+        open(text),
+        // `g` inside f
+        request(1, "textDocument/definition", 2, 14),
+        // `a` inside f
+        request(2, "textDocument/definition", 2, 10),
+        // `b` in return
+        request(3, "textDocument/definition", 3, 9),
+        // `f` in the call
+        request(4, "textDocument/definition", 5, 0),
+        // keyword, no definition
+        request(5, "textDocument/definition", 0, 1),
+    ]);
+    assert_eq!(result(&messages, 1)["range"]["start"], json!({ "line": 0, "character": 4 }));
+    assert_eq!(result(&messages, 2)["range"]["start"], json!({ "line": 1, "character": 5 }));
+    assert_eq!(result(&messages, 3)["range"]["start"], json!({ "line": 2, "character": 6 }));
+    assert_eq!(result(&messages, 4)["range"]["start"], json!({ "line": 1, "character": 3 }));
+    assert_eq!(result(&messages, 4)["uri"], URI);
+    assert_eq!(result(&messages, 5), &Json::Null);
+}
+
+#[test]
+fn definition_prefers_innermost_local() {
+    let text = "fn f() {\n  let x = 1;\n  {\n    let x = 2;\n    print x;\n  }\n  print x;\n}\n";
+    let messages = exchange(&[
+        open(text),
+        request(1, "textDocument/definition", 4, 10),
+        request(2, "textDocument/definition", 6, 8),
+    ]);
+    assert_eq!(result(&messages, 1)["range"]["start"], json!({ "line": 3, "character": 8 }));
+    assert_eq!(result(&messages, 2)["range"]["start"], json!({ "line": 1, "character": 6 }));
+}
+
+#[test]
+fn hover_shows_function_arity() {
+    let text = "fn add(a, b) { return a + b; }\nprint add(1, 2);\n";
+    let messages = exchange(&[open(text), request(1, "textDocument/hover", 1, 7)]);
+    let hover = result(&messages, 1)["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("fn add(a, b)"), "{}", hover);
+    assert!(hover.contains("arity: 2"), "{}", hover);
+}
+
+#[test]
+fn document_symbols_list_functions() {
+    let text = "fn one() {}\nlet x = 1;\nfn two(a) { fn inner() {} }\n";
+    let messages = exchange(&[
+        open(text),
+        json!({ "id": 1, "method": "textDocument/documentSymbol", "params": { "textDocument": { "uri": URI } } }),
+    ]);
+    let names: Vec<&Json> = result(&messages, 1)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| &s["name"])
+        .collect();
+    assert_eq!(names, vec!["one", "two", "inner"]);
+    assert_eq!(result(&messages, 1)[0]["kind"], 12);
+}
+
+#[test]
+fn completion_offers_keywords_and_visible_names() {
+    let text = "let total = 0;\nfn f(a) {\n  let inner = 1;\n  \n}\n\n";
+    let messages = exchange(&[
+        open(text),
+        request(1, "textDocument/completion", 3, 2),
+        request(2, "textDocument/completion", 5, 0),
+    ]);
+    let labels = |id| -> Vec<String> {
+        result(&messages, id)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let inside = labels(1);
+    for expected in ["while", "let", "total", "f", "a", "inner"] {
+        assert!(inside.contains(&expected.to_string()), "missing {}", expected);
+    }
+    let outside = labels(2);
+    assert!(outside.contains(&"total".to_string()));
+    assert!(!outside.contains(&"inner".to_string()));
+    assert!(!outside.contains(&"a".to_string()));
+}
+
+#[test]
+fn unknown_request_returns_method_not_found() {
+    let messages = exchange(&[json!({ "id": 7, "method": "textDocument/rename", "params": {} })]);
+    assert_eq!(messages[0]["error"]["code"], -32601);
+}
+
+#[test]
+fn positions_count_utf16_units() {
+    let text = "let s = \"é😀\";\nx";
+    let x = text.find('x').unwrap();
+    assert_eq!(offset_to_position(text, x), json!({ "line": 1, "character": 0 }));
+    let end = text.find(';').unwrap();
+    assert_eq!(offset_to_position(text, end), json!({ "line": 0, "character": 13 }));
+    assert_eq!(position_to_offset(text, 0, 13), end);
+    assert_eq!(position_to_offset(text, 1, 0), x);
+}
@@ -1,31 +1,62 @@
 use std::{env, io};
+mod ast;
 mod bytecode;
 mod chunk;
 mod compiler;
+mod dap;
+mod formatter;
 mod gc;
+mod globals;
+#[cfg(feature = "jit")]
+mod jit;
 mod lexer;
+mod lint;
+mod lsp;
+mod natives;
 mod object;
+mod protocol;
+mod register;
 mod repl;
 mod source;
 mod table;
+mod testing;
 mod token;
 mod value;
 mod vm;
 
-use source::{execute, open_source_file};
+use source::run_command;
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        println!("===============Lockhart initiated===============");
-        repl::start();
-    } else {
-        let src_filename = &args[1];
-        let code = open_source_file(&src_filename);
-        execute(code);
+    match args.get(1).map(|arg| arg.as_str()) {
+        None | Some("repl") => {
+            println!("===============Lockhart initiated===============");
+            std::process::exit(repl::start());
+        }
+        Some("dap") => dap::start()?,
+        Some("lsp") => lsp::start()?,
+        Some("fmt") => exit_on_failure(formatter::run(&args[2..])),
+        Some("lint") => exit_on_failure(lint::run(&args[2..])),
+        Some("test") => exit_on_failure(testing::run(&args[2..])),
+        Some("help") | Some("--help") | Some("-h") => source::help(),
+        Some(command @ ("run" | "check" | "compile" | "disassemble")) => {
+            std::process::exit(run_command(command, &args[2..]));
+        }
+        // a bare path runs the script
+        Some(_) => std::process::exit(run_command("run", &args[1..])),
     }
-    // let s = "4 == nil";
-    // let mut interpreter = Vm::init_vm();
-    // interpreter.interpret(s.to_string());
     Ok(())
 }
+
+/// Exit with 1 if a command over files failed, or like `run_command` does
+/// with `EXIT_NO_INPUT` if one of them couldn't be read.
+fn exit_on_failure(result: io::Result<bool>) {
+    match result {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(source::EXIT_NO_INPUT);
+        }
+    }
+}
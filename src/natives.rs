@@ -0,0 +1,66 @@
+//! Functions implemented in Rust and defined as globals in every VM.
+
+use crate::{
+    value::Value,
+    vm::{InterpretError, Vm},
+};
+
+mod assert;
+mod io;
+mod lists;
+mod math;
+mod strings;
+mod system;
+#[cfg(test)]
+mod tests;
+
+pub fn register(vm: &mut Vm) {
+    vm.set_args(Vec::new());
+    assert::register(vm);
+    io::register(vm);
+    lists::register(vm);
+    math::register(vm);
+    strings::register(vm);
+    system::register(vm);
+}
+
+/// Whether a global is one every VM starts with rather than one the script
+/// defined, for tools that list globals.
+pub fn is_builtin(name: &str, value: &Value) -> bool {
+    matches!(value, Value::NATIVE(_)) || BUILTIN_VALUES.contains(&name)
+}
+
+// globals defined at startup that aren't native functions
+const BUILTIN_VALUES: [&str; 5] = ["args", "pi", "e", "inf", "nan"];
+
+fn runtime_error<T>(message: String) -> Result<T, InterpretError> {
+    Err(InterpretError::InterpretRuntimeError(message))
+}
+
+/// The error for a native called with an argument it can't use.
+fn type_error<T>(native: &str, expected: &str, found: &Value) -> Result<T, InterpretError> {
+    runtime_error(format!("{} expected {} but found {}", native, expected, found.repr()))
+}
+
+fn string<'a>(native: &str, value: &'a Value) -> Result<&'a str, InterpretError> {
+    match value {
+        Value::STR(s) => Ok(&s.s),
+        other => type_error(native, "a string", other),
+    }
+}
+
+pub use math::Rng;
+
+fn number(native: &str, value: &Value) -> Result<f64, InterpretError> {
+    match value {
+        Value::NUMBER(n) => Ok(*n),
+        other => type_error(native, "a number", other),
+    }
+}
+
+fn integer(native: &str, value: &Value) -> Result<i64, InterpretError> {
+    match value {
+        Value::NUMBER(n) if n.fract() == 0.0 && n.abs() < 2f64.powi(53) => Ok(*n as i64),
+        other => type_error(native, "an integer", other),
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{
+    value::Value,
+    vm::{InterpretError, Vm},
+};
+
+use super::runtime_error;
+
+pub fn register(vm: &mut Vm) {
+    vm.define_native("assert", None, assert);
+    vm.define_native("assert_eq", Some(2), assert_eq);
+}
+
+/// `assert(condition[, message])`: fail with the caller's line if the condition
+/// is false the way an `if` would see it.
+fn assert(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    if args.is_empty() || args.len() > 2 {
+        return runtime_error(format!("assert expected 1 or 2 args but found {}", args.len()));
+    }
+    if !Value::is_falsey(&args[0]) {
+        return Ok(Value::NIL);
+    }
+    match args.get(1) {
+        Some(message) => runtime_error(format!("assertion failed on line {}: {}", vm.current_line(), message)),
+        None => runtime_error(format!("assertion failed on line {}", vm.current_line())),
+    }
+}
+
+fn assert_eq(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    if Value::values_equal(&args[0], &args[1]) {
+        return Ok(Value::NIL);
+    }
+    runtime_error(format!(
+        "assert_eq failed on line {}: got {}, expected {}",
+        vm.current_line(),
+        args[0].repr(),
+        args[1].repr()
+    ))
+}
@@ -0,0 +1,98 @@
+//! Files and standard input. I/O failures are runtime errors the script sees,
+//! naming the path and the reason.
+
+use std::{
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use crate::{
+    value::Value,
+    vm::{InterpretError, Vm},
+};
+
+use super::{runtime_error, string};
+
+pub fn register(vm: &mut Vm) {
+    vm.define_native("read_file", Some(1), read_file);
+    vm.define_native("write_file", Some(2), write_file);
+    vm.define_native("append_file", Some(2), append_file);
+    vm.define_native("file_exists", Some(1), file_exists);
+    vm.define_native("list_dir", Some(1), list_dir);
+    vm.define_native("read_line", Some(0), read_line);
+}
+
+fn io_error<T>(native: &str, path: &str, err: impl Display) -> Result<T, InterpretError> {
+    runtime_error(format!("{} failed for '{}': {}", native, path, err))
+}
+
+fn read_file(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let path = string("read_file", &args[0])?;
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(vm.intern(contents)),
+        Err(err) => io_error("read_file", path, err),
+    }
+}
+
+/// Replace the file's contents with `contents`, shown as `print` would show it.
+fn write_file(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let path = string("write_file", &args[0])?;
+    match fs::write(path, args[1].to_string()) {
+        Ok(()) => Ok(Value::NIL),
+        Err(err) => io_error("write_file", path, err),
+    }
+}
+
+/// Add `contents` to the end of the file, creating it if needed.
+fn append_file(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let path = string("append_file", &args[0])?;
+    let appended = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(args[1].to_string().as_bytes()));
+    match appended {
+        Ok(()) => Ok(Value::NIL),
+        Err(err) => io_error("append_file", path, err),
+    }
+}
+
+fn file_exists(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let path = string("file_exists", &args[0])?;
+    Ok(Value::BOOL(Path::new(path).exists()))
+}
+
+/// The names of the entries in a directory, sorted.
+fn list_dir(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let path = string("list_dir", &args[0])?;
+    let entries = fs::read_dir(path).and_then(|entries| {
+        entries
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>, _>>()
+    });
+    let mut names = match entries {
+        Ok(names) => names,
+        Err(err) => return io_error("list_dir", path, err),
+    };
+    names.sort();
+    let items = names.into_iter().map(|name| vm.intern(name)).collect();
+    Ok(vm.new_list(items))
+}
+
+/// The next line of input without its line ending, or nil at the end of input.
+fn read_line(vm: &mut Vm, _: &[Value]) -> Result<Value, InterpretError> {
+    // a prompt printed just before should be visible while we wait
+    let _ = vm.output().flush();
+    let mut line = String::new();
+    match vm.input().read_line(&mut line) {
+        Ok(0) => Ok(Value::NIL),
+        Ok(_) => {
+            let end = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(end);
+            Ok(vm.intern(line))
+        }
+        Err(err) => io_error("read_line", "stdin", err),
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{
+    value::Value,
+    vm::{InterpretError, Vm},
+};
+
+use super::type_error;
+
+pub fn register(vm: &mut Vm) {
+    vm.define_native("len", Some(1), len);
+    vm.define_native("push", Some(2), push);
+    vm.define_native("pop", Some(1), pop);
+}
+
+/// The number of items in a list, or of characters in a string.
+fn len(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    match &args[0] {
+        Value::LIST(list) => Ok(Value::NUMBER(list.items.len() as f64)),
+        Value::STR(s) => Ok(Value::NUMBER(s.s.chars().count() as f64)),
+        other => type_error("len", "a list or string", other),
+    }
+}
+
+/// Append to a list and return the list, so pushes can be chained.
+fn push(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    match &args[0] {
+        Value::LIST(list) => {
+            vm.list_push(*list, args[1].clone());
+            Ok(args[0].clone())
+        }
+        other => type_error("push", "a list", other),
+    }
+}
+
+/// Remove and return the last item of a list, or nil if it is empty.
+fn pop(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    match &args[0] {
+        Value::LIST(list) => {
+            let mut list = *list;
+            Ok(list.items.pop().unwrap_or(Value::NIL))
+        }
+        other => type_error("pop", "a list", other),
+    }
+}
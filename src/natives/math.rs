@@ -0,0 +1,152 @@
+use std::{
+    f64::consts,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    value::Value,
+    vm::{InterpretError, Vm},
+};
+
+use super::{integer, number, runtime_error, type_error};
+
+pub fn register(vm: &mut Vm) {
+    vm.define_global("pi", Value::NUMBER(consts::PI));
+    vm.define_global("e", Value::NUMBER(consts::E));
+    vm.define_global("inf", Value::NUMBER(f64::INFINITY));
+    vm.define_global("nan", Value::NUMBER(f64::NAN));
+
+    vm.define_native("sqrt", Some(1), |_, args| unary("sqrt", args, f64::sqrt));
+    vm.define_native("floor", Some(1), |_, args| unary("floor", args, f64::floor));
+    vm.define_native("ceil", Some(1), |_, args| unary("ceil", args, f64::ceil));
+    vm.define_native("round", Some(1), |_, args| unary("round", args, f64::round));
+    vm.define_native("abs", Some(1), |_, args| unary("abs", args, f64::abs));
+    vm.define_native("sin", Some(1), |_, args| unary("sin", args, f64::sin));
+    vm.define_native("cos", Some(1), |_, args| unary("cos", args, f64::cos));
+    vm.define_native("tan", Some(1), |_, args| unary("tan", args, f64::tan));
+    vm.define_native("asin", Some(1), |_, args| unary("asin", args, f64::asin));
+    vm.define_native("acos", Some(1), |_, args| unary("acos", args, f64::acos));
+    vm.define_native("atan", Some(1), |_, args| unary("atan", args, f64::atan));
+    vm.define_native("exp", Some(1), |_, args| unary("exp", args, f64::exp));
+    vm.define_native("log", Some(1), |_, args| unary("log", args, f64::ln));
+    vm.define_native("log10", Some(1), |_, args| unary("log10", args, f64::log10));
+    vm.define_native("log2", Some(1), |_, args| unary("log2", args, f64::log2));
+    vm.define_native("pow", Some(2), |_, args| binary("pow", args, f64::powf));
+    vm.define_native("atan2", Some(2), |_, args| binary("atan2", args, f64::atan2));
+    vm.define_native("min", None, |_, args| fold("min", args, f64::min));
+    vm.define_native("max", None, |_, args| fold("max", args, f64::max));
+    vm.define_native("is_nan", Some(1), is_nan);
+
+    vm.define_native("seed", Some(1), seed);
+    vm.define_native("random", Some(0), random);
+    vm.define_native("random_int", Some(2), random_int);
+    vm.define_native("shuffle", Some(1), shuffle);
+}
+
+fn unary(native: &str, args: &[Value], f: fn(f64) -> f64) -> Result<Value, InterpretError> {
+    Ok(Value::NUMBER(f(number(native, &args[0])?)))
+}
+
+fn binary(native: &str, args: &[Value], f: fn(f64, f64) -> f64) -> Result<Value, InterpretError> {
+    Ok(Value::NUMBER(f(number(native, &args[0])?, number(native, &args[1])?)))
+}
+
+/// `f` applied across one or more numbers, for `min` and `max`.
+fn fold(native: &str, args: &[Value], f: fn(f64, f64) -> f64) -> Result<Value, InterpretError> {
+    let (first, rest) = match args.split_first() {
+        Some(split) => split,
+        None => return runtime_error(format!("{} expected at least 1 arg but found 0", native)),
+    };
+    let mut result = number(native, first)?;
+    for arg in rest {
+        result = f(result, number(native, arg)?);
+    }
+    Ok(Value::NUMBER(result))
+}
+
+fn is_nan(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    Ok(Value::BOOL(number("is_nan", &args[0])?.is_nan()))
+}
+
+/// A small pseudo-random generator (xorshift64*). Each VM has its own, seeded
+/// from the clock unless a script calls `seed(n)` for a repeatable sequence.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // splitmix the seed so nearby seeds start far apart; the state can't be zero
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Rng {
+            state: (z ^ (z >> 31)).max(1),
+        }
+    }
+
+    pub fn from_clock() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Rng::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// uniform in [0, n)
+    pub fn below(&mut self, n: u64) -> u64 {
+        // rejection sampling avoids favouring small values
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+fn seed(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    *vm.rng() = Rng::new(integer("seed", &args[0])? as u64);
+    Ok(Value::NIL)
+}
+
+fn random(vm: &mut Vm, _: &[Value]) -> Result<Value, InterpretError> {
+    Ok(Value::NUMBER(vm.rng().next_f64()))
+}
+
+/// An integer from `lo` to `hi`, both included.
+fn random_int(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let (lo, hi) = (integer("random_int", &args[0])?, integer("random_int", &args[1])?);
+    if lo > hi {
+        return runtime_error(format!("random_int expected lo <= hi but found {} > {}", lo, hi));
+    }
+    let offset = vm.rng().below((hi - lo) as u64 + 1);
+    Ok(Value::NUMBER((lo + offset as i64) as f64))
+}
+
+/// Shuffle a list in place and return it.
+fn shuffle(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let mut list = match &args[0] {
+        Value::LIST(list) => *list,
+        other => return type_error("shuffle", "a list", other),
+    };
+    for i in (1..list.items.len()).rev() {
+        let j = vm.rng().below(i as u64 + 1) as usize;
+        list.items.swap(i, j);
+        // the collector may have scanned the list up to somewhere between j and i
+        vm.write_barrier(&list.items[j]);
+    }
+    Ok(args[0].clone())
+}
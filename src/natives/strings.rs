@@ -0,0 +1,174 @@
+//! String natives. Positions count characters, not bytes.
+
+use crate::{
+    value::Value,
+    vm::{InterpretError, Vm},
+};
+
+use super::{integer, runtime_error, string, type_error};
+
+pub fn register(vm: &mut Vm) {
+    vm.define_native("substring", Some(3), substring);
+    vm.define_native("slice", None, slice);
+    vm.define_native("find", Some(2), find);
+    vm.define_native("replace", Some(3), replace);
+    vm.define_native("split", Some(2), split);
+    vm.define_native("join", Some(2), join);
+    vm.define_native("trim", Some(1), trim);
+    vm.define_native("upper", Some(1), upper);
+    vm.define_native("lower", Some(1), lower);
+    vm.define_native("starts_with", Some(2), starts_with);
+    vm.define_native("ends_with", Some(2), ends_with);
+    vm.define_native("to_number", Some(1), to_number);
+    vm.define_native("ord", Some(1), ord);
+    vm.define_native("chr", Some(1), chr);
+}
+
+/// The characters of `s` from `start` up to but not including `end`; both must be in range.
+fn substring(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let s = string("substring", &args[0])?;
+    let (start, end) = (integer("substring", &args[1])?, integer("substring", &args[2])?);
+    let count = s.chars().count() as i64;
+    if start < 0 || end < start || end > count {
+        let msg = format!("substring range {}..{} out of bounds for length {}", start, end, count);
+        return runtime_error(msg);
+    }
+    let sub = s.chars().skip(start as usize).take((end - start) as usize).collect();
+    Ok(vm.intern(sub))
+}
+
+/// `slice(value, start[, end])` for strings and lists. Negative positions
+/// count from the end and out of range ones are clamped, so this never fails.
+fn slice(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    if args.len() != 2 && args.len() != 3 {
+        return runtime_error(format!("slice expected 2 or 3 args but found {}", args.len()));
+    }
+    let count = match &args[0] {
+        Value::STR(s) => s.s.chars().count(),
+        Value::LIST(list) => list.items.len(),
+        other => return type_error("slice", "a string or list", other),
+    };
+    let start = clamp(integer("slice", &args[1])?, count);
+    let end = match args.get(2) {
+        Some(end) => clamp(integer("slice", end)?, count),
+        None => count,
+    }
+    .max(start);
+    match &args[0] {
+        Value::STR(s) => {
+            let sub = s.s.chars().skip(start).take(end - start).collect();
+            Ok(vm.intern(sub))
+        }
+        Value::LIST(list) => {
+            let items = list.items[start..end].to_vec();
+            Ok(vm.new_list(items))
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn clamp(position: i64, count: usize) -> usize {
+    let position = if position < 0 { position + count as i64 } else { position };
+    position.clamp(0, count as i64) as usize
+}
+
+/// Position of the first occurrence of `needle`, or -1.
+fn find(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let (s, needle) = (string("find", &args[0])?, string("find", &args[1])?);
+    let position = match s.find(needle) {
+        Some(byte) => s[..byte].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(Value::NUMBER(position))
+}
+
+/// Replace every occurrence of `from` with `to`.
+fn replace(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let s = string("replace", &args[0])?;
+    let (from, to) = (string("replace", &args[1])?, string("replace", &args[2])?);
+    if from.is_empty() {
+        return runtime_error("replace expected a non-empty string to replace".to_string());
+    }
+    let replaced = s.replace(from, to);
+    Ok(vm.intern(replaced))
+}
+
+/// The pieces of `s` between separators; an empty separator splits into characters.
+fn split(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let (s, separator) = (string("split", &args[0])?, string("split", &args[1])?);
+    let pieces: Vec<String> = if separator.is_empty() {
+        s.chars().map(String::from).collect()
+    } else {
+        s.split(separator).map(String::from).collect()
+    };
+    let items = pieces.into_iter().map(|piece| vm.intern(piece)).collect();
+    Ok(vm.new_list(items))
+}
+
+/// The items of a list, shown as `print` would and separated by `separator`.
+fn join(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let items = match &args[0] {
+        Value::LIST(list) => list.items.iter().map(|item| item.to_string()).collect::<Vec<_>>(),
+        other => return type_error("join", "a list", other),
+    };
+    let separator = string("join", &args[1])?;
+    let joined = items.join(separator);
+    Ok(vm.intern(joined))
+}
+
+fn trim(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let trimmed = string("trim", &args[0])?.trim().to_string();
+    Ok(vm.intern(trimmed))
+}
+
+fn upper(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let upper = string("upper", &args[0])?.to_uppercase();
+    Ok(vm.intern(upper))
+}
+
+fn lower(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let lower = string("lower", &args[0])?.to_lowercase();
+    Ok(vm.intern(lower))
+}
+
+fn starts_with(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let (s, prefix) = (string("starts_with", &args[0])?, string("starts_with", &args[1])?);
+    Ok(Value::BOOL(s.starts_with(prefix)))
+}
+
+fn ends_with(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let (s, suffix) = (string("ends_with", &args[0])?, string("ends_with", &args[1])?);
+    Ok(Value::BOOL(s.ends_with(suffix)))
+}
+
+/// The number written in a string, ignoring surrounding whitespace, or nil if it isn't one.
+fn to_number(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let s = string("to_number", &args[0])?.trim();
+    // Rust also accepts "inf" and "NaN", which aren't numbers a script could write
+    if !s.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+' || c == '.') {
+        return Ok(Value::NIL);
+    }
+    match s.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(Value::NUMBER(n)),
+        _ => Ok(Value::NIL),
+    }
+}
+
+/// The code point of a one-character string.
+fn ord(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let s = string("ord", &args[0])?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Value::NUMBER(c as u32 as f64)),
+        _ => type_error("ord", "a single character", &args[0]),
+    }
+}
+
+/// The one-character string for a code point.
+fn chr(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let code = integer("chr", &args[0])?;
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(c) => Ok(vm.intern(c.to_string())),
+        None => runtime_error(format!("chr expected a code point but found {}", code)),
+    }
+}
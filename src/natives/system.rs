@@ -0,0 +1,51 @@
+use std::env;
+
+use crate::{
+    value::Value,
+    vm::{InterpretError, Vm},
+};
+
+use super::{runtime_error, type_error};
+
+pub fn register(vm: &mut Vm) {
+    vm.define_native("env", Some(1), get_env);
+    vm.define_native("set_env", Some(2), set_env);
+    vm.define_native("exit", Some(1), exit);
+}
+
+/// The value of an environment variable, or nil if it isn't set.
+fn get_env(vm: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let name = match &args[0] {
+        Value::STR(name) => name.s.clone(),
+        other => return type_error("env", "a string", other),
+    };
+    match env::var(name) {
+        Ok(value) => Ok(vm.intern(value)),
+        Err(_) => Ok(Value::NIL),
+    }
+}
+
+fn set_env(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    let name = match &args[0] {
+        Value::STR(name) if !name.s.is_empty() && !name.s.contains(['=', '\0']) => name.s.clone(),
+        other => return type_error("set_env", "a variable name", other),
+    };
+    // the OS can't store a NUL byte, and `set_var` panics rather than fail
+    let value = args[1].to_string();
+    if value.contains('\0') {
+        return runtime_error("set_env can't set a value containing a NUL byte".to_string());
+    }
+    env::set_var(name, value);
+    Ok(Value::NIL)
+}
+
+/// Stop the script; `Vm::run` returns `InterpretExit` with the code. Only
+/// codes a process can exit with are accepted, so none is silently truncated.
+fn exit(_: &mut Vm, args: &[Value]) -> Result<Value, InterpretError> {
+    match &args[0] {
+        Value::NUMBER(code) if code.fract() == 0.0 && (0.0..=255.0).contains(code) => {
+            Err(InterpretError::InterpretExit(*code as i32))
+        }
+        other => type_error("exit", "an integer from 0 to 255", other),
+    }
+}
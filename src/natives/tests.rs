@@ -0,0 +1,229 @@
+use crate::vm::{InterpretError, Vm};
+
+/// `source` evaluated as an expression, shown the way the REPL shows it
+fn eval(vm: &mut Vm, source: &str) -> String {
+    match vm.evaluate(source.to_string()) {
+        Ok(value) => value.repr(),
+        Err(err) => panic!("{} failed: {}", source, err),
+    }
+}
+
+fn eval_err(source: &str) -> InterpretError {
+    match Vm::init_vm().evaluate(source.to_string()) {
+        Ok(value) => panic!("{} should fail, got {}", source, value.repr()),
+        Err(err) => err,
+    }
+}
+
+#[test]
+fn list_natives() {
+    let mut vm = Vm::init_vm();
+    assert!(vm.interpret("let xs = [1];".to_string()).is_ok());
+    assert_eq!(eval(&mut vm, "len(xs)"), "1");
+    assert_eq!(eval(&mut vm, "push(push(xs, \"a\"), nil)"), "[1, \"a\", nil]");
+    assert_eq!(eval(&mut vm, "pop(xs)"), "nil");
+    assert_eq!(eval(&mut vm, "len(xs)"), "2");
+    assert_eq!(eval(&mut vm, "pop([])"), "nil");
+    assert!(eval_err("len(1)").to_string().contains("len expected a list or string but found 1"));
+    assert!(eval_err("len()").to_string().contains("len expected 1 args but found 0"));
+}
+
+#[test]
+fn lists_holding_themselves_print_finitely() {
+    let mut vm = Vm::init_vm();
+    assert!(vm.interpret("let l = [1];\npush(l, l);\nlet outer = [l];".to_string()).is_ok());
+    assert_eq!(eval(&mut vm, "l"), "[1, [...]]");
+    assert_eq!(eval(&mut vm, "outer"), "[[1, [...]]]");
+    assert_eq!(eval(&mut vm, "join(l, \" \")"), "\"1 [1, [...]]\"");
+    // the same list twice side by side isn't a cycle
+    assert_eq!(eval(&mut vm, "[outer, outer]"), "[[[1, [...]]], [[1, [...]]]]");
+}
+
+#[test]
+fn args_are_a_list_of_strings() {
+    let mut vm = Vm::init_vm();
+    assert_eq!(eval(&mut vm, "args"), "[]");
+    vm.set_args(vec!["a".to_string(), "b c".to_string()]);
+    assert_eq!(eval(&mut vm, "args"), "[\"a\", \"b c\"]");
+    assert_eq!(eval(&mut vm, "args[1] == \"b c\""), "true");
+}
+
+#[test]
+fn environment_variables() {
+    let mut vm = Vm::init_vm();
+    assert_eq!(eval(&mut vm, "env(\"LOCKHART_TEST_UNSET_VARIABLE\")"), "nil");
+    assert_eq!(eval(&mut vm, "set_env(\"LOCKHART_TEST_VARIABLE\", 12)"), "nil");
+    assert_eq!(eval(&mut vm, "env(\"LOCKHART_TEST_VARIABLE\")"), "\"12\"");
+    assert!(eval_err("set_env(\"A=B\", 1)").to_string().contains("set_env expected a variable name"));
+    assert!(eval_err("set_env(\"A\" + chr(0), 1)").to_string().contains("set_env expected a variable name"));
+    assert!(eval_err("set_env(\"LOCKHART_TEST_NUL\", chr(0))").to_string().contains("NUL byte"));
+}
+
+#[test]
+fn exit_stops_the_script_with_a_code() {
+    let mut vm = Vm::init_vm();
+    let result = vm.interpret("let before = 1;\nexit(4);\nlet after = 2;".to_string());
+    assert!(matches!(result, Err(InterpretError::InterpretExit(4))));
+    let names: Vec<String> = vm.globals().map(|(name, _)| name.s.clone()).collect();
+    assert!(names.contains(&"before".to_string()));
+    assert!(!names.contains(&"after".to_string()));
+    assert!(matches!(eval_err("exit(\"1\")"), InterpretError::InterpretRuntimeError(_)));
+    assert!(matches!(Vm::init_vm().interpret("exit(255);".to_string()), Err(InterpretError::InterpretExit(255))));
+    for code in ["256", "-1", "1.5"] {
+        let err = eval_err(&format!("exit({})", code));
+        assert!(err.to_string().contains("exit expected an integer from 0 to 255"), "{}", err);
+    }
+}
+
+#[test]
+fn slicing_strings() {
+    let mut vm = Vm::init_vm();
+    assert_eq!(eval(&mut vm, "len(\"héllo\")"), "5");
+    assert_eq!(eval(&mut vm, "substring(\"héllo\", 1, 3)"), "\"él\"");
+    assert_eq!(eval(&mut vm, "substring(\"abc\", 3, 3)"), "\"\"");
+    assert!(eval_err("substring(\"abc\", 2, 4)").to_string().contains("out of bounds"));
+    assert_eq!(eval(&mut vm, "slice(\"héllo\", -3)"), "\"llo\"");
+    assert_eq!(eval(&mut vm, "slice(\"hello\", 1, -1)"), "\"ell\"");
+    assert_eq!(eval(&mut vm, "slice(\"hello\", 4, 2)"), "\"\"");
+    assert_eq!(eval(&mut vm, "slice([1, 2, 3], 1, 100)"), "[2, 3]");
+    assert_eq!(eval(&mut vm, "\"héllo\"[1]"), "\"é\"");
+    assert!(eval_err("\"abc\"[3]").to_string().contains("out of range"));
+}
+
+#[test]
+fn searching_and_rewriting_strings() {
+    let mut vm = Vm::init_vm();
+    assert_eq!(eval(&mut vm, "find(\"héllo\", \"l\")"), "2");
+    assert_eq!(eval(&mut vm, "find(\"hello\", \"z\")"), "-1");
+    assert_eq!(eval(&mut vm, "replace(\"a-b-c\", \"-\", \"+\")"), "\"a+b+c\"");
+    assert_eq!(eval(&mut vm, "split(\"a,b,,c\", \",\")"), "[\"a\", \"b\", \"\", \"c\"]");
+    assert_eq!(eval(&mut vm, "split(\"ab\", \"\")"), "[\"a\", \"b\"]");
+    assert_eq!(eval(&mut vm, "join([1, \"b\", nil], \", \")"), "\"1, b, nil\"");
+    assert_eq!(eval(&mut vm, "trim(\"  x y \n\")"), "\"x y\"");
+    assert_eq!(eval(&mut vm, "upper(\"abc\") + lower(\"DEF\")"), "\"ABCdef\"");
+    assert_eq!(eval(&mut vm, "starts_with(\"lockhart\", \"lock\")"), "true");
+    assert_eq!(eval(&mut vm, "ends_with(\"lockhart\", \"lock\")"), "false");
+    assert!(eval_err("upper(1)").to_string().contains("upper expected a string but found 1"));
+}
+
+#[test]
+fn numbers_and_char_codes() {
+    let mut vm = Vm::init_vm();
+    assert_eq!(eval(&mut vm, "to_number(\" 42 \")"), "42");
+    assert_eq!(eval(&mut vm, "to_number(\"-2.5\")"), "-2.5");
+    assert_eq!(eval(&mut vm, "to_number(\"4x\")"), "nil");
+    assert_eq!(eval(&mut vm, "to_number(\"inf\")"), "nil");
+    assert_eq!(eval(&mut vm, "ord(\"A\")"), "65");
+    assert_eq!(eval(&mut vm, "chr(233)"), "\"é\"");
+    assert!(eval_err("ord(\"ab\")").to_string().contains("a single character"));
+    assert!(eval_err("chr(-1)").to_string().contains("code point"));
+}
+
+#[test]
+fn math_functions() {
+    let mut vm = Vm::init_vm();
+    assert_eq!(eval(&mut vm, "sqrt(16) + pow(2, 10)"), "1028");
+    assert_eq!(eval(&mut vm, "pow(4, 0.5)"), "2");
+    assert_eq!(eval(&mut vm, "[floor(2.7), ceil(2.1), round(2.5), abs(-3)]"), "[2, 3, 3, 3]");
+    assert_eq!(eval(&mut vm, "[min(3, 1, 2), max(3, 1, 2), max(-1)]"), "[1, 3, -1]");
+    assert_eq!(eval(&mut vm, "round(sin(pi / 2) * 1000) + cos(0) + tan(0)"), "1001");
+    assert_eq!(eval(&mut vm, "atan2(1, 1) * 4 == pi"), "true");
+    assert_eq!(eval(&mut vm, "[log(e), log10(1000), log2(8), exp(0)]"), "[1, 3, 3, 1]");
+    assert_eq!(eval(&mut vm, "[is_nan(nan), is_nan(inf), -inf < 0, sqrt(-1) == sqrt(-1)]"), "[true, false, true, false]");
+    assert!(eval_err("min()").to_string().contains("at least 1 arg"));
+    assert!(eval_err("sqrt(\"4\")").to_string().contains("sqrt expected a number"));
+}
+
+#[test]
+fn seeded_random_numbers_repeat() {
+    let draw = "[random(), random_int(1, 6), random_int(-2, 2), shuffle([1, 2, 3, 4, 5])]";
+    let mut vm = Vm::init_vm();
+    eval(&mut vm, "seed(42)");
+    let first = eval(&mut vm, draw);
+    eval(&mut vm, "seed(42)");
+    assert_eq!(eval(&mut vm, draw), first);
+    eval(&mut vm, "seed(43)");
+    assert_ne!(eval(&mut vm, draw), first);
+}
+
+#[test]
+fn random_numbers_stay_in_range() {
+    let mut vm = Vm::init_vm();
+    let source = "let ok = true;\nfor (let i = 0; i < 500; i = i + 1) {\n  let r = random();\n  let n = random_int(-1, 1);\n  if (r < 0 or r >= 1 or n < -1 or n > 1 or floor(n) != n) ok = false;\n}";
+    assert!(vm.interpret(source.to_string()).is_ok());
+    assert_eq!(eval(&mut vm, "ok"), "true");
+    let shuffled = eval(&mut vm, "join(shuffle([1, 2, 3, 4]), \"\")");
+    let mut digits: Vec<char> = shuffled.trim_matches('"').chars().collect();
+    digits.sort();
+    assert_eq!(digits, vec!['1', '2', '3', '4']);
+    assert!(eval_err("random_int(2, 1)").to_string().contains("lo <= hi"));
+}
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("lockhart_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn files_are_written_read_and_listed() {
+    let dir = temp_dir("io");
+    let mut vm = Vm::init_vm();
+    let path = vm.intern(dir.to_str().unwrap().to_string());
+    vm.define_global("dir", path);
+    let source = "let log = dir + \"/log.txt\";\n\
+                  let missing = file_exists(log);\n\
+                  write_file(log, \"a\n\");\n\
+                  append_file(log, 1);\n\
+                  append_file(dir + \"/new.txt\", [2]);\n\
+                  let text = read_file(log);\n\
+                  let names = list_dir(dir);";
+    assert!(vm.interpret(source.to_string()).is_ok());
+    assert_eq!(eval(&mut vm, "missing"), "false");
+    assert_eq!(eval(&mut vm, "file_exists(log)"), "true");
+    assert_eq!(eval(&mut vm, "text"), "\"a\\n1\"");
+    assert_eq!(eval(&mut vm, "names"), "[\"log.txt\", \"new.txt\"]");
+    assert_eq!(eval(&mut vm, "read_file(dir + \"/new.txt\")"), "\"[2]\"");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn io_failures_are_runtime_errors() {
+    for source in [
+        "read_file(\"/nonexistent/lockhart.txt\")",
+        "write_file(\"/nonexistent/lockhart.txt\", 1)",
+        "list_dir(\"/nonexistent/lockhart\")",
+    ] {
+        match eval_err(source) {
+            InterpretError::InterpretRuntimeError(msg) => assert!(msg.contains("failed for '/nonexistent/"), "{}", msg),
+            other => panic!("expected a runtime error from {}, got {:?}", source, other),
+        }
+    }
+}
+
+#[test]
+fn lines_are_read_from_the_input() {
+    let mut vm = Vm::builder().input(std::io::Cursor::new("first\r\nsecond\n\nlast")).build();
+    let lines = eval(&mut vm, "[read_line(), read_line(), read_line(), read_line(), read_line()]");
+    assert_eq!(lines, "[\"first\", \"second\", \"\", \"last\", nil]");
+}
+
+#[test]
+fn failed_assertions_name_the_line() {
+    let mut vm = Vm::init_vm();
+    assert_eq!(eval(&mut vm, "assert(1 < 2)"), "nil");
+    assert_eq!(eval(&mut vm, "assert_eq([1][0], 1)"), "nil");
+    let result = vm.interpret("let a = 1;\nassert_eq(a + 1, \"2\");".to_string());
+    match result {
+        Err(InterpretError::InterpretRuntimeError(msg)) => {
+            assert_eq!(msg, "assert_eq failed on line 2: got 2, expected \"2\"")
+        }
+        other => panic!("expected assert_eq to fail, got {:?}", other),
+    }
+    match vm.interpret("\n\nassert(nil, \"was \" + \"nil\");".to_string()) {
+        Err(InterpretError::InterpretRuntimeError(msg)) => assert_eq!(msg, "assertion failed on line 3: was nil"),
+        other => panic!("expected assert to fail, got {:?}", other),
+    }
+    assert!(matches!(eval_err("assert()"), InterpretError::InterpretRuntimeError(_)));
+}
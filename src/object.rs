@@ -1,14 +1,18 @@
-use std::mem::size_of;
+use std::{cell::RefCell, mem::size_of};
 
 use crate::{
     chunk::Chunk,
     gc::{GcManaged, GcObject, GcRef},
+    value::Value,
+    vm::{InterpretError, Vm},
 };
 
 #[derive(Clone)]
 pub enum ObjectType {
     STRING,
     FUNCTION,
+    NATIVE,
+    LIST,
     CLASS,
 }
 
@@ -18,15 +22,33 @@ pub struct ObjFunction {
     pub arity: u8,
     pub chunk: Chunk,
     pub name: GcRef<ObjString>,
+    #[cfg(feature = "jit")]
+    pub jit: crate::jit::FunctionJit,
+    // translated the first time the register backend calls the function
+    pub registers: Option<Box<crate::register::RegisterCode>>,
+    // the top-level function of a script rather than one it declared
+    script: bool,
 }
 
 impl ObjFunction {
     pub fn new(name: GcRef<ObjString>) -> ObjFunction {
         ObjFunction {
-            header: GcObject::new(ObjectType::FUNCTION, size_of::<ObjFunction>()),
+            header: GcObject::new(ObjectType::FUNCTION),
             arity: 0,
             chunk: Chunk::new(),
             name, 
+            #[cfg(feature = "jit")]
+            jit: Default::default(),
+            registers: None,
+            script: false,
+        }
+    }
+
+    /// The function a whole script compiles to.
+    pub fn script(name: GcRef<ObjString>) -> ObjFunction {
+        ObjFunction {
+            script: true,
+            ..ObjFunction::new(name)
         }
     }
 }
@@ -35,14 +57,19 @@ impl GcManaged for ObjFunction {
     fn header(&self) -> &GcObject {
         &self.header
     }
+
+    fn size(&self) -> usize {
+        let registers = self.registers.as_ref().map_or(0, |code| code.size());
+        size_of::<ObjFunction>() + self.chunk.heap_size() + registers
+    }
 }
 
 impl core::fmt::Display for ObjFunction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.name.s == "" {
-            f.write_fmt(format_args!("<fn {}>", *(*self).name))
-        } else {
+        if self.script {
             f.write_fmt(format_args!("<script>"))
+        } else {
+            f.write_fmt(format_args!("<fn {}>", *(*self).name))
         }
     }
 }
@@ -57,7 +84,7 @@ impl ObjString {
     pub fn from_string(s: String) -> ObjString {
         let hash = ObjString::compute_hash(&s);
         ObjString {
-            header: GcObject::new(ObjectType::STRING, size_of::<ObjString>()),
+            header: GcObject::new(ObjectType::STRING),
             s,
             hash,
         }
@@ -77,6 +104,10 @@ impl GcManaged for ObjString {
     fn header(&self) -> &GcObject {
         &self.header
     }
+
+    fn size(&self) -> usize {
+        size_of::<ObjString>() + self.s.capacity()
+    }
 }
 
 impl core::fmt::Display for ObjString {
@@ -84,3 +115,86 @@ impl core::fmt::Display for ObjString {
         f.write_str(&self.s)
     }
 }
+
+/// A function implemented in Rust. It receives its arguments and may inspect
+/// or change the VM, e.g. to allocate objects.
+pub type NativeFn = fn(&mut Vm, &[Value]) -> Result<Value, InterpretError>;
+
+#[repr(C)]
+pub struct ObjNative {
+    header: GcObject,
+    pub name: String,
+    /// None for natives that take any number of arguments
+    pub arity: Option<u8>,
+    pub function: NativeFn,
+}
+
+impl ObjNative {
+    pub fn new(name: String, arity: Option<u8>, function: NativeFn) -> ObjNative {
+        ObjNative {
+            header: GcObject::new(ObjectType::NATIVE),
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+impl GcManaged for ObjNative {
+    fn header(&self) -> &GcObject {
+        &self.header
+    }
+
+    fn size(&self) -> usize {
+        size_of::<ObjNative>() + self.name.capacity()
+    }
+}
+
+impl core::fmt::Display for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+#[repr(C)]
+pub struct ObjList {
+    header: GcObject,
+    pub items: Vec<Value>,
+}
+
+impl ObjList {
+    pub fn new(items: Vec<Value>) -> ObjList {
+        ObjList {
+            header: GcObject::new(ObjectType::LIST),
+            items,
+        }
+    }
+}
+
+impl GcManaged for ObjList {
+    fn header(&self) -> &GcObject {
+        &self.header
+    }
+
+    fn size(&self) -> usize {
+        size_of::<ObjList>() + self.items.capacity() * size_of::<Value>()
+    }
+}
+
+thread_local! {
+    // lists being printed further up the stack, so one holding itself ends
+    static PRINTING: RefCell<Vec<*const ObjList>> = const { RefCell::new(Vec::new()) };
+}
+
+impl core::fmt::Display for ObjList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let this = self as *const ObjList;
+        if PRINTING.with(|printing| printing.borrow().contains(&this)) {
+            return f.write_str("[...]");
+        }
+        PRINTING.with(|printing| printing.borrow_mut().push(this));
+        let items: Vec<String> = self.items.iter().map(|item| item.repr()).collect();
+        PRINTING.with(|printing| printing.borrow_mut().pop());
+        write!(f, "[{}]", items.join(", "))
+    }
+}
@@ -0,0 +1,36 @@
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value as Json;
+
+/// Reads one `Content-Length` framed JSON message, the base protocol shared by
+/// DAP and LSP. Returns None once the stream is closed.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
@@ -0,0 +1,193 @@
+use std::{fmt::Display, mem::size_of};
+
+use crate::{globals::Globals, object::ObjFunction, value::Value};
+
+pub use self::translate::translate;
+
+mod translate;
+#[cfg(test)]
+mod tests;
+
+/// Where an instruction reads a value from: one of the frame's registers, or
+/// the function's constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(u32),
+    Const(u32),
+}
+
+/// An instruction of the register backend. Registers are the frame's stack
+/// slots: register 0 holds the function, the parameters follow, then the
+/// locals, then temporaries. A call's callee and arguments sit in
+/// consecutive registers and its result replaces the callee.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instr {
+    Move { dst: u32, src: Operand },
+    Negate { dst: u32, src: Operand },
+    Not { dst: u32, src: Operand },
+    Add { dst: u32, a: Operand, b: Operand },
+    Subtract { dst: u32, a: Operand, b: Operand },
+    Multiply { dst: u32, a: Operand, b: Operand },
+    Divide { dst: u32, a: Operand, b: Operand },
+    Mod { dst: u32, a: Operand, b: Operand },
+    Equal { dst: u32, a: Operand, b: Operand },
+    Greater { dst: u32, a: Operand, b: Operand },
+    Less { dst: u32, a: Operand, b: Operand },
+    GetGlobal { dst: u32, slot: u32 },
+    SetGlobal { slot: u32, src: Operand },
+    DefineGlobal { slot: u32, src: Operand },
+    Jump { target: u32 },
+    JumpIfFalse { cond: Operand, target: u32 },
+    Call { base: u32, args: u8 },
+    /// a call that takes over the frame, see `Opcode::OP_TAIL_CALL`
+    TailCall { base: u32, args: u8 },
+    Return { src: Operand },
+    Print { src: Operand },
+    BuildList { dst: u32, start: u32, count: u32 },
+    GetIndex { dst: u32, target: Operand, index: Operand },
+    SetIndex { dst: u32, list: Operand, index: Operand, value: Operand },
+}
+
+impl Instr {
+    /// The register the instruction leaves its result in, for those whose
+    /// result can go anywhere.
+    fn dst_mut(&mut self) -> Option<&mut u32> {
+        match self {
+            Instr::Move { dst, .. }
+            | Instr::Negate { dst, .. }
+            | Instr::Not { dst, .. }
+            | Instr::Add { dst, .. }
+            | Instr::Subtract { dst, .. }
+            | Instr::Multiply { dst, .. }
+            | Instr::Divide { dst, .. }
+            | Instr::Mod { dst, .. }
+            | Instr::Equal { dst, .. }
+            | Instr::Greater { dst, .. }
+            | Instr::Less { dst, .. }
+            | Instr::GetGlobal { dst, .. }
+            | Instr::BuildList { dst, .. }
+            | Instr::GetIndex { dst, .. }
+            | Instr::SetIndex { dst, .. } => Some(dst),
+            _ => None,
+        }
+    }
+}
+
+/// A function translated for the register backend.
+pub struct RegisterCode {
+    pub code: Vec<Instr>,
+    /// For each instruction, the offset in the function's bytecode it came
+    /// from, so frames can still report lines.
+    pub origins: Vec<usize>,
+    /// The chunk's constants, then any literals the translation needed.
+    pub constants: Vec<Value>,
+    /// Registers a frame of this function uses.
+    pub registers: usize,
+}
+
+impl RegisterCode {
+    /// Bytes the code takes up, buffers included.
+    pub fn size(&self) -> usize {
+        size_of::<RegisterCode>()
+            + self.code.capacity() * size_of::<Instr>()
+            + self.origins.capacity() * size_of::<usize>()
+            + self.constants.capacity() * size_of::<Value>()
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "r{}", reg),
+            Operand::Const(idx) => write!(f, "k{}", idx),
+        }
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Instr::Move { dst, src } => write!(f, "MOVE r{} {}", dst, src),
+            Instr::Negate { dst, src } => write!(f, "NEGATE r{} {}", dst, src),
+            Instr::Not { dst, src } => write!(f, "NOT r{} {}", dst, src),
+            Instr::Add { dst, a, b } => write!(f, "ADD r{} {} {}", dst, a, b),
+            Instr::Subtract { dst, a, b } => write!(f, "SUBTRACT r{} {} {}", dst, a, b),
+            Instr::Multiply { dst, a, b } => write!(f, "MULTIPLY r{} {} {}", dst, a, b),
+            Instr::Divide { dst, a, b } => write!(f, "DIVIDE r{} {} {}", dst, a, b),
+            Instr::Mod { dst, a, b } => write!(f, "MOD r{} {} {}", dst, a, b),
+            Instr::Equal { dst, a, b } => write!(f, "EQUAL r{} {} {}", dst, a, b),
+            Instr::Greater { dst, a, b } => write!(f, "GREATER r{} {} {}", dst, a, b),
+            Instr::Less { dst, a, b } => write!(f, "LESS r{} {} {}", dst, a, b),
+            Instr::GetGlobal { dst, slot } => write!(f, "GET_GLOBAL r{} g{}", dst, slot),
+            Instr::SetGlobal { slot, src } => write!(f, "SET_GLOBAL g{} {}", slot, src),
+            Instr::DefineGlobal { slot, src } => write!(f, "DEFINE_GLOBAL g{} {}", slot, src),
+            Instr::Jump { target } => write!(f, "JUMP {:04}", target),
+            Instr::JumpIfFalse { cond, target } => write!(f, "JUMP_IF_FALSE {} {:04}", cond, target),
+            Instr::Call { base, args } => write!(f, "CALL r{} {}", base, args),
+            Instr::TailCall { base, args } => write!(f, "TAIL_CALL r{} {}", base, args),
+            Instr::Return { src } => write!(f, "RETURN {}", src),
+            Instr::Print { src } => write!(f, "PRINT {}", src),
+            Instr::BuildList { dst, start, count } => write!(f, "BUILD_LIST r{} r{} {}", dst, start, count),
+            Instr::GetIndex { dst, target, index } => write!(f, "GET_INDEX r{} {} {}", dst, target, index),
+            Instr::SetIndex { dst, list, index, value } => {
+                write!(f, "SET_INDEX r{} {} {} {}", dst, list, index, value)
+            }
+        }
+    }
+}
+
+/// The register translation of `function` and every function nested in it,
+/// in the same layout as `function_listing`. Globals and constants are named
+/// after each instruction that uses them.
+pub fn register_listing(function: &ObjFunction, globals: &Globals) -> String {
+    let code = translate(function);
+    let mut out = format!("== {} ({} registers) ==\n", function.name.s, code.registers);
+    for (pc, instr) in code.code.iter().enumerate() {
+        let line = function.chunk.line_at(code.origins[pc]);
+        out.push_str(&format!("{:04} {:?} {}\n", pc, line, instr));
+        for name in operand_names(instr, &code, globals) {
+            out.push_str(&format!("{}\n", name));
+        }
+    }
+    for constant in &function.chunk.constants {
+        if let Value::FUNCTION(nested) = constant {
+            out.push('\n');
+            out.push_str(&register_listing(nested, globals));
+        }
+    }
+    out
+}
+
+fn operand_names(instr: &Instr, code: &RegisterCode, globals: &Globals) -> Vec<String> {
+    let constant = |operand: Operand| match operand {
+        Operand::Const(idx) => Some(code.constants[idx as usize].to_string()),
+        Operand::Reg(_) => None,
+    };
+    match *instr {
+        Instr::GetGlobal { slot, .. } => vec![globals.name(slot as usize).s.clone()],
+        Instr::SetGlobal { slot, src } | Instr::DefineGlobal { slot, src } => {
+            let mut names = vec![globals.name(slot as usize).s.clone()];
+            names.extend(constant(src));
+            names
+        }
+        Instr::Move { src, .. }
+        | Instr::Negate { src, .. }
+        | Instr::Not { src, .. }
+        | Instr::Return { src }
+        | Instr::Print { src } => constant(src).into_iter().collect(),
+        Instr::Add { a, b, .. }
+        | Instr::Subtract { a, b, .. }
+        | Instr::Multiply { a, b, .. }
+        | Instr::Divide { a, b, .. }
+        | Instr::Mod { a, b, .. }
+        | Instr::Equal { a, b, .. }
+        | Instr::Greater { a, b, .. }
+        | Instr::Less { a, b, .. }
+        | Instr::GetIndex { target: a, index: b, .. } => constant(a).into_iter().chain(constant(b)).collect(),
+        Instr::SetIndex { list, index, value, .. } => {
+            constant(list).into_iter().chain(constant(index)).chain(constant(value)).collect()
+        }
+        Instr::JumpIfFalse { cond, .. } => constant(cond).into_iter().collect(),
+        Instr::Jump { .. } | Instr::Call { .. } | Instr::TailCall { .. } | Instr::BuildList { .. } => Vec::new(),
+    }
+}
@@ -0,0 +1,247 @@
+use std::{io::Cursor, path::Path};
+
+use crate::{
+    compiler::compile,
+    gc::{Gc, GcRef},
+    globals::Globals,
+    object::ObjFunction,
+    value::Value,
+    vm::{output::MemoryOutput, Backend, InterpretError, Vm},
+};
+
+use super::{register_listing, translate};
+
+/// What the script and its tests printed, how each test went, and how the
+/// script ended.
+fn run(source: &str, backend: Backend) -> String {
+    let out = MemoryOutput::new();
+    let mut vm = Vm::builder().output(out.clone()).input(Cursor::new("")).seed(0).backend(backend).build();
+    let ended = match vm.test(source.to_string()) {
+        Ok(outcomes) => outcomes
+            .iter()
+            .map(|outcome| format!("test {}: {:?}\n", outcome.name, outcome.result))
+            .collect(),
+        Err(InterpretError::InterpretCompileError(msg)) => panic!("{}", msg),
+        Err(err) => format!("{}", err),
+    };
+    out.contents() + &ended
+}
+
+fn same_on_both(source: &str) -> String {
+    let stack = run(source, Backend::Stack);
+    assert_eq!(run(source, Backend::Register), stack, "for {}", source);
+    stack
+}
+
+/// `source` compiled, with the heap and globals it was compiled against.
+fn compiled(source: &str) -> (Gc, Globals, GcRef<ObjFunction>) {
+    let mut gc = Gc::new();
+    let mut globals = Globals::new();
+    let function = compile(source.to_string(), &mut gc, &mut globals).unwrap();
+    (gc, globals, function)
+}
+
+fn nested(function: GcRef<ObjFunction>) -> impl Iterator<Item = GcRef<ObjFunction>> {
+    let constants = function.chunk.constants.clone();
+    constants.into_iter().filter_map(|constant| match constant {
+        Value::FUNCTION(nested) => Some(nested),
+        _ => None,
+    })
+}
+
+/// Instructions in `function` and the functions nested in it: (stack, register).
+fn counts(function: GcRef<ObjFunction>) -> (usize, usize) {
+    let mut total = (function.chunk.instructions().count(), translate(&function).code.len());
+    for nested in nested(function) {
+        let (stack, register) = counts(nested);
+        total.0 += stack;
+        total.1 += register;
+    }
+    total
+}
+
+#[test]
+fn golden_scripts_run_the_same() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let source = std::fs::read_to_string(&path).unwrap();
+        if path.file_name().unwrap() == "compile_error.lh" {
+            continue;
+        }
+        same_on_both(&source);
+    }
+}
+
+#[test]
+fn benchmark_scripts_run_the_same() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/scripts");
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let source = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+        same_on_both(&source);
+    }
+}
+
+#[test]
+fn locals_and_assignments() {
+    same_on_both(
+        "fn f(a, b) { let c = a; let d = b; c = d; d = a + b; a = c = d * 2; print a; print b; print c; print d; return c - a; }
+         print f(1, 2);
+         { let x = 1; let y = x; x = 5; print y; print x; let z = x = y; print z; print x; }
+         { let n = 3; n = n; print n; let m = -n; print m; print !m; }",
+    );
+}
+
+#[test]
+fn branches_loops_and_logic() {
+    same_on_both(
+        "let out = 0;
+         for (let i = 0; i < 10; i = i + 1) {
+           let odd = false;
+           if (i > 4 and i < 8 or i == 1) { odd = true; } else { out = out - 1; }
+           while (odd) { out = out + i; odd = !odd; }
+         }
+         print out;
+         print nil or \"x\"; print false and 1; print 1 and 2 or 3;
+         fn pick(n) { if (n < 0) return \"neg\"; else if (n == 0) return \"zero\"; return \"pos\"; }
+         print pick(-1) + pick(0) + pick(1);",
+    );
+}
+
+#[test]
+fn calls_natives_lists_and_strings() {
+    same_on_both(
+        "fn fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+         print fib(15);
+         fn make(n) { let xs = []; for (let i = 0; i < n; i = i + 1) push(xs, [i, \"n\" + chr(65 + i)]); return xs; }
+         let xs = make(5);
+         print xs; print len(xs); print xs[2][1] + \"!\";
+         xs[0] = xs[1][0] = \"set\"; print xs[0]; print xs[1];
+         let s = \"hello\"; print s[1]; print s > \"abc\"; print s < \"abc\";
+         fn apply(f, x) { return f(x); } print apply(len, [1, 2, 3]); print apply(fib, 10);",
+    );
+}
+
+#[test]
+fn runtime_errors_are_the_same() {
+    for source in [
+        "print nope;",
+        "nope = 1;",
+        "print 1 + true;",
+        "print \"a\" - 1;",
+        "print -\"a\";",
+        "print [1][3];",
+        "print 1[0];",
+        "let s = \"abc\"; s[0] = \"x\";",
+        "fn f(a) { return a; } f();",
+        "let x = 1; x();",
+        "fn f(n) { return 1 + f(n + 1); } f(0);",
+        "print len(1, 2);",
+        "print 1; { let a = [1]; print a[0] + a; }",
+        "fn f() { assert_eq(1, 2); }\nf();",
+        "exit(3);",
+    ] {
+        same_on_both(source);
+    }
+}
+
+#[test]
+fn tail_calls_run_the_same() {
+    let printed = same_on_both(
+        "fn count(n, acc) { if (n == 0) return acc; return count(n - 1, acc + [n][0] - n + 1); }
+         fn even(n) { if (n == 0) return true; return odd(n - 1); }
+         fn odd(n) { if (n == 0) return false; return even(n - 1); }
+         fn first(xs) { return len(xs) and xs[0] or chr(len(xs) + 65); }
+         fn wide(a, b, c) { let d = a + b; if (c == 0) return d; return wide(d, b, c - 1); }
+         print count(20000, 0); print even(20001); print first([]); print first([7]); print wide(0, 2, 10);",
+    );
+    assert_eq!(printed, "20000\nfalse\nA\n7\n22\n");
+}
+
+#[test]
+fn collection_during_deep_calls_keeps_registers_alive() {
+    let printed = same_on_both(
+        "fn build(depth) {
+           let here = [depth, [depth, depth]];
+           if (depth == 0) {
+             let junk = [];
+             for (let i = 0; i < 50000; i = i + 1) push(junk, [i]);
+             return len(junk);
+           }
+           let below = build(depth - 1);
+           return below + here[0] + len(here[1]);
+         }
+         for (let round = 0; round < 3; round = round + 1) print build(50);",
+    );
+    assert_eq!(printed, "51375\n51375\n51375\n");
+}
+
+#[test]
+fn evaluate_uses_the_register_backend() {
+    let mut vm = Vm::builder().backend(Backend::Register).build();
+    vm.interpret("fn sq(x) { return x * x; }".to_string()).unwrap();
+    let value = vm.evaluate("sq(3) + 1".to_string()).unwrap();
+    assert_eq!(value.get_number(), Some(10.0));
+}
+
+#[test]
+fn translations_count_toward_the_heap() {
+    let heap_after = |backend| {
+        let mut vm = Vm::builder().backend(backend).gc_threshold(usize::MAX).build();
+        vm.interpret("fn count(n) { let i = 0; while (i < n) i = i + 1; return i; }\ncount(3);".to_string()).unwrap();
+        vm.gc_stats().bytes_allocated
+    };
+    let (stack, register) = (heap_after(Backend::Stack), heap_after(Backend::Register));
+    // the script and `count` were translated
+    assert!(register >= stack + 2 * std::mem::size_of::<super::RegisterCode>(), "{} and {}", stack, register);
+}
+
+#[test]
+fn loops_take_fewer_instructions() {
+    let (_gc, _globals, script) = compiled(
+        "fn count(n) { let sum = 0; let i = 0; while (i < n) { sum = sum + i; i = i + 1; } return sum; }",
+    );
+    let count = nested(script).next().unwrap();
+    let listing: Vec<String> = translate(&count).code.iter().map(|instr| instr.to_string()).collect();
+    // each time round: the test, its jump, the two assignments and the jump back
+    assert_eq!(
+        listing,
+        [
+            "MOVE r2 k0",
+            "MOVE r3 k1",
+            "LESS r4 r3 r1",
+            "JUMP_IF_FALSE r4 0007",
+            "ADD r2 r2 r3",
+            "ADD r3 r3 k2",
+            "JUMP 0002",
+            "RETURN r2",
+        ]
+    );
+    let (stack, register) = counts(count);
+    assert!(register * 2 <= stack, "{} register instructions for {} stack ones", register, stack);
+}
+
+#[test]
+fn programs_take_fewer_instructions() {
+    for path in ["benches/scripts/fib.lh", "benches/scripts/loop.lh", "tests/golden/functions.lh"] {
+        let source = std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap();
+        let (_gc, _globals, script) = compiled(&source);
+        let (stack, register) = counts(script);
+        assert!(register < stack, "{}: {} register instructions for {} stack ones", path, register, stack);
+    }
+}
+
+#[test]
+fn listing_names_globals_and_constants() {
+    let (_gc, globals, script) = compiled("let greeting = \"hi\"; print greeting + \"!\";");
+    let listing = register_listing(&script, &globals);
+    assert_eq!(
+        listing,
+        "== script (3 registers) ==\n\
+         0000 Lineno(1) DEFINE_GLOBAL g0 k0\ngreeting\nhi\n\
+         0001 Lineno(1) GET_GLOBAL r1 g0\ngreeting\n\
+         0002 Lineno(1) ADD r1 r1 k1\n!\n\
+         0003 Lineno(1) PRINT r1\n\
+         0004 Lineno(1) RETURN k2\nnil\n"
+    );
+}
@@ -0,0 +1,371 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{bytecode::Opcode, object::ObjFunction, value::Value};
+
+use super::{Instr, Operand, RegisterCode};
+
+/// What the translation knows about one slot of the stack the bytecode
+/// works on: stack slot `n` of the frame is register `n`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Entry {
+    // the value is in the slot's own register
+    Home,
+    // the value hasn't been copied into the slot's register; instructions
+    // read it from the operand instead. A register operand is always a slot
+    // below this one.
+    Pending(Operand),
+}
+
+struct Translation {
+    code: Vec<Instr>,
+    origins: Vec<usize>,
+    constants: Vec<Value>,
+    stack: Vec<Entry>,
+    registers: usize,
+    // offset of the bytecode instruction being translated
+    origin: usize,
+    // first instruction that only runs straight after the ones before it
+    block_start: usize,
+}
+
+impl Translation {
+    fn emit(&mut self, instr: Instr) {
+        self.code.push(instr);
+        self.origins.push(self.origin);
+    }
+
+    fn push(&mut self, entry: Entry) -> u32 {
+        self.stack.push(entry);
+        self.registers = self.registers.max(self.stack.len());
+        (self.stack.len() - 1) as u32
+    }
+
+    fn operand(&self, slot: usize) -> Operand {
+        match self.stack[slot] {
+            Entry::Home => Operand::Reg(slot as u32),
+            Entry::Pending(operand) => operand,
+        }
+    }
+
+    fn pop(&mut self) -> Operand {
+        let operand = self.operand(self.stack.len() - 1);
+        self.stack.pop();
+        operand
+    }
+
+    fn top(&self) -> usize {
+        self.stack.len() - 1
+    }
+
+    /// Copy the value of `slot` into its register.
+    fn materialise(&mut self, slot: usize) {
+        if let Entry::Pending(src) = self.stack[slot] {
+            self.emit(Instr::Move { dst: slot as u32, src });
+            self.stack[slot] = Entry::Home;
+        }
+    }
+
+    /// Put every value in its register, so the code after a jump can find
+    /// them wherever it came from.
+    fn flush(&mut self) {
+        for slot in 0..self.stack.len() {
+            self.materialise(slot);
+        }
+    }
+
+    /// A constant operand for `value`, added to the constants if the chunk
+    /// doesn't have it.
+    fn literal(&mut self, value: Value) -> Operand {
+        let idx = match self.constants.iter().position(|constant| literal_eq(constant, &value)) {
+            Some(idx) => idx,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        };
+        Operand::Const(idx as u32)
+    }
+
+    fn binary(&mut self, make: fn(u32, Operand, Operand) -> Instr) {
+        let b = self.pop();
+        let a = self.pop();
+        let dst = self.push(Entry::Home);
+        self.emit(make(dst, a, b));
+    }
+
+    fn unary(&mut self, make: fn(u32, Operand) -> Instr) {
+        let src = self.pop();
+        let dst = self.push(Entry::Home);
+        self.emit(make(dst, src));
+    }
+
+    fn set_local(&mut self, local: usize) {
+        let top = self.top();
+        let reads_local = Operand::Reg(local as u32);
+        for slot in local + 1..top {
+            if self.stack[slot] == Entry::Pending(reads_local) {
+                self.materialise(slot);
+            }
+        }
+        // the instruction that just worked the value out can leave it in the
+        // local straight away
+        let retargeted = self.stack[top] == Entry::Home
+            && self.code.len() > self.block_start
+            && match self.code.last_mut().and_then(Instr::dst_mut) {
+                Some(dst) if *dst == top as u32 => {
+                    *dst = local as u32;
+                    true
+                }
+                _ => false,
+            };
+        if !retargeted {
+            let src = self.operand(top);
+            if src != reads_local {
+                self.emit(Instr::Move { dst: local as u32, src });
+            }
+        }
+        self.stack[local] = Entry::Home;
+        self.stack[top] = Entry::Pending(reads_local);
+    }
+}
+
+// nil and the booleans are the only literals the translation adds
+fn literal_eq(a: &Value, b: &Value) -> bool {
+    matches!(
+        (a, b),
+        (Value::NIL, Value::NIL) | (Value::BOOL(true), Value::BOOL(true)) | (Value::BOOL(false), Value::BOOL(false))
+    )
+}
+
+/// Translate `function`'s bytecode for the register backend.
+///
+/// The bytecode's stack heights are known at every instruction, so each
+/// stack slot becomes a register. Reading a local or a constant doesn't
+/// copy it anywhere: the instruction that uses it reads it where it is.
+/// Results are written straight into the local they are assigned to, and
+/// pops disappear. Values are only copied into their own registers where
+/// control flow meets, or where calls and lists need them side by side.
+pub fn translate(function: &ObjFunction) -> RegisterCode {
+    let chunk = &function.chunk;
+    let mut decoded = Vec::new();
+    let mut instructions = chunk.instructions().peekable();
+    while let Some((offset, op)) = instructions.next() {
+        let next = instructions.peek().map_or(chunk.code.len(), |(next, _)| *next);
+        decoded.push((offset, op, next));
+    }
+    let heights = stack_heights(&decoded, function.arity as usize + 1);
+    let targets: BTreeSet<usize> = decoded
+        .iter()
+        .filter_map(|&(_, op, next)| match op {
+            Opcode::OP_JUMP(jump) | Opcode::OP_JUMP_IF_FALSE(jump) => Some(next + jump),
+            Opcode::OP_LOOP(jump) => Some(next - jump),
+            _ => None,
+        })
+        .collect();
+
+    let mut t = Translation {
+        code: Vec::new(),
+        origins: Vec::new(),
+        constants: chunk.constants.clone(),
+        stack: vec![Entry::Home; function.arity as usize + 1],
+        registers: function.arity as usize + 1,
+        origin: 0,
+        block_start: 0,
+    };
+    // where the jump targets ended up
+    let mut pcs: HashMap<usize, usize> = HashMap::new();
+    // forward jumps waiting for their target's pc
+    let mut fixups: Vec<(usize, usize)> = Vec::new();
+
+    for (offset, op, next) in decoded {
+        t.origin = offset;
+        let height = match heights.get(&offset) {
+            Some(&height) => height,
+            // nothing jumps here
+            None => continue,
+        };
+        if targets.contains(&offset) {
+            t.flush();
+            t.block_start = t.code.len();
+            pcs.insert(offset, t.code.len());
+        }
+        if t.stack.len() != height {
+            // the code before jumped away or returned
+            t.stack = vec![Entry::Home; height];
+        }
+        match op {
+            Opcode::OP_CONSTANT(idx) => {
+                t.push(Entry::Pending(Operand::Const(idx as u32)));
+            }
+            Opcode::OP_TRUE | Opcode::OP_FALSE | Opcode::OP_NIL => {
+                let value = match op {
+                    Opcode::OP_TRUE => Value::BOOL(true),
+                    Opcode::OP_FALSE => Value::BOOL(false),
+                    _ => Value::NIL,
+                };
+                let literal = t.literal(value);
+                t.push(Entry::Pending(literal));
+            }
+            Opcode::OP_NEGATE => t.unary(|dst, src| Instr::Negate { dst, src }),
+            Opcode::OP_NOT => t.unary(|dst, src| Instr::Not { dst, src }),
+            Opcode::OP_ADD => t.binary(|dst, a, b| Instr::Add { dst, a, b }),
+            Opcode::OP_SUBSTRACT => t.binary(|dst, a, b| Instr::Subtract { dst, a, b }),
+            Opcode::OP_MULTIPLY => t.binary(|dst, a, b| Instr::Multiply { dst, a, b }),
+            Opcode::OP_DIVIDE => t.binary(|dst, a, b| Instr::Divide { dst, a, b }),
+            Opcode::OP_MOD => t.binary(|dst, a, b| Instr::Mod { dst, a, b }),
+            Opcode::OP_EQ => t.binary(|dst, a, b| Instr::Equal { dst, a, b }),
+            Opcode::OP_GT => t.binary(|dst, a, b| Instr::Greater { dst, a, b }),
+            Opcode::OP_LT => t.binary(|dst, a, b| Instr::Less { dst, a, b }),
+            Opcode::OP_GET_LOCAL(local) => {
+                t.materialise(local);
+                t.push(Entry::Pending(Operand::Reg(local as u32)));
+            }
+            Opcode::OP_SET_LOCAL(local) => t.set_local(local),
+            Opcode::OP_GET_GLOBAL(slot) => {
+                let dst = t.push(Entry::Home);
+                t.emit(Instr::GetGlobal { dst, slot: slot as u32 });
+            }
+            Opcode::OP_SET_GLOBAL(slot) => {
+                let src = t.operand(t.top());
+                t.emit(Instr::SetGlobal { slot: slot as u32, src });
+            }
+            Opcode::OP_DEFINE_GLOBAL(slot) => {
+                let src = t.pop();
+                t.emit(Instr::DefineGlobal { slot: slot as u32, src });
+            }
+            Opcode::OP_PRINT => {
+                let src = t.pop();
+                t.emit(Instr::Print { src });
+            }
+            Opcode::OP_POP => {
+                t.pop();
+            }
+            Opcode::OP_RETURN => {
+                let src = t.pop();
+                t.emit(Instr::Return { src });
+                t.stack.clear();
+            }
+            Opcode::OP_JUMP(jump) | Opcode::OP_JUMP_IF_FALSE(jump) => {
+                t.flush();
+                fixups.push((t.code.len(), next + jump));
+                if let Opcode::OP_JUMP(_) = op {
+                    t.emit(Instr::Jump { target: 0 });
+                    t.stack.clear();
+                } else {
+                    let cond = Operand::Reg(t.top() as u32);
+                    t.emit(Instr::JumpIfFalse { cond, target: 0 });
+                }
+            }
+            Opcode::OP_LOOP(jump) => {
+                t.flush();
+                let target = pcs[&(next - jump)] as u32;
+                t.emit(Instr::Jump { target });
+                t.stack.clear();
+            }
+            Opcode::OP_CALL(args) | Opcode::OP_TAIL_CALL(args) => {
+                let base = t.stack.len() - 1 - args as usize;
+                for slot in base..t.stack.len() {
+                    t.materialise(slot);
+                }
+                t.stack.truncate(base + 1);
+                let base = base as u32;
+                match op {
+                    Opcode::OP_CALL(_) => t.emit(Instr::Call { base, args }),
+                    _ => t.emit(Instr::TailCall { base, args }),
+                }
+            }
+            Opcode::OP_BUILD_LIST(count) => {
+                let start = t.stack.len() - count;
+                for slot in start..t.stack.len() {
+                    t.materialise(slot);
+                }
+                t.stack.truncate(start);
+                let dst = t.push(Entry::Home);
+                t.emit(Instr::BuildList { dst, start: start as u32, count: count as u32 });
+            }
+            Opcode::OP_GET_INDEX => {
+                let index = t.pop();
+                let target = t.pop();
+                let dst = t.push(Entry::Home);
+                t.emit(Instr::GetIndex { dst, target, index });
+            }
+            Opcode::OP_SET_INDEX => {
+                let value = t.pop();
+                let index = t.pop();
+                let list = t.pop();
+                let dst = t.push(Entry::Home);
+                t.emit(Instr::SetIndex { dst, list, index, value });
+            }
+        }
+    }
+
+    for (pc, target) in fixups {
+        let target_pc = pcs[&target] as u32;
+        match &mut t.code[pc] {
+            Instr::Jump { target } | Instr::JumpIfFalse { target, .. } => *target = target_pc,
+            _ => unreachable!("fixup for a non-jump"),
+        }
+    }
+    RegisterCode {
+        code: t.code,
+        origins: t.origins,
+        constants: t.constants,
+        registers: t.registers,
+    }
+}
+
+/// The height of the stack before every instruction that can run, found by
+/// following each path from the start of the function.
+fn stack_heights(decoded: &[(usize, Opcode, usize)], start: usize) -> HashMap<usize, usize> {
+    let at: HashMap<usize, (Opcode, usize)> = decoded.iter().map(|&(offset, op, next)| (offset, (op, next))).collect();
+    let mut heights = HashMap::new();
+    let mut pending = vec![(0, start)];
+    while let Some((offset, height)) = pending.pop() {
+        if heights.insert(offset, height).is_some() {
+            continue;
+        }
+        let (op, next) = match at.get(&offset) {
+            Some(&decoded) => decoded,
+            None => continue,
+        };
+        let after = match op {
+            Opcode::OP_CONSTANT(_)
+            | Opcode::OP_TRUE
+            | Opcode::OP_FALSE
+            | Opcode::OP_NIL
+            | Opcode::OP_GET_LOCAL(_)
+            | Opcode::OP_GET_GLOBAL(_) => height + 1,
+            Opcode::OP_NEGATE | Opcode::OP_NOT | Opcode::OP_SET_LOCAL(_) | Opcode::OP_SET_GLOBAL(_) => height,
+            Opcode::OP_ADD
+            | Opcode::OP_SUBSTRACT
+            | Opcode::OP_MULTIPLY
+            | Opcode::OP_DIVIDE
+            | Opcode::OP_MOD
+            | Opcode::OP_EQ
+            | Opcode::OP_GT
+            | Opcode::OP_LT
+            | Opcode::OP_DEFINE_GLOBAL(_)
+            | Opcode::OP_PRINT
+            | Opcode::OP_POP
+            | Opcode::OP_GET_INDEX => height - 1,
+            Opcode::OP_SET_INDEX => height - 2,
+            Opcode::OP_CALL(args) | Opcode::OP_TAIL_CALL(args) => height - args as usize,
+            Opcode::OP_BUILD_LIST(count) => height + 1 - count,
+            Opcode::OP_RETURN => continue,
+            Opcode::OP_JUMP(jump) => {
+                pending.push((next + jump, height));
+                continue;
+            }
+            Opcode::OP_LOOP(jump) => {
+                pending.push((next - jump, height));
+                continue;
+            }
+            Opcode::OP_JUMP_IF_FALSE(jump) => {
+                pending.push((next + jump, height));
+                height
+            }
+        };
+        pending.push((next, after));
+    }
+    heights
+}
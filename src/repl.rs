@@ -1,17 +1,64 @@
+use std::borrow::Cow;
+use std::env;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
-use crate::vm::Vm;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+
+use crate::chunk::disassemble::chunk_listing;
+use crate::compiler::compile_expression;
+use crate::gc::Gc;
+use crate::globals::Globals;
+use crate::lexer::Lexer;
+use crate::natives;
+use crate::source;
+use crate::token::{TokenType, KEYWORDS};
+use crate::value::Value;
+use crate::vm::{InterpretError, Vm};
+
+#[cfg(test)]
+mod tests;
 
-pub fn start() {
-    let mut rl = Editor::<()>::new();
+/// Run the prompt until end of input, or until a script calls `exit`;
+/// returns the process exit code.
+pub fn start() -> i32 {
+    let mut rl = Editor::<ReplHelper>::new();
+    rl.set_helper(Some(ReplHelper::default()));
+    let history = history_path();
+    if let Some(path) = &history {
+        // there is no history on the first run
+        let _ = rl.load_history(path);
+    }
     let mut interpreter = Vm::init_vm();
+    let mut exit_code = 0;
     loop {
         let readline = rl.readline(">> ");
         match readline {
             Ok(line) => {
-                interpreter.interpret(line).unwrap_or_else(|err| {
-                    println!("{:?}", err);
-                });
+                if line.trim().is_empty() {
+                    continue;
+                }
+                rl.add_history_entry(line.as_str());
+                let output = match line.trim().strip_prefix(':') {
+                    Some(command) => meta_command(&mut interpreter, command).map(Some),
+                    None => eval(&mut interpreter, line),
+                };
+                match output {
+                    Ok(Some(output)) => println!("{}", output),
+                    Ok(None) => {}
+                    Err(code) => {
+                        exit_code = code;
+                        break;
+                    }
+                }
+                if let Some(helper) = rl.helper_mut() {
+                    helper.globals = interpreter.globals().map(|(name, _)| name.s.clone()).collect();
+                }
             }
             Err(ReadlineError::Interrupted) => break,
             Err(ReadlineError::Eof) => break,
@@ -20,4 +67,276 @@ pub fn start() {
             }
         }
     }
+    if let Some(path) = &history {
+        if let Err(err) = rl.save_history(path) {
+            eprintln!("could not save history to {}: {}", path.display(), err);
+        }
+    }
+    exit_code
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".lockhart_history"))
+}
+
+/// Run one REPL entry. A lone expression has its value echoed back; anything
+/// else runs as statements. Returns what should be shown to the user, or the
+/// code passed to `exit`.
+fn eval(vm: &mut Vm, input: String) -> Result<Option<String>, i32> {
+    if is_expression(&input) {
+        return match vm.evaluate(input) {
+            Ok(Value::NIL) => Ok(None),
+            Ok(value) => Ok(Some(value.repr())),
+            Err(err) => report(err).map(Some),
+        };
+    }
+    match vm.interpret(input) {
+        Ok(()) => Ok(None),
+        Err(err) => report(err).map(Some),
+    }
+}
+
+fn report(err: InterpretError) -> Result<String, i32> {
+    match err {
+        InterpretError::InterpretExit(code) => Err(code),
+        err => Ok(err.to_string()),
+    }
+}
+
+const HELP: &str = "\
+:load <file>   run a script in the current session
+:dis <name>    disassemble a global function
+:globals       list global variables
+:gc            collect garbage and show heap statistics
+:time <code>   run code and report how long it took
+:reset         start over with a fresh VM
+:help          show this message";
+
+/// Handle a `:command` (given without its colon); returns what to show the
+/// user, or the code passed to `exit` by a loaded or timed script.
+fn meta_command(vm: &mut Vm, command: &str) -> Result<String, i32> {
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    let output = match name {
+        "load" => match source::open_source_file(arg) {
+            Ok(source) => match vm.interpret(source) {
+                Ok(()) => format!("loaded {}", arg),
+                Err(err) => report(err)?,
+            },
+            Err(err) => format!("could not read '{}': {}", arg, err),
+        },
+        "dis" => {
+            let function = vm
+                .globals()
+                .find(|(key, _)| key.s == arg)
+                .map(|(_, value)| value);
+            match function {
+                Some(Value::FUNCTION(function)) => {
+                    chunk_listing(&function.chunk, arg, vm.global_slots()).trim_end().to_string()
+                }
+                Some(value) => format!("'{}' is not a function but {}", arg, value.repr()),
+                None => format!("no global named '{}'", arg),
+            }
+        }
+        "globals" => {
+            // only what the session defined, not the builtins every VM starts with
+            let mut globals: Vec<(String, Value)> = vm
+                .globals()
+                .filter(|(key, value)| !natives::is_builtin(&key.s, value))
+                .map(|(key, value)| (key.s.clone(), value))
+                .collect();
+            globals.sort_by(|a, b| a.0.cmp(&b.0));
+            let lines: Vec<String> = globals
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value.repr()))
+                .collect();
+            lines.join("\n")
+        }
+        "gc" => {
+            let before = vm.gc_stats();
+            vm.collect_garbage();
+            let after = vm.gc_stats();
+            format!(
+                "freed {} bytes and {} objects in {:.3?}; {} bytes in {} objects live, next collection at {} bytes\n{}",
+                before.bytes_allocated - after.bytes_allocated,
+                before.objects - after.objects,
+                after.last_pause,
+                after.bytes_allocated,
+                after.objects,
+                after.next_gc,
+                after
+            )
+        }
+        #[cfg(feature = "jit")]
+        "jit" => {
+            let stats = vm.jit_stats();
+            format!(
+                "{} compiled, {} rejected; compiled code ran {} times",
+                stats.compiled, stats.rejected, stats.entered
+            )
+        }
+        "time" => {
+            let start = Instant::now();
+            let output = eval(vm, arg.to_string())?;
+            let elapsed = format!("time: {:.3?}", start.elapsed());
+            match output {
+                Some(output) => format!("{}\n{}", output, elapsed),
+                None => elapsed,
+            }
+        }
+        "reset" => {
+            *vm = Vm::init_vm();
+            "started a fresh VM".to_string()
+        }
+        #[cfg(feature = "jit")]
+        "help" => format!("{}\n:jit           show what the JIT has compiled", HELP),
+        #[cfg(not(feature = "jit"))]
+        "help" => HELP.to_string(),
+        _ => format!("unknown command ':{}', try :help", name),
+    };
+    Ok(output)
+}
+
+fn is_expression(input: &str) -> bool {
+    compile_expression(input.to_string(), &mut Gc::new(), &mut Globals::new()).is_ok()
+}
+
+/// Whether `input` closes every brace, bracket and parenthesis it opens. Strings and
+/// comments are skipped by lexing; an unterminated string also needs more input.
+fn is_complete(input: &str) -> bool {
+    let mut lexer = Lexer::new(input.to_string());
+    let mut depth: i64 = 0;
+    loop {
+        let token = lexer.next_token();
+        match token.type_ {
+            TokenType::LBRACE | TokenType::LPAREN | TokenType::LBRACKET => depth += 1,
+            TokenType::RBRACE | TokenType::RPAREN | TokenType::RBRACKET => depth -= 1,
+            TokenType::ILLEGAL if token.literal == "Unterminated string" => return false,
+            TokenType::EOF => return depth <= 0,
+            _ => {}
+        }
+    }
 }
+
+/// Completion, highlighting and multi-line validation for the line editor.
+#[derive(Default)]
+struct ReplHelper {
+    // names defined in the session, refreshed after every entry
+    globals: Vec<String>,
+}
+
+impl ReplHelper {
+    /// Keywords and globals that extend the word before `pos`, and where that word starts.
+    fn completions(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |idx| idx + 1);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return (start, Vec::new());
+        }
+        let mut candidates: Vec<String> = KEYWORDS
+            .keys()
+            .copied()
+            .chain(self.globals.iter().map(|name| name.as_str()))
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| name.to_string())
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        (start, candidates)
+    }
+}
+
+/// `line` with ANSI colours, using the lexer so highlighting matches the language exactly.
+fn highlight(line: &str) -> String {
+    let mut tokens = Vec::new();
+    let mut lexer = Lexer::new(line.to_string());
+    loop {
+        let token = lexer.next_token();
+        if token.type_ == TokenType::EOF {
+            break;
+        }
+        tokens.push(token);
+    }
+
+    let mut out = String::with_capacity(line.len());
+    let mut written = 0;
+    for (idx, token) in tokens.iter().enumerate() {
+        if token.offset < written {
+            continue;
+        }
+        out.push_str(&line[written..token.offset]);
+        // a token runs until the next one, minus the whitespace in between
+        let next = tokens.get(idx + 1).map_or(line.len(), |next| next.offset);
+        let text = line[token.offset..next].trim_end();
+        // `test` is only a keyword in front of a test's name
+        let starts_test = token.type_ == TokenType::IDENT
+            && token.literal == "test"
+            && tokens.get(idx + 1).is_some_and(|next| next.type_ == TokenType::STRING);
+        let code = if starts_test { Some("35") } else { colour(token.type_) };
+        match code {
+            Some(code) => out.push_str(&format!("\x1b[{}m{}\x1b[0m", code, text)),
+            None => out.push_str(text),
+        }
+        written = token.offset + text.len();
+    }
+    out.push_str(&line[written..]);
+    out
+}
+
+fn colour(type_: TokenType) -> Option<&'static str> {
+    match type_ {
+        TokenType::LET
+        | TokenType::FUNCTION
+        | TokenType::IF
+        | TokenType::ELSE
+        | TokenType::FOR
+        | TokenType::WHILE
+        | TokenType::PRINT
+        | TokenType::RETURN
+        | TokenType::AND
+        | TokenType::OR => Some("35"),
+        TokenType::NUM | TokenType::TRUE | TokenType::FALSE | TokenType::NIL => Some("33"),
+        TokenType::STRING => Some("32"),
+        TokenType::COMMENT => Some("90"),
+        TokenType::ILLEGAL => Some("31"),
+        _ => None,
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_complete(ctx.input()) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.completions(line, pos))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight(line))
+    }
+
+    fn highlight_char(&self, _: &str, _: usize) -> bool {
+        true
+    }
+}
+
+impl Helper for ReplHelper {}
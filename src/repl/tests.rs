@@ -0,0 +1,119 @@
+use crate::vm::Vm;
+
+use super::{eval, highlight, is_complete, meta_command, ReplHelper};
+
+#[test]
+fn waits_for_balanced_braces_and_parens() {
+    assert!(is_complete("print 1;"));
+    assert!(!is_complete("fn f(a) {"));
+    assert!(!is_complete("fn f(a) {\n  if (a) {\n    return 1;\n  }"));
+    assert!(is_complete("fn f(a) {\n  if (a) {\n    return 1;\n  }\n}"));
+    assert!(!is_complete("print (1 +"));
+    assert!(!is_complete("let xs = [1,"));
+    // stray closers are left for the compiler to report
+    assert!(is_complete("}"));
+}
+
+#[test]
+fn brackets_in_strings_and_comments_dont_count() {
+    assert!(is_complete("print \"{(\";"));
+    assert!(is_complete("let a = 1; // {"));
+    assert!(!is_complete("print \"unterminated"));
+}
+
+fn session(inputs: &[&str]) -> Vec<Option<String>> {
+    let mut vm = Vm::init_vm();
+    inputs.iter().map(|input| eval(&mut vm, input.to_string()).unwrap()).collect()
+}
+
+#[test]
+fn echoes_expression_values() {
+    let outputs = session(&["1 + 2", "let s = \"a\";", "s + \"b\";", "1 < 2", "nil"]);
+    assert_eq!(
+        outputs,
+        vec![Some("3".to_string()), None, Some("\"ab\"".to_string()), Some("true".to_string()), None]
+    );
+}
+
+#[test]
+fn multi_line_definitions_persist() {
+    let outputs = session(&["fn add(a, b) {\n  return a + b;\n}", "add(2, 3)"]);
+    assert_eq!(outputs, vec![None, Some("5".to_string())]);
+}
+
+#[test]
+fn errors_are_reported_and_the_session_continues() {
+    let outputs = session(&["let x = 1;", "x + nil", "undefined;", "x"]);
+    assert!(outputs[1].as_ref().unwrap().starts_with("Runtime error: "));
+    assert!(outputs[2].as_ref().unwrap().starts_with("Runtime error: "));
+    assert_eq!(outputs[3], Some("1".to_string()));
+}
+
+#[test]
+fn meta_commands_inspect_the_session() {
+    let mut vm = Vm::init_vm();
+    eval(&mut vm, "fn add(a, b) { return a + b; }\nlet name = \"lh\";".to_string()).unwrap();
+
+    assert_eq!(meta_command(&mut vm, "globals").unwrap(), "add = <fn add>\nname = \"lh\"");
+    let listing = meta_command(&mut vm, "dis add").unwrap();
+    assert!(listing.starts_with("== add =="), "{}", listing);
+    assert!(listing.contains("OP_ADD"));
+    assert_eq!(meta_command(&mut vm, "dis name").unwrap(), "'name' is not a function but \"lh\"");
+    assert_eq!(meta_command(&mut vm, "dis nope").unwrap(), "no global named 'nope'");
+
+    let timed = meta_command(&mut vm, "time add(1, 2)").unwrap();
+    assert!(timed.starts_with("3\ntime: "), "{}", timed);
+    assert!(meta_command(&mut vm, "gc").unwrap().starts_with("freed "));
+
+    assert_eq!(meta_command(&mut vm, "reset").unwrap(), "started a fresh VM");
+    assert_eq!(meta_command(&mut vm, "globals").unwrap(), "");
+    assert!(meta_command(&mut vm, "bogus").unwrap().contains("try :help"));
+}
+
+#[test]
+fn load_runs_a_file_in_the_session() {
+    let path = std::env::temp_dir().join(format!("lockhart_repl_{}.lh", std::process::id()));
+    std::fs::write(&path, "#!/usr/bin/env lockhart\nlet loaded = 42;\n").unwrap();
+    let mut vm = Vm::init_vm();
+    let output = meta_command(&mut vm, &format!("load {}", path.display())).unwrap();
+    assert!(output.starts_with("loaded "), "{}", output);
+    assert_eq!(eval(&mut vm, "loaded".to_string()), Ok(Some("42".to_string())));
+    assert!(meta_command(&mut vm, "load /nonexistent.lh").unwrap().starts_with("could not read"));
+}
+
+#[test]
+fn completes_keywords_and_globals() {
+    let helper = ReplHelper {
+        globals: vec!["printer".to_string(), "total".to_string()],
+    };
+    assert_eq!(helper.completions("pri", 3), (0, vec!["print".to_string(), "printer".to_string()]));
+    assert_eq!(helper.completions("let x = to", 10), (8, vec!["total".to_string()]));
+    assert_eq!(helper.completions("whi", 2), (0, vec!["while".to_string()]));
+    assert_eq!(helper.completions("f(", 2), (2, Vec::<String>::new()));
+}
+
+#[test]
+fn highlights_tokens_by_kind() {
+    assert_eq!(
+        highlight("let s = \"a b\"; // c"),
+        "\x1b[35mlet\x1b[0m s = \x1b[32m\"a b\"\x1b[0m; \x1b[90m// c\x1b[0m"
+    );
+    assert_eq!(highlight("print  nil"), "\x1b[35mprint\x1b[0m  \x1b[33mnil\x1b[0m");
+    assert_eq!(highlight("\"open"), "\x1b[31m\"open\x1b[0m");
+}
+
+#[test]
+fn highlights_non_ascii_identifiers_without_panicking() {
+    assert_eq!(
+        highlight("let é = 1;"),
+        "\x1b[35mlet\x1b[0m \x1b[31mé\x1b[0m = \x1b[33m1\x1b[0m;"
+    );
+}
+
+#[test]
+fn exit_ends_the_session_with_its_code() {
+    let mut vm = Vm::init_vm();
+    assert_eq!(eval(&mut vm, "exit(3);".to_string()), Err(3));
+    assert_eq!(eval(&mut vm, "exit(0)".to_string()), Err(0));
+    assert_eq!(meta_command(&mut vm, "time exit(2)"), Err(2));
+}
@@ -1,28 +1,283 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-
-use crate::vm::Vm;
-
-pub fn open_source_file(file_name: &str) -> String {
-    let path = Path::new(file_name);
-    let display = path.display();
-    let mut file = match File::open(path) {
-        Err(err) => panic!("Could not open file {}: {}", display, err),
-        Ok(file) => file,
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    ast,
+    chunk::disassemble::{function_json, function_listing},
+    gc::{Gc, GcConfig},
+    globals::Globals,
+    register::register_listing,
+    vm::{Backend, Frontend, InterpretError, Vm, VmBuilder},
+};
+
+#[cfg(test)]
+mod tests;
+
+// exit codes, following sysexits.h
+pub const EXIT_USAGE: i32 = 64;
+pub const EXIT_COMPILE_ERROR: i32 = 65;
+pub const EXIT_NO_INPUT: i32 = 66;
+pub const EXIT_RUNTIME_ERROR: i32 = 70;
+
+const USAGE: &str = "\
+usage: lockhart [command] [options] <file> [args...]
+
+commands:
+  run <file> [args]    run a script (the default when no command is given);
+                       arguments after the file are passed to it as `args`
+  repl                 start the interactive prompt
+  check <file>         report compile errors without running
+  compile <file>       print the compiled bytecode as JSON
+  disassemble <file>   print a bytecode listing
+  test <paths>         run scripts and check their `// expect` comments
+  fmt, lint, lsp, dap  tooling, see the README
+
+options:
+  --frontend=ast       compile by way of the syntax tree
+  --backend=register   with run, execute on the register machine; with
+                       disassemble, list the register code
+  --emit=ast           with compile, print the syntax tree instead
+  --max-depth=<n>      with run, how deep calls may nest (default 10000)
+  --gc-stats           with run, print garbage collector statistics at exit
+  --gc-threshold=<n>   with run, bytes allocated before the first collection
+                       (default 1048576)
+  --gc-growth=<f>      with run, how many times over what survived a
+                       collection the heap grows before the next (default 2)
+  --no-jit             with run, interpret everything (builds with the jit feature)
+
+<file> may be '-' to read the script from stdin.";
+
+/// Read a script from `file_name`, or from stdin if it is `-`. A leading
+/// `#!` line is blanked so scripts can be executable without shifting line numbers.
+pub fn open_source_file(file_name: &str) -> io::Result<String> {
+    let source = if file_name == "-" {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf)?;
+        buf
+    } else {
+        fs::read_to_string(file_name)?
+    };
+    Ok(strip_shebang(source))
+}
+
+/// The error for `file` not being readable, naming it for commands that read
+/// several files.
+pub fn unreadable(file: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("could not read {}: {}", file.display(), err))
+}
+
+fn strip_shebang(source: String) -> String {
+    if !source.starts_with("#!") {
+        return source;
+    }
+    match source.find('\n') {
+        Some(end) => source[end..].to_string(),
+        None => String::new(),
+    }
+}
+
+/// Run `code` on a VM built from `vm`; with `gc_stats` the collector's
+/// statistics go to stderr afterwards.
+pub fn execute(code: String, vm: VmBuilder, gc_stats: bool) -> Result<(), InterpretError> {
+    let mut interpreter = vm.build();
+    let result = interpreter.interpret(code);
+    if gc_stats {
+        eprintln!("gc: {}", interpreter.gc_stats());
+    }
+    result
+}
+
+/// Exit code for a script that failed or called `exit`.
+pub fn exit_code(err: &InterpretError) -> i32 {
+    match err {
+        InterpretError::InterpretCompileError(_) => EXIT_COMPILE_ERROR,
+        InterpretError::InterpretRuntimeError(_) => EXIT_RUNTIME_ERROR,
+        InterpretError::InterpretExit(code) => *code,
+    }
+}
+
+struct Options {
+    file: String,
+    frontend: Frontend,
+    backend: Backend,
+    emit_ast: bool,
+    // for `run`, everything after the file
+    script_args: Vec<String>,
+    max_depth: usize,
+    gc_stats: bool,
+    gc: GcConfig,
+    #[cfg(feature = "jit")]
+    jit: bool,
+}
+
+fn parse_options(command: &str, args: &[String]) -> Result<Options, String> {
+    let mut file = None;
+    let mut frontend = Frontend::SinglePass;
+    let mut backend = Backend::Stack;
+    let mut emit_ast = false;
+    let mut script_args = Vec::new();
+    let mut max_depth = Vm::DEFAULT_MAX_FRAMES;
+    let mut gc_stats = false;
+    let mut gc = GcConfig::default();
+    #[cfg(feature = "jit")]
+    let mut jit = true;
+    for (idx, arg) in args.iter().enumerate() {
+        if file.is_some() && command == "run" {
+            script_args = args[idx..].to_vec();
+            break;
+        }
+        match arg.as_str() {
+            "--frontend=ast" => frontend = Frontend::Ast,
+            "--frontend=single-pass" => frontend = Frontend::SinglePass,
+            "--backend=register" if command == "run" || command == "disassemble" => backend = Backend::Register,
+            "--backend=stack" if command == "run" || command == "disassemble" => backend = Backend::Stack,
+            "--emit=ast" if command == "compile" => emit_ast = true,
+            "--emit=bytecode" if command == "compile" => emit_ast = false,
+            _ if command == "run" && arg.starts_with("--max-depth=") => {
+                max_depth = match arg["--max-depth=".len()..].parse() {
+                    Ok(depth) if depth > 0 => depth,
+                    _ => return Err(format!("invalid call depth in '{}'", arg)),
+                }
+            }
+            "--gc-stats" if command == "run" => gc_stats = true,
+            _ if command == "run" && arg.starts_with("--gc-threshold=") => {
+                gc.threshold = match arg["--gc-threshold=".len()..].parse() {
+                    Ok(bytes) => bytes,
+                    _ => return Err(format!("invalid heap size in '{}'", arg)),
+                }
+            }
+            _ if command == "run" && arg.starts_with("--gc-growth=") => {
+                gc.growth_factor = match arg["--gc-growth=".len()..].parse() {
+                    Ok(factor) if GcConfig::valid_growth_factor(factor) => factor,
+                    _ => return Err(format!("invalid growth factor in '{}'", arg)),
+                }
+            }
+            #[cfg(feature = "jit")]
+            "--no-jit" if command == "run" => jit = false,
+            "-" => file = Some(arg.clone()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}' for {}", arg, command)),
+            _ if file.is_some() => return Err(format!("unexpected argument '{}'", arg)),
+            _ => file = Some(arg.clone()),
+        }
+    }
+    match file {
+        Some(file) => Ok(Options {
+            file,
+            frontend,
+            backend,
+            emit_ast,
+            script_args,
+            max_depth,
+            gc_stats,
+            gc,
+            #[cfg(feature = "jit")]
+            jit,
+        }),
+        None => Err(format!("{} needs a file to read, or '-' for stdin", command)),
+    }
+}
+
+/// Run `command` (`run`, `check`, `compile` or `disassemble`) and return the
+/// process exit code. Errors go to stderr, results to stdout.
+pub fn run_command(command: &str, args: &[String]) -> i32 {
+    let options = match parse_options(command, args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    let code = match open_source_file(&options.file) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("could not read {}: {}", options.file, err);
+            return EXIT_NO_INPUT;
+        }
     };
 
-    let mut buf = String::new();
-    match file.read_to_string(&mut buf) {
-        Ok(_) => return buf,
-        Err(err) => panic!("Could not read data from file: {}", err),
+    if command == "compile" && options.emit_ast {
+        return if emit_ast(&code) { 0 } else { EXIT_COMPILE_ERROR };
+    }
+    let result = match command {
+        "run" => {
+            let vm = Vm::builder()
+                .frontend(options.frontend)
+                .backend(options.backend)
+                .args(options.script_args)
+                .max_depth(options.max_depth)
+                .gc_threshold(options.gc.threshold)
+                .gc_growth_factor(options.gc.growth_factor);
+            #[cfg(feature = "jit")]
+            let vm = if options.jit { vm } else { vm.jit_threshold(None) };
+            execute(code, vm, options.gc_stats)
+        }
+        _ => {
+            let mut gc = Gc::new();
+            let mut globals = Globals::new();
+            options.frontend.compile(code, &mut gc, &mut globals).map(|function| match command {
+                "compile" => print_json(&function_json(&function, &globals)),
+                "disassemble" => match options.backend {
+                    Backend::Stack => write_stdout(&function_listing(&function, &globals)),
+                    Backend::Register => write_stdout(&register_listing(&function, &globals)),
+                },
+                _ => {}
+            })
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(InterpretError::InterpretExit(code)) => code,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit_code(&err)
+        }
     }
 }
 
-pub fn execute(code: String) {
-    let mut interpreter = Vm::init_vm();
-    match interpreter.interpret(code) {
-        Ok(_) => (),
-        Err(err) => println!("Error: {:?}", err),
+pub fn help() {
+    println!("{}", USAGE);
+}
+
+fn print_json(json: &serde_json::Value) {
+    write_stdout(&format!("{}\n", serde_json::to_string_pretty(json).unwrap()));
+}
+
+// a reader that goes away early, like `head`, isn't an error worth a panic
+fn write_stdout(text: &str) {
+    let _ = io::stdout().lock().write_all(text.as_bytes());
+}
+
+/// Print the syntax tree of `code` as JSON; returns false if it doesn't parse.
+pub fn emit_ast(code: &str) -> bool {
+    match ast::parse(code) {
+        Ok(program) => {
+            print_json(&ast::to_json(&program));
+            true
+        }
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            false
+        }
     }
-}
\ No newline at end of file
+}
+
+/// `path` if it is a file, or every `.lh` file under it if it is a directory, in order.
+pub fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if path.is_dir() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(path)
+            .and_then(|entries| entries.map(|entry| entry.map(|e| e.path())).collect())
+            .map_err(|err| unreadable(path, err))?;
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() || entry.extension().is_some_and(|ext| ext == "lh") {
+                collect_files(&entry, files)?;
+            }
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
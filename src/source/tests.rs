@@ -0,0 +1,86 @@
+use crate::vm::{Backend, Frontend, InterpretError, Vm};
+
+use super::{
+    execute, exit_code, open_source_file, parse_options, strip_shebang, EXIT_COMPILE_ERROR,
+    EXIT_RUNTIME_ERROR,
+};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn shebang_line_is_blanked() {
+    assert_eq!(strip_shebang("#!/usr/bin/env lockhart\nprint 1;\n".to_string()), "\nprint 1;\n");
+    assert_eq!(strip_shebang("#!lockhart".to_string()), "");
+    assert_eq!(strip_shebang("print 1;".to_string()), "print 1;");
+    // line numbers in errors still match the file
+    let err = execute(strip_shebang("#!lockhart\nprint ;".to_string()), Vm::builder(), false).unwrap_err();
+    assert!(err.to_string().starts_with("[line 2]"), "{}", err);
+}
+
+#[test]
+fn missing_file_is_an_error_not_a_panic() {
+    assert!(open_source_file("/nonexistent/script.lh").is_err());
+}
+
+#[test]
+fn commands_over_files_name_a_missing_one() {
+    let files = args(&["/nonexistent/script.lh"]);
+    for result in [crate::formatter::run(&files), crate::lint::run(&files), crate::testing::run(&files)] {
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with("could not read /nonexistent/script.lh: "), "{}", err);
+    }
+}
+
+#[test]
+fn options_and_file_are_parsed() {
+    let options = parse_options("run", &args(&["--frontend=ast", "-"])).unwrap();
+    assert_eq!(options.file, "-");
+    assert_eq!(options.frontend, Frontend::Ast);
+
+    let options = parse_options("compile", &args(&["a.lh", "--emit=ast"])).unwrap();
+    assert!(options.emit_ast);
+
+    assert!(parse_options("run", &args(&["--emit=ast", "a.lh"])).is_err());
+    assert!(parse_options("check", &args(&["a.lh", "b.lh"])).is_err());
+    assert!(parse_options("check", &args(&[])).is_err());
+
+    assert_eq!(parse_options("run", &args(&["--max-depth=50", "a.lh"])).unwrap().max_depth, 50);
+    assert!(parse_options("run", &args(&["--max-depth=0", "a.lh"])).is_err());
+    assert!(parse_options("check", &args(&["--max-depth=50", "a.lh"])).is_err());
+    assert!(parse_options("run", &args(&["--gc-stats", "a.lh"])).unwrap().gc_stats);
+    assert!(parse_options("check", &args(&["--gc-stats", "a.lh"])).is_err());
+    let options = parse_options("run", &args(&["--gc-threshold=4096", "--gc-growth=1.5", "a.lh"])).unwrap();
+    assert_eq!((options.gc.threshold, options.gc.growth_factor), (4096, 1.5));
+    for growth in ["0.5", "-2", "inf", "NaN"] {
+        let arg = format!("--gc-growth={}", growth);
+        assert!(parse_options("run", &args(&[&arg, "a.lh"])).is_err(), "{}", arg);
+    }
+    assert!(parse_options("run", &args(&["--gc-threshold=lots", "a.lh"])).is_err());
+
+    assert_eq!(parse_options("run", &args(&["--backend=register", "a.lh"])).unwrap().backend, Backend::Register);
+    assert_eq!(parse_options("disassemble", &args(&["--backend=register", "a.lh"])).unwrap().backend, Backend::Register);
+    assert!(parse_options("check", &args(&["--backend=register", "a.lh"])).is_err());
+}
+
+#[test]
+fn arguments_after_the_file_go_to_the_script() {
+    let options = parse_options("run", &args(&["--frontend=ast", "a.lh", "b", "--frontend=ast"])).unwrap();
+    assert_eq!(options.file, "a.lh");
+    assert_eq!(options.frontend, Frontend::Ast);
+    assert_eq!(options.script_args, args(&["b", "--frontend=ast"]));
+
+    let source = "if (len(args) != 2 or args[1] != \"x\") exit(3); exit(7);";
+    let err = execute(source.to_string(), Vm::builder().args(args(&["w", "x"])), false).unwrap_err();
+    assert_eq!(exit_code(&err), 7);
+}
+
+#[test]
+fn compile_and_runtime_errors_have_distinct_exit_codes() {
+    let compile = InterpretError::InterpretCompileError("bad".to_string());
+    let runtime = InterpretError::InterpretRuntimeError("bad".to_string());
+    assert_eq!(exit_code(&compile), EXIT_COMPILE_ERROR);
+    assert_eq!(exit_code(&runtime), EXIT_RUNTIME_ERROR);
+    assert_ne!(EXIT_COMPILE_ERROR, EXIT_RUNTIME_ERROR);
+}
@@ -0,0 +1,171 @@
+use std::{
+    io::{self, Cursor},
+    path::Path,
+};
+
+use crate::{
+    source::{collect_files, open_source_file, unreadable},
+    vm::{output::MemoryOutput, InterpretError, Vm},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// What a script says it should do, from comments like `// expect: 5`.
+#[derive(Debug, Default, PartialEq)]
+struct Expectations {
+    // (line of the comment, expected text)
+    output: Vec<(usize, String)>,
+    runtime_error: Option<(usize, String)>,
+    compile_errors: Vec<(usize, String)>,
+}
+
+const EXPECT_OUTPUT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const EXPECT_COMPILE_ERROR: &str = "// expect compile error: ";
+
+fn expectations(source: &str) -> Expectations {
+    let mut expected = Expectations::default();
+    for (idx, line) in source.lines().enumerate() {
+        let lineno = idx + 1;
+        let comment = match line.find("// expect") {
+            Some(start) => &line[start..],
+            None => continue,
+        };
+        if let Some(text) = comment.strip_prefix(EXPECT_OUTPUT) {
+            expected.output.push((lineno, text.to_string()));
+        } else if let Some(text) = comment.strip_prefix(EXPECT_RUNTIME_ERROR) {
+            expected.runtime_error = Some((lineno, text.to_string()));
+        } else if let Some(text) = comment.strip_prefix(EXPECT_COMPILE_ERROR) {
+            expected.compile_errors.push((lineno, text.to_string()));
+        }
+    }
+    expected
+}
+
+/// How a script compared with its expectations.
+#[derive(Debug, Default)]
+struct Report {
+    // every difference from the `// expect` comments
+    failures: Vec<String>,
+    // each test block's name, and why it failed if it did
+    tests: Vec<(String, Option<String>)>,
+}
+
+impl Report {
+    fn passed(&self) -> bool {
+        self.failures.is_empty() && self.tests.iter().all(|(_, failure)| failure.is_none())
+    }
+}
+
+/// Run `source` and its test blocks and compare what they do with the
+/// script's expectations. Tests print after the script, in the order they're
+/// declared.
+fn check(source: &str) -> Report {
+    let expected = expectations(source);
+    let out = MemoryOutput::new();
+    // scripts see no input and the same random numbers on every run
+    let mut vm = Vm::builder().output(out.clone()).input(Cursor::new("")).seed(0).build();
+    let (result, tests) = match vm.test(source.to_string()) {
+        Ok(outcomes) => (Ok(()), outcomes),
+        Err(err) => (Err(err), Vec::new()),
+    };
+    let printed = out.contents();
+    let printed: Vec<&str> = printed.lines().collect();
+
+    let mut failures = Vec::new();
+    for (idx, (lineno, text)) in expected.output.iter().enumerate() {
+        match printed.get(idx) {
+            Some(actual) if actual == text => {}
+            Some(actual) => failures.push(format!("line {}: expected {:?}, got {:?}", lineno, text, actual)),
+            None => failures.push(format!("line {}: expected {:?}, got nothing", lineno, text)),
+        }
+    }
+    for actual in printed.iter().skip(expected.output.len()) {
+        failures.push(format!("unexpected output {:?}", actual));
+    }
+
+    let (runtime_error, compile_errors) = match &result {
+        Ok(()) | Err(InterpretError::InterpretExit(0)) => (None, Vec::new()),
+        Err(InterpretError::InterpretRuntimeError(msg)) => (Some(msg.clone()), Vec::new()),
+        Err(InterpretError::InterpretCompileError(msg)) => (None, msg.lines().map(String::from).collect()),
+        Err(exit @ InterpretError::InterpretExit(_)) => (Some(exit.to_string()), Vec::new()),
+    };
+    match (&expected.runtime_error, runtime_error) {
+        (Some((_, text)), Some(actual)) if *text == actual => {}
+        (Some((lineno, text)), Some(actual)) => {
+            failures.push(format!("line {}: expected runtime error {:?}, got {:?}", lineno, text, actual))
+        }
+        (Some((lineno, text)), None) => {
+            failures.push(format!("line {}: expected runtime error {:?}, got none", lineno, text))
+        }
+        (None, Some(actual)) => failures.push(format!("unexpected runtime error {:?}", actual)),
+        (None, None) => {}
+    }
+    for (lineno, text) in &expected.compile_errors {
+        if !compile_errors.contains(text) {
+            failures.push(format!("line {}: expected compile error {:?}", lineno, text));
+        }
+    }
+    let expected_errors: Vec<&String> = expected.compile_errors.iter().map(|(_, text)| text).collect();
+    for actual in compile_errors.iter().filter(|actual| !expected_errors.contains(actual)) {
+        failures.push(format!("unexpected compile error {:?}", actual));
+    }
+
+    let tests = tests
+        .into_iter()
+        .map(|test| match test.result {
+            Ok(()) | Err(InterpretError::InterpretExit(0)) => (test.name, None),
+            Err(InterpretError::InterpretRuntimeError(msg)) => (test.name, Some(msg)),
+            Err(err) => (test.name, Some(err.to_string())),
+        })
+        .collect();
+    Report { failures, tests }
+}
+
+/// `lockhart test <files or directories>`: run every script and its test
+/// blocks and compare them with its `// expect` comments; returns false if
+/// any script or test fails.
+pub fn run(args: &[String]) -> io::Result<bool> {
+    let mut files = Vec::new();
+    for arg in args {
+        collect_files(Path::new(arg), &mut files)?;
+    }
+    if files.is_empty() {
+        eprintln!("no scripts to test");
+        return Ok(false);
+    }
+
+    let mut failed = 0;
+    let (mut tests_passed, mut tests_failed) = (0, 0);
+    for file in &files {
+        let source = open_source_file(&file.to_string_lossy()).map_err(|err| unreadable(file, err))?;
+        let report = check(&source);
+        if report.passed() {
+            println!("PASS {}", file.display());
+        } else {
+            failed += 1;
+            println!("FAIL {}", file.display());
+        }
+        for failure in &report.failures {
+            println!("  {}", failure);
+        }
+        for (name, failure) in &report.tests {
+            match failure {
+                None => {
+                    tests_passed += 1;
+                    println!("  ok {}", name);
+                }
+                Some(failure) => {
+                    tests_failed += 1;
+                    println!("  FAILED {}: {}", name, failure);
+                }
+            }
+        }
+    }
+    println!("\n{} passed, {} failed", files.len() - failed, failed);
+    if tests_passed + tests_failed > 0 {
+        println!("tests: {} passed, {} failed", tests_passed, tests_failed);
+    }
+    Ok(failed == 0)
+}
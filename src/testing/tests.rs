@@ -0,0 +1,79 @@
+use std::path::Path;
+
+use crate::source::collect_files;
+
+use super::{check, expectations};
+
+fn failures(source: &str) -> Vec<String> {
+    check(source).failures
+}
+
+#[test]
+fn reads_expectations_from_comments() {
+    let source = "print 1; // expect: 1\nprint x; // expect runtime error: Undefined Variable\n// expect compile error: bad";
+    let expected = expectations(source);
+    assert_eq!(expected.output, vec![(1, "1".to_string())]);
+    assert_eq!(expected.runtime_error, Some((2, "Undefined Variable".to_string())));
+    assert_eq!(expected.compile_errors, vec![(3, "bad".to_string())]);
+}
+
+#[test]
+fn reports_differences() {
+    assert!(failures("print 1; // expect: 1").is_empty());
+    assert_eq!(failures("print 2; // expect: 1"), vec!["line 1: expected \"1\", got \"2\""]);
+    assert_eq!(
+        failures("print 1;\n// expect: 1\n// expect: 2"),
+        vec!["line 3: expected \"2\", got nothing"]
+    );
+    assert_eq!(failures("print 1;"), vec!["unexpected output \"1\""]);
+    assert_eq!(failures("print x;"), vec!["unexpected runtime error \"Undefined Variable\""]);
+    assert_eq!(
+        failures("print 1; // expect runtime error: Undefined Variable\n// expect: 1"),
+        vec!["line 1: expected runtime error \"Undefined Variable\", got none"]
+    );
+    assert_eq!(failures("exit(0); // expect: 1"), vec!["line 1: expected \"1\", got nothing"]);
+    assert_eq!(failures("exit(2);"), vec!["unexpected runtime error \"exit(2)\""]);
+    assert_eq!(
+        failures("let = 1;"),
+        vec!["unexpected compile error \"[line 1] Error at '=': Expected variable name\""]
+    );
+}
+
+#[test]
+fn runs_test_blocks_after_the_script() {
+    let source = "fn add(a, b) { return a + b; }\n\
+        test \"adds\" { print add(1, 2); assert_eq(add(1, 2), 3); }\n\
+        test \"fails\" {\n  assert_eq(add(1, 1), 3);\n}\n\
+        test \"says why\" { assert(false, \"nope\"); }\n\
+        print \"script\";\n\
+        // expect: script\n\
+        // expect: 3";
+    let report = check(source);
+    assert!(report.failures.is_empty(), "{:?}", report.failures);
+    assert_eq!(
+        report.tests,
+        vec![
+            ("adds".to_string(), None),
+            ("fails".to_string(), Some("assert_eq failed on line 4: got 2, expected 3".to_string())),
+            ("says why".to_string(), Some("assertion failed on line 6: nope".to_string())),
+        ]
+    );
+    assert!(!report.passed());
+
+    // a script that fails never gets to its tests
+    let report = check("test \"t\" { }\nprint x; // expect runtime error: Undefined Variable");
+    assert!(report.passed());
+    assert!(report.tests.is_empty());
+}
+
+#[test]
+fn golden_scripts_pass() {
+    let mut files = Vec::new();
+    collect_files(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden"), &mut files).unwrap();
+    assert!(!files.is_empty());
+    for file in files {
+        let source = std::fs::read_to_string(&file).unwrap();
+        let report = check(&source);
+        assert!(report.passed(), "{}:\n{:?}", file.display(), report);
+    }
+}
@@ -36,6 +36,9 @@ pub enum TokenType {
     RBRACE,
     LPAREN,
     RPAREN,
+    LBRACKET,
+    RBRACKET,
+    COMMENT,
     ILLEGAL,
     EOF,
 }
@@ -45,6 +48,7 @@ pub struct Token {
     pub type_: TokenType,
     pub literal: String,
     pub lineno: usize,
+    pub offset: usize, // byte offset of the token in the source
 }
 
 impl PartialEq for Token {
@@ -67,6 +71,7 @@ impl Token {
             type_,
             literal,
             lineno,
+            offset: 0,
         }
     }
 
@@ -75,6 +80,7 @@ impl Token {
             type_: TokenType::ILLEGAL,
             literal: "".to_string(),
             lineno: 0,
+            offset: 0,
         }
     }
 
@@ -122,6 +128,8 @@ pub static DELIMITERS: phf::Map<&'static str, TokenType> = phf_map! {
     "}" => TokenType::RBRACE,
     "(" => TokenType::LPAREN,
     ")" => TokenType::RPAREN,
+    "[" => TokenType::LBRACKET,
+    "]" => TokenType::RBRACKET,
     "," => TokenType::COMMA,
     ";" => TokenType::SEMICOLON,
 };
@@ -1,6 +1,63 @@
 use std::fmt::Display;
 
-use crate::{gc::GcRef, object::{ObjFunction, ObjString}};
+use crate::{gc::{Gc, GcRef}, object::{ObjFunction, ObjList, ObjNative, ObjString}};
+
+#[cfg(feature = "nan-boxing")]
+pub mod nanbox;
+
+/// What the VM's stack holds: a `Value`, or with the `nan-boxing` feature a
+/// `NanBox`. Convert with `to_slot` and `from_slot`; `copy_slot` moves a
+/// value between stack slots without unpacking it.
+#[cfg(not(feature = "nan-boxing"))]
+pub type Slot = Value;
+#[cfg(feature = "nan-boxing")]
+pub type Slot = nanbox::NanBox;
+
+#[cfg(not(feature = "nan-boxing"))]
+#[inline(always)]
+pub fn to_slot(value: Value) -> Slot {
+    value
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+#[inline(always)]
+pub fn from_slot(slot: &Slot) -> Value {
+    slot.clone()
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+#[inline(always)]
+pub fn copy_slot(slot: &Slot) -> Slot {
+    slot.clone()
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+pub fn mark_slot(gc: &mut Gc, slot: &Slot) {
+    gc.mark_value(slot);
+}
+
+#[cfg(feature = "nan-boxing")]
+#[inline(always)]
+pub fn to_slot(value: Value) -> Slot {
+    nanbox::NanBox::pack(&value)
+}
+
+#[cfg(feature = "nan-boxing")]
+#[inline(always)]
+pub fn from_slot(slot: &Slot) -> Value {
+    slot.unpack()
+}
+
+#[cfg(feature = "nan-boxing")]
+#[inline(always)]
+pub fn copy_slot(slot: &Slot) -> Slot {
+    *slot
+}
+
+#[cfg(feature = "nan-boxing")]
+pub fn mark_slot(gc: &mut Gc, slot: &Slot) {
+    slot.mark(gc);
+}
 
 #[derive(Clone, PartialEq)]
 pub enum Value {
@@ -8,6 +65,8 @@ pub enum Value {
     BOOL(bool),
     STR(GcRef<ObjString>),
     FUNCTION(GcRef<ObjFunction>),
+    NATIVE(GcRef<ObjNative>),
+    LIST(GcRef<ObjList>),
     NIL,
 }
 
@@ -36,13 +95,22 @@ impl Value {
         }
     }
 
+    /// How the value is shown to users: like `Display`, but strings are quoted
+    /// and escaped so `"1"` and `1` don't look alike.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::STR(s) => format!("{:?}", s.s),
+            _ => self.to_string(),
+        }
+    }
+
     pub fn is_falsey(value: &Value) -> bool {
         match value {
             Value::NUMBER(x) => *x == 0f64,
             Value::BOOL(bool) => !bool,
             Value::STR(_) => false,
             Value::NIL => true,
-            _ => true,
+            Value::FUNCTION(_) | Value::NATIVE(_) | Value::LIST(_) => false,
         }
     }
 
@@ -61,6 +129,7 @@ impl Value {
                 Value::NUMBER(x) => *x == v2.get_number().unwrap(),
                 Value::NIL => true,
                 Value::STR(s) => *s == v2.get_string().unwrap(),
+                Value::LIST(l) => matches!(v2, Value::LIST(other) if other == l),
                 _ => false,
             }
         } else {
@@ -76,6 +145,8 @@ impl Display for Value {
             Value::BOOL(x) => write!(f, "{}", x),
             Value::STR(s) => write!(f, "{}", **s),
             Value::FUNCTION(x) => write!(f, "{}", **x),
+            Value::NATIVE(x) => write!(f, "{}", **x),
+            Value::LIST(x) => write!(f, "{}", **x),
             Value::NIL => write!(f, "nil"),
         }
     }
@@ -0,0 +1,182 @@
+use std::fmt::Display;
+
+use crate::{
+    gc::{Gc, GcRef},
+    object::{ObjFunction, ObjList, ObjNative, ObjString},
+    value::Value,
+};
+
+// Any double with all of these bits set is a quiet NaN that arithmetic never
+// produces, which leaves the low 50 bits free for other values.
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+// set on top of QNAN for object pointers
+const SIGN: u64 = 0x8000_0000_0000_0000;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+// Objects are at least 8 byte aligned, so the low three bits of a pointer
+// say which kind of object it points to.
+const KIND_MASK: u64 = 0b111;
+const KIND_STR: u64 = 0;
+const KIND_FUNCTION: u64 = 1;
+const KIND_NATIVE: u64 = 2;
+const KIND_LIST: u64 = 3;
+
+/// A `Value` packed into 64 bits: numbers are stored as themselves, and nil,
+/// booleans and object pointers hide in the payload of a quiet NaN. Only the
+/// VM's stack holds these, behind the `nan-boxing` feature; everything else
+/// keeps working with `Value`.
+#[derive(Clone, Copy)]
+pub struct NanBox(u64);
+
+impl NanBox {
+    pub const NIL: NanBox = NanBox(QNAN | TAG_NIL);
+
+    #[inline(always)]
+    pub fn pack(value: &Value) -> NanBox {
+        match value {
+            // NaNs that come out of arithmetic can't collide with QNAN, but
+            // make sure every NaN reads back as a number
+            Value::NUMBER(x) if x.is_nan() => NanBox(f64::NAN.to_bits()),
+            Value::NUMBER(x) => NanBox(x.to_bits()),
+            Value::BOOL(true) => NanBox(QNAN | TAG_TRUE),
+            Value::BOOL(false) => NanBox(QNAN | TAG_FALSE),
+            Value::NIL => NanBox::NIL,
+            Value::STR(s) => NanBox::object(s.as_ptr() as u64, KIND_STR),
+            Value::FUNCTION(f) => NanBox::object(f.as_ptr() as u64, KIND_FUNCTION),
+            Value::NATIVE(n) => NanBox::object(n.as_ptr() as u64, KIND_NATIVE),
+            Value::LIST(l) => NanBox::object(l.as_ptr() as u64, KIND_LIST),
+        }
+    }
+
+    #[inline(always)]
+    fn object(address: u64, kind: u64) -> NanBox {
+        debug_assert!(address & KIND_MASK == 0 && address & (SIGN | QNAN) == 0);
+        NanBox(SIGN | QNAN | address | kind)
+    }
+
+    #[inline(always)]
+    pub fn unpack(self) -> Value {
+        let bits = self.0;
+        if bits & QNAN != QNAN {
+            return Value::NUMBER(f64::from_bits(bits));
+        }
+        if bits & SIGN == 0 {
+            return match bits {
+                _ if bits == QNAN | TAG_TRUE => Value::BOOL(true),
+                _ if bits == QNAN | TAG_FALSE => Value::BOOL(false),
+                _ => Value::NIL,
+            };
+        }
+        let address = bits & !(SIGN | QNAN | KIND_MASK);
+        // SAFETY: only `pack` makes boxes with SIGN set, from a live GcRef
+        unsafe {
+            match bits & KIND_MASK {
+                KIND_STR => Value::STR(GcRef::from_ptr(address as *mut ObjString)),
+                KIND_FUNCTION => Value::FUNCTION(GcRef::from_ptr(address as *mut ObjFunction)),
+                KIND_NATIVE => Value::NATIVE(GcRef::from_ptr(address as *mut ObjNative)),
+                _ => Value::LIST(GcRef::from_ptr(address as *mut ObjList)),
+            }
+        }
+    }
+
+    /// Mark the object this points to, if it is one.
+    pub fn mark(self, gc: &mut Gc) {
+        if self.0 & (SIGN | QNAN) == SIGN | QNAN {
+            gc.mark_value(&self.unpack());
+        }
+    }
+}
+
+/// Same as `Value::values_equal`: numbers compare as numbers, so `NaN` isn't
+/// equal to itself even though its bits are.
+impl PartialEq for NanBox {
+    fn eq(&self, other: &NanBox) -> bool {
+        Value::values_equal(&self.unpack(), &other.unpack())
+    }
+}
+
+impl Display for NanBox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.unpack())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gc::Gc,
+        object::{ObjFunction, ObjList, ObjNative},
+        value::{nanbox::NanBox, Value},
+    };
+
+    #[test]
+    fn every_kind_of_value_round_trips() {
+        let mut gc = Gc::new();
+        let s = gc.intern("abc".to_string());
+        let list = gc.alloc(ObjList::new(vec![Value::NUMBER(1.0)]));
+        let native = gc.alloc(ObjNative::new("clock".to_string(), Some(0), |_, _| Ok(Value::NIL)));
+        let function = gc.alloc(ObjFunction::new(s));
+        let values = [
+            Value::NUMBER(0.0),
+            Value::NUMBER(-2.5),
+            Value::NUMBER(f64::INFINITY),
+            Value::NUMBER(f64::MAX),
+            Value::BOOL(true),
+            Value::BOOL(false),
+            Value::NIL,
+            Value::STR(s),
+            Value::LIST(list),
+            Value::NATIVE(native),
+            Value::FUNCTION(function),
+        ];
+        for value in values {
+            let unpacked = NanBox::pack(&value).unpack();
+            assert!(unpacked == value, "{} came back as {}", value, unpacked);
+        }
+    }
+
+    #[test]
+    fn nan_stays_a_number() {
+        assert!(matches!(NanBox::pack(&Value::NUMBER(f64::NAN)).unpack(), Value::NUMBER(n) if n.is_nan()));
+        assert!(matches!(NanBox::pack(&Value::NUMBER((-1f64).sqrt())).unpack(), Value::NUMBER(n) if n.is_nan()));
+    }
+
+    #[test]
+    fn equality_matches_values_equal() {
+        let mut gc = Gc::new();
+        let a = gc.intern("a".to_string());
+        let pack = |value: Value| NanBox::pack(&value);
+
+        assert!(pack(Value::NUMBER(1.0)) == pack(Value::NUMBER(1.0)));
+        assert!(pack(Value::NUMBER(0.0)) == pack(Value::NUMBER(-0.0)));
+        assert!(pack(Value::NUMBER(f64::NAN)) != pack(Value::NUMBER(f64::NAN)));
+        assert!(pack(Value::STR(a)) == pack(Value::STR(gc.intern("a".to_string()))));
+        assert!(pack(Value::NUMBER(1.0)) != pack(Value::BOOL(true)));
+        assert!(pack(Value::NIL) != pack(Value::BOOL(false)));
+    }
+
+    #[test]
+    fn displays_like_the_value() {
+        let mut gc = Gc::new();
+        let s = gc.intern("hi".to_string());
+        for value in [Value::NUMBER(1.5), Value::BOOL(false), Value::NIL, Value::STR(s)] {
+            assert_eq!(NanBox::pack(&value).to_string(), value.to_string());
+        }
+    }
+
+    #[test]
+    fn marking_keeps_objects_alive() {
+        let mut gc = Gc::new();
+        let kept = gc.intern("kept".to_string());
+        gc.intern("dropped".to_string());
+        gc.collect_garbage(|gc| {
+            NanBox::pack(&Value::STR(kept)).mark(gc);
+            NanBox::pack(&Value::NUMBER(3.0)).mark(gc);
+        });
+        assert_eq!(kept.s, "kept");
+        assert!(gc.intern("kept".to_string()) == kept);
+    }
+}
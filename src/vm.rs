@@ -1,23 +1,75 @@
-use std::ptr::null;
+use std::{
+    io::{BufRead, Write},
+    ptr::null,
+};
 
 use crate::{
-    bytecode::Opcode,
-    chunk::{disassemble::disassemble_instruction, Chunk, Lineno},
-    compiler::compile,
-    gc::{Gc, GcManaged, GcRef},
-    object::ObjFunction,
-    table::Table,
-    value::Value,
+    ast,
+    bytecode::{read_jump, read_varint, tag},
+    chunk::{disassemble::disassemble_instruction, Chunk},
+    compiler::{compile, compile_expression},
+    gc::{Gc, GcConfig, GcManaged, GcRef, GcStats},
+    natives::{self, Rng},
+    object::{NativeFn, ObjFunction, ObjList, ObjNative, ObjString},
+    globals::Globals,
+    value::{copy_slot, from_slot, mark_slot, to_slot, Slot, Value},
 };
 
+#[cfg(feature = "jit")]
+use crate::jit::{Jit, JitStats};
+
+use self::hook::{HookAction, VmHook};
+pub use self::builder::VmBuilder;
+
+mod builder;
+pub mod hook;
+pub mod output;
+mod registers;
+#[cfg(test)]
 mod tests;
 pub struct Vm {
     gc: Gc,
-    frames: [CallFrame; Vm::MAX_FRAMES],
+    // both grow as needed; slots past `frame_count` and `stack_top` are stale
+    frames: Vec<CallFrame>,
     frame_count: usize,
-    stack: Vec<Value>,
+    max_frames: usize,
+    stack: Vec<Slot>,
     stack_top: usize,
-    globals: Table,
+    globals: Globals,
+    frontend: Frontend,
+    backend: Backend,
+    rng: Rng,
+    // where `print` writes and `read_line` reads
+    output: Box<dyn Write>,
+    input: Box<dyn BufRead>,
+    // None runs everything in the interpreter
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
+}
+
+/// How source is turned into bytecode: straight from tokens, or by way of a syntax tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frontend {
+    SinglePass,
+    Ast,
+}
+
+impl Frontend {
+    pub fn compile(self, source: String, gc: &mut Gc, globals: &mut Globals) -> Result<GcRef<ObjFunction>, InterpretError> {
+        match self {
+            Frontend::SinglePass => compile(source, gc, globals),
+            Frontend::Ast => ast::compile(source, gc, globals),
+        }
+    }
+}
+
+/// Which instruction set runs the bytecode: the compiler's own stack
+/// machine, or its translation for the register machine. A VM with a hook
+/// attached always uses the stack machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Stack,
+    Register,
 }
 
 macro_rules! binary_op {
@@ -25,26 +77,63 @@ macro_rules! binary_op {
         {
             let right = $x.pop();
             let left = $x.pop();
-            if let (Value::NUMBER(x), Value::NUMBER(y)) = (left, right) {
+            if let (Value::NUMBER(x), Value::NUMBER(y)) = (&left, &right) {
                 $x.push((Value::$ret(x $op y)));
             } else {
-                panic!("Mismatched Types");
+                return Err(operands_error(stringify!($op), &left, &right));
             }
         }
     }
 }
 
+/// Mark what the program holds outside the heap: the live part of the
+/// stack, the functions being run and the globals.
+fn mark_roots(gc: &mut Gc, stack: &[Slot], frames: &[CallFrame], globals: &Globals) {
+    for slot in stack {
+        mark_slot(gc, slot);
+    }
+    for frame in frames {
+        gc.mark_object(frame.function);
+    }
+    globals.mark(gc);
+}
+
+fn operands_error(op: &str, left: &Value, right: &Value) -> InterpretError {
+    let msg = format!("Operands of '{}' must be numbers, not {} and {}", op, left.repr(), right.repr());
+    InterpretError::InterpretRuntimeError(msg)
+}
+
 #[derive(Debug)]
 pub enum InterpretError {
     InterpretCompileError(String),
     InterpretRuntimeError(String),
+    /// the script called `exit(code)`
+    InterpretExit(i32),
+}
+
+impl std::fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::InterpretCompileError(msg) => write!(f, "{}", msg),
+            InterpretError::InterpretRuntimeError(msg) => write!(f, "Runtime error: {}", msg),
+            InterpretError::InterpretExit(code) => write!(f, "exit({})", code),
+        }
+    }
+}
+
+/// How one `test` block went.
+#[derive(Debug)]
+pub struct TestOutcome {
+    pub name: String,
+    pub result: Result<(), InterpretError>,
 }
 
 #[derive(Clone, Copy)]
-struct CallFrame {
+pub struct CallFrame {
     function: GcRef<ObjFunction>,
-    ip: *const (Opcode, Lineno), // pointer to instruction vector
+    ip: *const u8,               // next byte of the chunk's code to run
     slot: usize,                 // starting stack-slot index of this function call
+    tail_calls: usize,           // frames elided by tail calls that reused this one
 }
 
 impl CallFrame {
@@ -53,6 +142,7 @@ impl CallFrame {
             function,
             ip: function.chunk.code.as_ptr(),
             slot,
+            tail_calls: 0,
         }
     }
 
@@ -62,73 +152,317 @@ impl CallFrame {
             pos as usize
         }
     }
+
+    pub fn function(&self) -> GcRef<ObjFunction> {
+        self.function
+    }
+
+    /// How many calls ran in this frame before the current one, each handing
+    /// it on with `return f(...)`.
+    pub fn tail_calls(&self) -> usize {
+        self.tail_calls
+    }
+
+    /// line of the instruction this frame is currently executing
+    pub fn line(&self) -> usize {
+        match self.offset().checked_sub(1) {
+            // `ip` is past the whole instruction by the time it runs
+            Some(offset) => self.function.chunk.line_at(offset).0,
+            None => 0,
+        }
+    }
 }
 
 impl Vm {
-    const MAX_FRAMES: usize = 64;
-    const MAX_STACK: usize = 255;
+    /// Calls deeper than this are a `Stack Overflow` unless the builder says otherwise.
+    pub const DEFAULT_MAX_FRAMES: usize = 10_000;
+    const INITIAL_FRAMES: usize = 64;
+    const INITIAL_STACK: usize = 256;
+    /// A VM with the defaults: stdout, stdin and the single-pass compiler.
     pub fn init_vm() -> Vm {
-        let gc = Gc::new();
-        Vm {
-            gc,
-            frames: [CallFrame {
-                function: GcRef::dangling(),
-                ip: null(),
-                slot: 0,
-            }; Vm::MAX_FRAMES],
+        Vm::builder().build()
+    }
+
+    pub fn builder() -> VmBuilder {
+        VmBuilder::new()
+    }
+
+    fn new(output: Box<dyn Write>, input: Box<dyn BufRead>, rng: Rng, max_frames: usize) -> Vm {
+        let mut vm = Vm {
+            gc: Gc::new(),
+            frames: vec![
+                CallFrame {
+                    function: GcRef::dangling(),
+                    ip: null(),
+                    slot: 0,
+                    tail_calls: 0,
+                };
+                Vm::INITIAL_FRAMES
+            ],
             frame_count: 0,
-            stack: vec![Value::NIL; Vm::MAX_STACK],
+            max_frames,
+            stack: vec![to_slot(Value::NIL); Vm::INITIAL_STACK],
             stack_top: 0,
-            globals: Table::new(),
-        }
+            globals: Globals::new(),
+            frontend: Frontend::SinglePass,
+            backend: Backend::Stack,
+            rng,
+            output,
+            input,
+            #[cfg(feature = "jit")]
+            jit: None,
+        };
+        natives::register(&mut vm);
+        vm
+    }
+
+    /// Make a Rust function callable from scripts as the global `name`.
+    /// An `arity` of None accepts any number of arguments.
+    pub fn define_native(&mut self, name: &str, arity: Option<u8>, function: NativeFn) {
+        let native = self.alloc(ObjNative::new(name.to_string(), arity, function));
+        self.define_global(name, Value::NATIVE(native));
+    }
+
+    /// Set the global `args` to the command line arguments given to the script.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        let items = args.into_iter().map(|arg| Value::STR(self.gc.intern(arg))).collect();
+        let list = self.new_list(items);
+        self.define_global("args", list);
+    }
+
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        let name = self.gc.intern(name.to_string());
+        let slot = self.globals.slot(name);
+        self.globals.define(slot, value);
+    }
+
+    pub fn intern(&mut self, s: String) -> Value {
+        Value::STR(self.gc.intern(s))
+    }
+
+    pub fn new_list(&mut self, items: Vec<Value>) -> Value {
+        Value::LIST(self.alloc(ObjList::new(items)))
+    }
+
+    /// The generator behind `random`, `random_int` and `shuffle`.
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    pub fn input(&mut self) -> &mut dyn BufRead {
+        &mut self.input
+    }
+
+    pub fn set_frontend(&mut self, frontend: Frontend) {
+        self.frontend = frontend;
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.gc.configure(config);
+    }
+
+    /// Compile functions and loops once they've run `threshold` times; None
+    /// leaves everything to the interpreter.
+    #[cfg(feature = "jit")]
+    pub fn set_jit_threshold(&mut self, threshold: Option<u32>) {
+        self.jit = threshold.map(Jit::new);
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn jit_stats(&self) -> JitStats {
+        self.jit.as_ref().map(Jit::stats).unwrap_or_default()
     }
 
     pub fn interpret(&mut self, source: String) -> Result<(), InterpretError> {
-        let function = compile(source, &mut self.gc)?;
+        self.load(source)?;
+        self.run(None).map(|_| ())
+    }
+
+    /// Same as `interpret`, but reports progress to `hook` as the script runs.
+    pub fn debug(&mut self, source: String, hook: &mut dyn VmHook) -> Result<(), InterpretError> {
+        self.load(source)?;
+        self.run(Some(hook)).map(|_| ())
+    }
+
+    /// Run `source`, then each of its `test` blocks in order. An error from the
+    /// script itself is returned as is; a failing test doesn't stop the others.
+    pub fn test(&mut self, source: String) -> Result<Vec<TestOutcome>, InterpretError> {
+        let script = self.frontend.compile(source, &mut self.gc, &mut self.globals)?;
+        self.start(script)?;
+        self.run(None)?;
+
+        let tests: Vec<GcRef<ObjFunction>> = script
+            .chunk
+            .tests
+            .iter()
+            .filter_map(|&idx| match &script.chunk.constants[idx] {
+                Value::FUNCTION(test) => Some(*test),
+                _ => None,
+            })
+            .collect();
+        let mut outcomes = Vec::new();
+        for test in tests {
+            // the script stays in slot 0, without a frame, so the tests it
+            // holds aren't collected
+            self.frame_count = 0;
+            self.stack_top = 0;
+            self.push(Value::FUNCTION(script));
+            self.push(Value::FUNCTION(test));
+            self.call(test, 0)?;
+            let result = self.run(None).map(|_| ());
+            outcomes.push(TestOutcome {
+                name: test.name.s.clone(),
+                result,
+            });
+        }
+        Ok(outcomes)
+    }
+
+    /// Evaluate `source` as a single expression and return its value.
+    pub fn evaluate(&mut self, source: String) -> Result<Value, InterpretError> {
+        let function = compile_expression(source, &mut self.gc, &mut self.globals)?;
+        self.start(function)?;
+        self.run(None)
+    }
+
+    fn load(&mut self, source: String) -> Result<(), InterpretError> {
+        let function = self.frontend.compile(source, &mut self.gc, &mut self.globals)?;
+        self.start(function)
+    }
+
+    fn start(&mut self, function: GcRef<ObjFunction>) -> Result<(), InterpretError> {
+        // a script that failed at runtime leaves its frames behind
+        self.frame_count = 0;
+        self.stack_top = 0;
         self.push(Value::FUNCTION(function));
         // let closure = self.alloc(function);
         // let frame = CallFrame::new(*closure, 0);
         // self.frames[self.frame_count] = frame;
         // self.frame_count += 1;
-        self.call(function, 0)?;
-        return self.run();
+        self.call(function, 0)
+    }
+
+    /// Source line of the instruction running in the innermost frame, e.g. the
+    /// call of the native asking.
+    pub fn current_line(&self) -> usize {
+        self.frames().last().map_or(0, |frame| frame.line())
+    }
+
+    /// active call frames, outermost first
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames[..self.frame_count]
+    }
+
+    /// named locals in scope in `frame` along with their current values
+    pub fn frame_locals(&self, frame: &CallFrame) -> Vec<(String, Value)> {
+        let offset = frame.offset().saturating_sub(1);
+        frame
+            .function
+            .chunk
+            .locals_at(offset)
+            .filter(|local| frame.slot + local.slot < self.stack_top)
+            .map(|local| (local.name.clone(), from_slot(&self.stack[frame.slot + local.slot])))
+            .collect()
+    }
+
+    /// Every global name the VM has compiled against, with the slot it was given.
+    pub fn global_slots(&self) -> &Globals {
+        &self.globals
+    }
+
+    /// Defined globals and their values, natives included.
+    pub fn globals(&self) -> impl Iterator<Item = (GcRef<ObjString>, Value)> + '_ {
+        self.globals.iter()
     }
 
     fn alloc<T: GcManaged>(&mut self, object: T) -> GcRef<T> {
         self.gc.alloc(object)
     }
 
-    fn run(&mut self) -> Result<(), InterpretError> {
+    /// The innermost frame. `frames` reallocates when a call makes it grow, so
+    /// `run` takes this again after every call instead of keeping the old pointer.
+    fn current_frame(&mut self) -> *mut CallFrame {
+        &mut self.frames[self.frame_count - 1] as *mut CallFrame
+    }
+
+    fn run(&mut self, mut hook: Option<&mut dyn VmHook>) -> Result<Value, InterpretError> {
+        if hook.is_none() && self.backend == Backend::Register {
+            return self.run_registers();
+        }
         unsafe {
-            let mut frame_ptr = &mut self.frames[self.frame_count - 1] as *mut CallFrame;
+            let mut frame_ptr = self.current_frame();
+            // instructions are a tag byte followed by their operand, see `Opcode::encode`
+            macro_rules! read_byte {
+                () => {{
+                    let byte = *(*frame_ptr).ip;
+                    (*frame_ptr).ip = (*frame_ptr).ip.add(1);
+                    byte
+                }};
+            }
+            macro_rules! read_operand {
+                ($read: path) => {
+                    $read(&mut || read_byte!())
+                };
+            }
+            // (frame depth, line) last reported to the hook, and what it was
+            // in each caller, so returning to a line doesn't report it again
+            let mut last_line = None;
+            let mut caller_lines: Vec<Option<(usize, usize)>> = Vec::new();
+            let mut depth = self.frame_count;
             loop {
                 if self.gc.should_collect() {
-                    self.collect_garbage();
+                    self.collect_step();
                 }
 
                 // disassemble_instruction(&(*frame_ptr).function.chunk, _i);
-                let op = (*(*frame_ptr).ip).0;
-                // println!("{:?}", op);
-                (*frame_ptr).ip = (*frame_ptr).ip.offset(1);
+                let op = read_byte!();
+                if let Some(hook) = hook.as_deref_mut() {
+                    while depth < self.frame_count {
+                        caller_lines.push(last_line.take());
+                        depth += 1;
+                    }
+                    while depth > self.frame_count {
+                        last_line = caller_lines.pop().flatten();
+                        depth -= 1;
+                    }
+                    let at = (self.frame_count, (*frame_ptr).line());
+                    if last_line != Some(at) {
+                        last_line = Some(at);
+                        if hook.on_line(self, at.1, at.0) == HookAction::Abort {
+                            return Err(InterpretError::InterpretRuntimeError(
+                                "Aborted by debugger".to_string(),
+                            ));
+                        }
+                    }
+                }
                 match op {
-                    Opcode::OP_RETURN => {
+                    tag::OP_RETURN => {
                         let returned_value = self.pop();
                         self.frame_count -= 1;
                         if self.frame_count == 0 {
                             self.pop();
-                            return Ok(());
+                            return Ok(returned_value);
                         }
                         self.stack_top = (*frame_ptr).slot;
                         self.push(returned_value);
-                        frame_ptr = &mut self.frames[self.frame_count - 1] as *mut CallFrame;
+                        frame_ptr = self.current_frame();
                     }
-                    Opcode::OP_CONSTANT(idx) => {
+                    tag::OP_CONSTANT => {
+                        let idx = read_operand!(read_varint);
                         let constant = Vm::read_constant(&*frame_ptr, idx);
                         // println!("{}", constant);
                         self.push(constant);
                         // return InterpretResult::InterpretOk;
                     }
-                    Opcode::OP_NEGATE => {
+                    tag::OP_NEGATE => {
                         let to_negate = self.peek(0);
                         if let Value::NUMBER(mut n) = to_negate {
                             n = -n;
@@ -141,15 +475,15 @@ impl Vm {
                             ));
                         }
                     }
-                    Opcode::OP_ADD => {
+                    tag::OP_ADD => {
                         let (x, y) = (self.peek(0), self.peek(1));
-                        if let (Value::STR(s1), Value::STR(s2)) = (x, y) {
+                        if let (Value::STR(s1), Value::STR(s2)) = (&x, &y) {
                             let concatenated = s2.s.to_owned() + &s1.s;
                             let interned = self.gc.intern(concatenated);
                             self.pop();
                             self.pop();
                             self.push(Value::STR(interned));
-                        } else if let (Value::NUMBER(_), Value::NUMBER(_)) = (x, y) {
+                        } else if let (Value::NUMBER(_), Value::NUMBER(_)) = (&x, &y) {
                             binary_op!(NUMBER, +, self);
                         } else {
                             return Err(InterpretError::InterpretRuntimeError(
@@ -158,55 +492,76 @@ impl Vm {
                         }
                         // println!("{:?}", self.peek());
                     }
-                    Opcode::OP_SUBSTRACT => {
+                    tag::OP_SUBSTRACT => {
                         binary_op!(NUMBER, -, self);
                         // println!("{:?}", self.peek());
                     }
-                    Opcode::OP_DIVIDE => {
+                    tag::OP_DIVIDE => {
                         binary_op!(NUMBER, /, self);
                         // println!("{:?}", self.peek());
                     }
-                    Opcode::OP_MULTIPLY => {
+                    tag::OP_MULTIPLY => {
                         binary_op!(NUMBER, *, self);
                         // println!("{:?}", self.peek())
                     }
-                    Opcode::OP_MOD => {
+                    tag::OP_MOD => {
                         binary_op!(NUMBER, %, self);
                         // println!("{:?}", self.peek())
                     }
-                    Opcode::OP_TRUE => self.push(Value::BOOL(true)),
-                    Opcode::OP_FALSE => self.push(Value::BOOL(false)),
-                    Opcode::OP_NIL => self.push(Value::NIL),
-                    Opcode::OP_NOT => {
+                    tag::OP_TRUE => self.push(Value::BOOL(true)),
+                    tag::OP_FALSE => self.push(Value::BOOL(false)),
+                    tag::OP_NIL => self.push(Value::NIL),
+                    tag::OP_NOT => {
                         let falsified = Value::BOOL(Value::falsify(&self.pop()));
                         self.push(falsified);
                     }
-                    Opcode::OP_EQ => {
+                    tag::OP_EQ => {
                         let a = self.pop();
                         let b = self.pop();
                         self.push(Value::BOOL(Value::values_equal(&a, &b)));
                     }
-                    Opcode::OP_GT => {
-                        binary_op!(BOOL, >, self);
+                    tag::OP_GT => {
+                        if let (Value::STR(right), Value::STR(left)) = (self.peek(0), self.peek(1)) {
+                            let greater = left.s > right.s;
+                            self.pop();
+                            self.pop();
+                            self.push(Value::BOOL(greater));
+                        } else {
+                            binary_op!(BOOL, >, self);
+                        }
                     }
-                    Opcode::OP_LT => {
-                        binary_op!(BOOL, <, self);
+                    tag::OP_LT => {
+                        if let (Value::STR(right), Value::STR(left)) = (self.peek(0), self.peek(1)) {
+                            let less = left.s < right.s;
+                            self.pop();
+                            self.pop();
+                            self.push(Value::BOOL(less));
+                        } else {
+                            binary_op!(BOOL, <, self);
+                        }
                     }
-                    Opcode::OP_PRINT => {
+                    tag::OP_PRINT => {
                         let val = self.pop();
-                        println!("{}", val);
+                        let written = match hook.as_deref_mut() {
+                            Some(hook) => hook.on_print(&val.to_string(), &mut self.output),
+                            None => writeln!(self.output, "{}", val),
+                        };
+                        if let Err(err) = written {
+                            let msg = format!("Could not write output: {}", err);
+                            return Err(InterpretError::InterpretRuntimeError(msg));
+                        }
                     }
-                    Opcode::OP_POP => {
+                    tag::OP_POP => {
                         self.pop();
                     }
-                    Opcode::OP_DEFINE_GLOBAL(idx) => {
-                        let name = Vm::read_constant(&*frame_ptr, idx).get_string().unwrap();
+                    tag::OP_DEFINE_GLOBAL => {
+                        let slot = read_operand!(read_varint);
                         let value = self.pop();
-                        self.globals.set(name, value);
+                        self.globals.define(slot, value);
                     }
-                    Opcode::OP_GET_GLOBAL(idx) => {
-                        let name = Vm::read_constant(&*frame_ptr, idx).get_string().unwrap();
-                        if let Some(value) = self.globals.get(name) {
+                    tag::OP_GET_GLOBAL => {
+                        let slot = read_operand!(read_varint);
+                        if let Some(value) = self.globals.get(slot) {
                             self.push(value.clone());
                         } else {
                             return Err(InterpretError::InterpretRuntimeError(
@@ -214,103 +569,289 @@ impl Vm {
                             ));
                         }
                     }
-                    Opcode::OP_SET_GLOBAL(idx) => {
-                        let name = Vm::read_constant(&*frame_ptr, idx).get_string().unwrap();
+                    tag::OP_SET_GLOBAL => {
+                        let slot = read_operand!(read_varint);
                         let value = self.peek(0);
-                        if self.globals.set(name, value.clone()) {
-                            self.globals.delete_entry(name);
+                        if !self.globals.assign(slot, value) {
                             return Err(InterpretError::InterpretRuntimeError(
                                 "Undefined Variable".to_string(),
                             ));
                         }
                     }
-                    Opcode::OP_GET_LOCAL(slot_index) => {
+                    tag::OP_GET_LOCAL => {
+                        let slot_index = read_operand!(read_varint);
                         let offset = slot_index + (*frame_ptr).slot;
-                        self.push(self.stack[offset].clone());
+                        self.push_slot(copy_slot(&self.stack[offset]));
                     }
-                    Opcode::OP_SET_LOCAL(slot_index) => {
+                    tag::OP_SET_LOCAL => {
+                        let slot_index = read_operand!(read_varint);
                         let offset = slot_index + (*frame_ptr).slot;
-                        self.stack[offset] = self.peek(0).clone();
+                        self.stack[offset] = copy_slot(&self.stack[self.stack_top - 1]);
                     }
-                    Opcode::OP_JUMP_IF_FALSE(jump_size) => {
-                        if Value::is_falsey(self.peek(0)) {
+                    tag::OP_JUMP_IF_FALSE => {
+                        let jump_size = read_operand!(read_jump);
+                        if Value::is_falsey(&self.peek(0)) {
                             (*frame_ptr).ip = (*frame_ptr).ip.offset(jump_size as isize);
                         }
                     }
-                    Opcode::OP_JUMP(jump_size) => {
+                    tag::OP_JUMP => {
+                        let jump_size = read_operand!(read_jump);
                         (*frame_ptr).ip = (*frame_ptr).ip.offset(jump_size as isize);
                     }
-                    Opcode::OP_LOOP(jump_size) => {
+                    tag::OP_LOOP => {
+                        let jump_size = read_operand!(read_jump);
                         (*frame_ptr).ip = (*frame_ptr).ip.offset(-(jump_size as isize));
+                        // each iteration counts as reaching the line again
+                        last_line = None;
+                        #[cfg(feature = "jit")]
+                        if hook.is_none() {
+                            self.run_compiled(frame_ptr);
+                        }
                     }
-                    Opcode::OP_CALL(arg_count) => {
+                    tag::OP_CALL => {
+                        let arg_count = read_byte!();
                         self.call_value(arg_count)?;
-                        frame_ptr = &mut self.frames[self.frame_count - 1] as *mut CallFrame;
+                        frame_ptr = self.current_frame();
+                        // a function just started; natives leave the caller's frame on top
+                        #[cfg(feature = "jit")]
+                        if hook.is_none() && (*frame_ptr).offset() == 0 {
+                            self.run_compiled(frame_ptr);
+                        }
+                    }
+                    tag::OP_TAIL_CALL => {
+                        let arg_count = read_byte!();
+                        // a function takes over this frame, a native returns to it
+                        if self.tail_call(arg_count)? {
+                            #[cfg(feature = "jit")]
+                            if hook.is_none() {
+                                self.run_compiled(frame_ptr);
+                            }
+                        }
+                    }
+                    tag::OP_BUILD_LIST => {
+                        let count = read_operand!(read_varint);
+                        let items = self.stack[self.stack_top - count..self.stack_top].iter().map(from_slot).collect();
+                        self.stack_top -= count;
+                        let list = self.new_list(items);
+                        self.push(list);
+                    }
+                    tag::OP_GET_INDEX => {
+                        let index = self.pop();
+                        let target = self.pop();
+                        let item = self.get_index(target, &index)?;
+                        self.push(item);
                     }
+                    tag::OP_SET_INDEX => {
+                        let value = self.pop();
+                        let index = self.pop();
+                        let list = self.pop();
+                        let (mut list, index) = Vm::list_index(&list, &index)?;
+                        self.gc.write_barrier(&value);
+                        list.items[index] = value.clone();
+                        self.push(value);
+                    }
+                    other => panic!("invalid opcode {}", other),
                 }
             }
         }
         // println!("{:?}", self.peek(0));
     }
 
-    fn collect_garbage(&mut self) {
-        self.mark_roots();
-        self.gc.collect_garbage();
+    /// Run compiled code for the frame if the JIT has some for where it is,
+    /// and move the frame on to where the compiled code stopped.
+    #[cfg(feature = "jit")]
+    unsafe fn run_compiled(&mut self, frame_ptr: *mut CallFrame) {
+        let jit = match &mut self.jit {
+            Some(jit) => jit,
+            None => return,
+        };
+        let frame = &mut *frame_ptr;
+        let entered = jit.enter(
+            frame.function,
+            frame.offset(),
+            &mut self.stack,
+            frame.slot,
+            self.stack_top,
+            &mut self.globals,
+        );
+        if let Some((resume, top)) = entered {
+            frame.ip = frame.function.chunk.code.as_ptr().add(resume);
+            self.stack_top = top;
+        }
     }
 
-    fn mark_roots(&mut self) {
-        for idx in 0..self.stack_top {
-            let value = self.stack[idx].clone();
-            self.gc.mark_value(&value);
-        }
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc.stats()
+    }
 
-        for frame_index in 0..self.frame_count {
-            let function = self.frames[frame_index].function;
-            self.gc.mark_object(function);
-        }
+    /// A whole collection in one pause.
+    pub fn collect_garbage(&mut self) {
+        let (stack, frames, globals) = (&self.stack[..self.stack_top], &self.frames[..self.frame_count], &self.globals);
+        self.gc.collect_garbage(|gc| mark_roots(gc, stack, frames, globals));
+    }
+
+    /// One increment of the collector, run between instructions.
+    fn collect_step(&mut self) {
+        let (stack, frames, globals) = (&self.stack[..self.stack_top], &self.frames[..self.frame_count], &self.globals);
+        self.gc.step(|gc| mark_roots(gc, stack, frames, globals));
+    }
+
+    /// `value` is going into an object already on the heap.
+    pub fn write_barrier(&mut self, value: &Value) {
+        self.gc.write_barrier(value);
+    }
 
-        self.gc.mark_table(&self.globals);
+    /// Append `item` to `list`, counting any room the list grows by.
+    pub fn list_push(&mut self, mut list: GcRef<ObjList>, item: Value) {
+        self.gc.write_barrier(&item);
+        let capacity = list.items.capacity();
+        list.items.push(item);
+        if list.items.capacity() != capacity {
+            self.gc.resize(list);
+        }
     }
 
-    fn peek(&self, idx: usize) -> &Value {
-        &self.stack[self.stack_top - 1 - idx]
+    fn peek(&self, idx: usize) -> Value {
+        from_slot(&self.stack[self.stack_top - 1 - idx])
     }
 
     fn push(&mut self, value: Value) {
-        self.stack[self.stack_top] = value;
+        self.push_slot(to_slot(value));
+    }
+
+    #[inline(always)]
+    fn push_slot(&mut self, value: Slot) {
+        if self.stack_top == self.stack.len() {
+            // nothing holds on to stack addresses, frames keep indices
+            self.stack.push(value);
+        } else {
+            self.stack[self.stack_top] = value;
+        }
         self.stack_top += 1;
     }
 
     fn pop(&mut self) -> Value {
         self.stack_top -= 1;
-        self.stack[self.stack_top].clone()
+        from_slot(&self.stack[self.stack_top])
     }
 
     fn call_value(&mut self, arg_count: u8) -> Result<(), InterpretError> {
         let callee = self.peek(arg_count.into());
-        match callee {
+        match &callee {
             Value::FUNCTION(x) => {
                 return self.call(*x, arg_count);
             }
+            Value::NATIVE(native) => {
+                let native = *native;
+                return self.call_native(native, arg_count);
+            }
             _ => return Err(InterpretError::InterpretRuntimeError("Calling uncallable object".to_string())),
         }
     }
 
     fn call(&mut self, func: GcRef<ObjFunction>, arg_count: u8) -> Result<(), InterpretError> {
-        if arg_count != func.arity {
-            let msg = format!("Expected {} args but found {}", func.arity, arg_count);
-            return Err(InterpretError::InterpretRuntimeError(msg));
-        }
+        Vm::check_arity(func, arg_count)?;
 
-        if self.frame_count == Vm::MAX_FRAMES {
+        if self.frame_count == self.max_frames {
             return Err(InterpretError::InterpretRuntimeError("Stack Overflow".to_string()));
         }
 
         let frame = CallFrame::new(func, self.stack_top - 1 - (arg_count as usize));
-        self.frames[self.frame_count] = frame;
+        if self.frame_count == self.frames.len() {
+            self.frames.push(frame);
+        } else {
+            self.frames[self.frame_count] = frame;
+        }
         self.frame_count += 1;
         Ok(())
     }
+
+    /// Call the callee below the top `arg_count` values in place of the
+    /// current frame: a function is moved down, with its arguments, to the
+    /// frame's slot and runs in the frame from its start. Returns whether it
+    /// did; anything else is called as usual and the frame carries on.
+    fn tail_call(&mut self, arg_count: u8) -> Result<bool, InterpretError> {
+        let callee = self.stack_top - 1 - arg_count as usize;
+        let func = match from_slot(&self.stack[callee]) {
+            Value::FUNCTION(func) => func,
+            _ => return self.call_value(arg_count).map(|_| false),
+        };
+        Vm::check_arity(func, arg_count)?;
+        let frame = &mut self.frames[self.frame_count - 1];
+        for idx in 0..=arg_count as usize {
+            self.stack[frame.slot + idx] = copy_slot(&self.stack[callee + idx]);
+        }
+        self.stack_top = frame.slot + 1 + arg_count as usize;
+        frame.function = func;
+        frame.ip = func.chunk.code.as_ptr();
+        frame.tail_calls += 1;
+        Ok(true)
+    }
+
+    fn check_arity(func: GcRef<ObjFunction>, arg_count: u8) -> Result<(), InterpretError> {
+        if arg_count != func.arity {
+            let msg = format!("Expected {} args but found {}", func.arity, arg_count);
+            return Err(InterpretError::InterpretRuntimeError(msg));
+        }
+        Ok(())
+    }
+
+    fn call_native(&mut self, native: GcRef<ObjNative>, arg_count: u8) -> Result<(), InterpretError> {
+        if let Some(arity) = native.arity {
+            if arg_count != arity {
+                let msg = format!("{} expected {} args but found {}", native.name, arity, arg_count);
+                return Err(InterpretError::InterpretRuntimeError(msg));
+            }
+        }
+        let args_start = self.stack_top - arg_count as usize;
+        let args: Vec<Value> = self.stack[args_start..self.stack_top].iter().map(from_slot).collect();
+        let result = (native.function)(self, &args)?;
+        // the arguments and the native itself
+        self.stack_top = args_start - 1;
+        self.push(result);
+        Ok(())
+    }
+
+    /// `target[index]`, for a list or a string.
+    fn get_index(&mut self, target: Value, index: &Value) -> Result<Value, InterpretError> {
+        match target {
+            Value::STR(s) => {
+                let count = s.s.chars().count();
+                let index = Vm::index(index, count)?;
+                let c = s.s.chars().nth(index).unwrap();
+                Ok(Value::STR(self.gc.intern(c.to_string())))
+            }
+            Value::LIST(list) => Ok(list.items[Vm::index(index, list.items.len())?].clone()),
+            other => {
+                let msg = format!("Only lists and strings can be indexed, not {}", other.repr());
+                Err(InterpretError::InterpretRuntimeError(msg))
+            }
+        }
+    }
+
+    /// The list and in-bounds index for `list[index]`.
+    fn list_index(list: &Value, index: &Value) -> Result<(GcRef<ObjList>, usize), InterpretError> {
+        match list {
+            Value::LIST(list) => Ok((*list, Vm::index(index, list.items.len())?)),
+            other => {
+                // strings are immutable
+                let msg = format!("Only list items can be assigned, not items of {}", other.repr());
+                Err(InterpretError::InterpretRuntimeError(msg))
+            }
+        }
+    }
+
+    /// `index` as a position in something of length `len`.
+    fn index(index: &Value, len: usize) -> Result<usize, InterpretError> {
+        match index {
+            Value::NUMBER(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => Ok(*n as usize),
+            other => {
+                let msg = format!("Index {} out of range for length {}", other.repr(), len);
+                Err(InterpretError::InterpretRuntimeError(msg))
+            }
+        }
+    }
+
     fn read_constant(frame: &CallFrame, idx: usize) -> Value {
         frame.function.chunk.constants[idx].clone()
     }
@@ -0,0 +1,124 @@
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::{gc::GcConfig, natives::Rng};
+
+use super::{Backend, Frontend, Vm};
+
+/// Configures a `Vm` before it starts: where output goes, where input comes
+/// from, the compiler front end, the backend that runs it, script arguments,
+/// the random seed, how deep calls may nest and when the garbage collector runs.
+pub struct VmBuilder {
+    output: Option<Box<dyn Write>>,
+    input: Option<Box<dyn BufRead>>,
+    frontend: Frontend,
+    backend: Backend,
+    args: Vec<String>,
+    seed: Option<u64>,
+    max_depth: usize,
+    gc: GcConfig,
+    #[cfg(feature = "jit")]
+    jit_threshold: Option<u32>,
+}
+
+impl VmBuilder {
+    pub fn new() -> VmBuilder {
+        VmBuilder {
+            output: None,
+            input: None,
+            frontend: Frontend::SinglePass,
+            backend: Backend::Stack,
+            args: Vec::new(),
+            seed: None,
+            max_depth: Vm::DEFAULT_MAX_FRAMES,
+            gc: GcConfig::default(),
+            #[cfg(feature = "jit")]
+            jit_threshold: Some(crate::jit::DEFAULT_THRESHOLD),
+        }
+    }
+
+    /// Where `print` writes; stdout by default.
+    pub fn output(mut self, output: impl Write + 'static) -> VmBuilder {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Where `read_line` reads; stdin by default.
+    pub fn input(mut self, input: impl BufRead + 'static) -> VmBuilder {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    pub fn frontend(mut self, frontend: Frontend) -> VmBuilder {
+        self.frontend = frontend;
+        self
+    }
+
+    /// Run on the stack machine, the default, or the register machine.
+    pub fn backend(mut self, backend: Backend) -> VmBuilder {
+        self.backend = backend;
+        self
+    }
+
+    /// The script's `args` list.
+    pub fn args(mut self, args: Vec<String>) -> VmBuilder {
+        self.args = args;
+        self
+    }
+
+    /// Seed `random` and friends; they are seeded from the clock otherwise.
+    pub fn seed(mut self, seed: u64) -> VmBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// How many calls may be active at once, the script itself included;
+    /// one more is a `Stack Overflow` runtime error.
+    pub fn max_depth(mut self, max_depth: usize) -> VmBuilder {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// How big the heap gets, in bytes, before the first collection, and the
+    /// least any later one waits for; 1 MiB by default.
+    pub fn gc_threshold(mut self, bytes: usize) -> VmBuilder {
+        self.gc.threshold = bytes;
+        self
+    }
+
+    /// How many times over what survived a collection the heap may grow
+    /// before the next one; 2 by default. Panics unless `factor` is finite
+    /// and at least 1.
+    pub fn gc_growth_factor(mut self, factor: f64) -> VmBuilder {
+        assert!(GcConfig::valid_growth_factor(factor), "invalid gc growth factor {}", factor);
+        self.gc.growth_factor = factor;
+        self
+    }
+
+    /// How many times a function has to be called, or a loop has to come
+    /// round, before it is compiled to native code; None turns the JIT off.
+    #[cfg(feature = "jit")]
+    pub fn jit_threshold(mut self, threshold: Option<u32>) -> VmBuilder {
+        self.jit_threshold = threshold;
+        self
+    }
+
+    pub fn build(self) -> Vm {
+        let output = self.output.unwrap_or_else(|| Box::new(io::stdout()));
+        let input = self.input.unwrap_or_else(|| Box::new(BufReader::new(io::stdin())));
+        let rng = self.seed.map_or_else(Rng::from_clock, Rng::new);
+        let mut vm = Vm::new(output, input, rng, self.max_depth);
+        vm.set_frontend(self.frontend);
+        vm.set_backend(self.backend);
+        vm.set_args(self.args);
+        vm.set_gc_config(self.gc);
+        #[cfg(feature = "jit")]
+        vm.set_jit_threshold(self.jit_threshold);
+        vm
+    }
+}
+
+impl Default for VmBuilder {
+    fn default() -> VmBuilder {
+        VmBuilder::new()
+    }
+}
@@ -0,0 +1,24 @@
+use std::io::{self, Write};
+
+use super::Vm;
+
+/// What the vm should do after a hook returns control to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+    Abort,
+}
+
+/// Callbacks invoked by `Vm::run` while executing a script. Used by debuggers
+/// to implement breakpoints and stepping without touching the core loop.
+pub trait VmHook {
+    /// Called before the first instruction of every new source line, and again
+    /// whenever a loop jumps back. `depth` is the number of active call frames.
+    fn on_line(&mut self, vm: &Vm, line: usize, depth: usize) -> HookAction;
+
+    /// Called for every `print` statement with the vm's output; by default
+    /// the line is written there, as it is without a hook.
+    fn on_print(&mut self, text: &str, output: &mut dyn Write) -> io::Result<()> {
+        writeln!(output, "{}", text)
+    }
+}
@@ -0,0 +1,34 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+/// An output sink that keeps everything written to it in memory. Clones share
+/// the buffer, so one clone can be given to a `Vm` and another read afterwards.
+#[derive(Clone, Default)]
+pub struct MemoryOutput {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl MemoryOutput {
+    pub fn new() -> MemoryOutput {
+        MemoryOutput::default()
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+}
+
+impl Write for MemoryOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
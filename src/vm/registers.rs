@@ -0,0 +1,224 @@
+use std::io::Write;
+
+use crate::{
+    gc::GcRef,
+    object::ObjFunction,
+    register::{translate, Instr, Operand, RegisterCode},
+    value::{from_slot, to_slot, Value},
+};
+
+use super::{operands_error, CallFrame, InterpretError, Vm};
+
+macro_rules! numeric {
+    ($ret: ident, $op: tt, $left: expr, $right: expr) => {{
+        let (left, right) = ($left, $right);
+        match (&left, &right) {
+            (Value::NUMBER(x), Value::NUMBER(y)) => Value::$ret(x $op y),
+            _ => return Err(operands_error(stringify!($op), &left, &right)),
+        }
+    }};
+}
+
+impl Vm {
+    /// The register code for `function`, translated on its first call.
+    fn register_code(&mut self, mut function: GcRef<ObjFunction>) -> *const RegisterCode {
+        if function.registers.is_none() {
+            function.registers = Some(Box::new(translate(&function)));
+            self.gc.resize(function);
+        }
+        function.registers.as_deref().unwrap()
+    }
+
+    /// `run` for the register backend. Each frame's registers are its stack
+    /// slots, and `stack_top` stays above the registers of every frame so
+    /// the collector sees them all.
+    pub(super) fn run_registers(&mut self) -> Result<Value, InterpretError> {
+        unsafe {
+            let mut frame_ptr = self.current_frame();
+            let mut code = &*self.enter_registers(frame_ptr);
+            let mut base = (*frame_ptr).slot;
+            let mut pc = 0;
+            // for each caller: where it carries on, and its `stack_top`
+            let mut returns: Vec<(usize, usize)> = Vec::new();
+
+            macro_rules! get {
+                ($operand: expr) => {
+                    match $operand {
+                        Operand::Reg(reg) => from_slot(&self.stack[base + reg as usize]),
+                        Operand::Const(idx) => code.constants[idx as usize].clone(),
+                    }
+                };
+            }
+            macro_rules! set {
+                ($dst: expr, $value: expr) => {{
+                    let value = $value;
+                    self.stack[base + $dst as usize] = to_slot(value);
+                }};
+            }
+            loop {
+                if self.gc.should_collect() {
+                    self.collect_step();
+                }
+
+                let instr = code.code[pc];
+                pc += 1;
+                match instr {
+                    Instr::Move { dst, src } => set!(dst, get!(src)),
+                    Instr::Negate { dst, src } => match get!(src) {
+                        Value::NUMBER(n) => set!(dst, Value::NUMBER(-n)),
+                        _ => {
+                            return Err(InterpretError::InterpretRuntimeError(
+                                "Failed to negate non-number value".to_string(),
+                            ))
+                        }
+                    },
+                    Instr::Not { dst, src } => set!(dst, Value::BOOL(Value::falsify(&get!(src)))),
+                    Instr::Add { dst, a, b } => {
+                        let sum = match (get!(a), get!(b)) {
+                            (Value::NUMBER(x), Value::NUMBER(y)) => Value::NUMBER(x + y),
+                            (Value::STR(left), Value::STR(right)) => {
+                                Value::STR(self.gc.intern(left.s.to_owned() + &right.s))
+                            }
+                            _ => {
+                                return Err(InterpretError::InterpretRuntimeError(
+                                    "Invalid addition operands".to_string(),
+                                ))
+                            }
+                        };
+                        set!(dst, sum);
+                    }
+                    Instr::Subtract { dst, a, b } => set!(dst, numeric!(NUMBER, -, get!(a), get!(b))),
+                    Instr::Multiply { dst, a, b } => set!(dst, numeric!(NUMBER, *, get!(a), get!(b))),
+                    Instr::Divide { dst, a, b } => set!(dst, numeric!(NUMBER, /, get!(a), get!(b))),
+                    Instr::Mod { dst, a, b } => set!(dst, numeric!(NUMBER, %, get!(a), get!(b))),
+                    Instr::Equal { dst, a, b } => {
+                        let (left, right) = (get!(a), get!(b));
+                        set!(dst, Value::BOOL(Value::values_equal(&right, &left)));
+                    }
+                    Instr::Greater { dst, a, b } => match (get!(a), get!(b)) {
+                        (Value::STR(left), Value::STR(right)) => set!(dst, Value::BOOL(left.s > right.s)),
+                        (left, right) => set!(dst, numeric!(BOOL, >, left, right)),
+                    },
+                    Instr::Less { dst, a, b } => match (get!(a), get!(b)) {
+                        (Value::STR(left), Value::STR(right)) => set!(dst, Value::BOOL(left.s < right.s)),
+                        (left, right) => set!(dst, numeric!(BOOL, <, left, right)),
+                    },
+                    Instr::GetGlobal { dst, slot } => match self.globals.get(slot as usize) {
+                        Some(value) => set!(dst, value.clone()),
+                        None => {
+                            return Err(InterpretError::InterpretRuntimeError(
+                                "Undefined Variable".to_string(),
+                            ))
+                        }
+                    },
+                    Instr::SetGlobal { slot, src } => {
+                        if !self.globals.assign(slot as usize, get!(src)) {
+                            return Err(InterpretError::InterpretRuntimeError(
+                                "Undefined Variable".to_string(),
+                            ));
+                        }
+                    }
+                    Instr::DefineGlobal { slot, src } => self.globals.define(slot as usize, get!(src)),
+                    Instr::Jump { target } => pc = target as usize,
+                    Instr::JumpIfFalse { cond, target } => {
+                        if Value::is_falsey(&get!(cond)) {
+                            pc = target as usize;
+                        }
+                    }
+                    Instr::Call { base: callee, args } => {
+                        // natives asking for the current line find it the usual way
+                        let origin = code.origins[pc - 1];
+                        let function = (*frame_ptr).function;
+                        (*frame_ptr).ip = function.chunk.code.as_ptr().add(origin + 1);
+                        let top = self.stack_top;
+                        self.stack_top = base + callee as usize + 1 + args as usize;
+                        let depth = self.frame_count;
+                        self.call_value(args)?;
+                        if self.frame_count == depth {
+                            // a native, its result is already in place
+                            self.stack_top = top;
+                        } else {
+                            returns.push((pc, top));
+                            self.stack_top = top;
+                            frame_ptr = self.current_frame();
+                            code = &*self.enter_registers(frame_ptr);
+                            base = (*frame_ptr).slot;
+                            pc = 0;
+                        }
+                    }
+                    Instr::TailCall { base: callee, args } => {
+                        let origin = code.origins[pc - 1];
+                        let function = (*frame_ptr).function;
+                        (*frame_ptr).ip = function.chunk.code.as_ptr().add(origin + 1);
+                        let top = self.stack_top;
+                        self.stack_top = base + callee as usize + 1 + args as usize;
+                        let took_over = self.tail_call(args)?;
+                        // back above the frame's registers, `enter_registers` adds any the callee needs
+                        self.stack_top = top;
+                        if took_over {
+                            code = &*self.enter_registers(frame_ptr);
+                            pc = 0;
+                        }
+                    }
+                    Instr::Return { src } => {
+                        let returned_value = get!(src);
+                        self.frame_count -= 1;
+                        if self.frame_count == 0 {
+                            self.stack_top = base;
+                            return Ok(returned_value);
+                        }
+                        set!(0, returned_value);
+                        let (resume, top) = returns.pop().expect("a caller to return to");
+                        self.stack_top = top;
+                        frame_ptr = self.current_frame();
+                        code = &*self.register_code((*frame_ptr).function);
+                        base = (*frame_ptr).slot;
+                        pc = resume;
+                    }
+                    Instr::Print { src } => {
+                        let val = get!(src);
+                        if let Err(err) = writeln!(self.output, "{}", val) {
+                            let msg = format!("Could not write output: {}", err);
+                            return Err(InterpretError::InterpretRuntimeError(msg));
+                        }
+                    }
+                    Instr::BuildList { dst, start, count } => {
+                        let start = base + start as usize;
+                        let items = self.stack[start..start + count as usize].iter().map(from_slot).collect();
+                        let list = self.new_list(items);
+                        set!(dst, list);
+                    }
+                    Instr::GetIndex { dst, target, index } => {
+                        let item = self.get_index(get!(target), &get!(index))?;
+                        set!(dst, item);
+                    }
+                    Instr::SetIndex { dst, list, index, value } => {
+                        let (mut list, index) = Vm::list_index(&get!(list), &get!(index))?;
+                        let value = get!(value);
+                        self.gc.write_barrier(&value);
+                        list.items[index] = value.clone();
+                        set!(dst, value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Set up the registers of the frame that was just called: the ones
+    /// past its arguments start out nil, and `stack_top` moves above them.
+    unsafe fn enter_registers(&mut self, frame_ptr: *mut CallFrame) -> *const RegisterCode {
+        let frame = &*frame_ptr;
+        let code = self.register_code(frame.function);
+        let end = frame.slot + (*code).registers;
+        if self.stack.len() < end {
+            self.stack.resize(end, to_slot(Value::NIL));
+        }
+        // registers above `stack_top` may hold values the collector has freed
+        let args_end = frame.slot + 1 + frame.function.arity as usize;
+        for slot in &mut self.stack[args_end.min(end)..end] {
+            *slot = to_slot(Value::NIL);
+        }
+        self.stack_top = self.stack_top.max(end);
+        code
+    }
+}
@@ -1,6 +1,12 @@
+use std::io;
+
 use crate::value::Value;
 
-use super::{InterpretError, Vm};
+use super::{
+    hook::{HookAction, VmHook},
+    output::MemoryOutput,
+    InterpretError, Vm,
+};
 
 fn run(source: &str) -> Vm {
     let mut vm = Vm::init_vm();
@@ -18,7 +24,8 @@ fn run_err(source: &str) -> InterpretError {
 fn global(vm: &mut Vm, name: &str) -> Value {
     let key = vm.gc.intern(name.to_string());
     vm.globals
-        .get(key)
+        .lookup(key)
+        .and_then(|slot| vm.globals.get(slot).cloned())
         .unwrap_or_else(|| panic!("missing global '{name}'"))
 }
 
@@ -104,3 +111,420 @@ fn adding_invalid_operands_returns_runtime_error() {
         _ => panic!("expected runtime error"),
     }
 }
+
+struct LineRecorder {
+    lines: Vec<(usize, usize)>,
+    printed: Vec<String>,
+    locals: Vec<Vec<String>>,
+}
+
+impl VmHook for LineRecorder {
+    fn on_line(&mut self, vm: &Vm, line: usize, depth: usize) -> HookAction {
+        self.lines.push((line, depth));
+        let frame = vm.frames().last().unwrap();
+        let names = vm.frame_locals(frame).into_iter().map(|(name, _)| name).collect();
+        self.locals.push(names);
+        HookAction::Continue
+    }
+
+    fn on_print(&mut self, text: &str, _output: &mut dyn io::Write) -> io::Result<()> {
+        self.printed.push(text.to_string());
+        Ok(())
+    }
+}
+
+#[test]
+fn hook_sees_lines_frames_and_prints() {
+    let mut vm = Vm::init_vm();
+    let mut hook = LineRecorder {
+        lines: Vec::new(),
+        printed: Vec::new(),
+        locals: Vec::new(),
+    };
+    let source = "fn f(a) {\n  print a;\n}\nf(1);\n";
+    vm.debug(source.to_string(), &mut hook).unwrap();
+
+    // returning to line 4 after the call doesn't count as reaching it again
+    assert_eq!(hook.lines, vec![(3, 1), (4, 1), (2, 2), (3, 2), (5, 1)]);
+    assert_eq!(hook.locals[2], vec!["a".to_string()]);
+    assert_eq!(hook.printed, vec!["1".to_string()]);
+}
+
+#[test]
+fn hooks_that_leave_printing_alone_print_to_the_output() {
+    struct LineCounter(usize);
+    impl VmHook for LineCounter {
+        fn on_line(&mut self, _vm: &Vm, _line: usize, _depth: usize) -> HookAction {
+            self.0 += 1;
+            HookAction::Continue
+        }
+    }
+
+    let out = MemoryOutput::new();
+    let mut vm = Vm::builder().output(out.clone()).build();
+    let mut hook = LineCounter(0);
+    vm.debug("print 1;\nprint \"two\";\n".to_string(), &mut hook).unwrap();
+    assert_eq!(out.contents(), "1\ntwo\n");
+    assert!(hook.0 >= 2);
+}
+
+#[test]
+fn comments_are_ignored() {
+    let mut vm = run("// leading\nlet x = 1; // trailing\n// last");
+    assert_eq!(global(&mut vm, "x").get_number(), Some(1.0));
+}
+
+#[test]
+fn locals_go_out_of_scope() {
+    let err = run_err("{ let a = 1; } let b = a;");
+    match err {
+        InterpretError::InterpretRuntimeError(msg) => assert_eq!(msg, "Undefined Variable"),
+        _ => panic!("expected runtime error"),
+    }
+}
+
+#[test]
+fn inner_local_shadows_outer() {
+    let mut vm = run("let out = 0; { let x = 1; { let x = 2; out = x; } }");
+    assert_eq!(global(&mut vm, "out").get_number(), Some(2.0));
+}
+
+#[test]
+fn syntax_errors_are_reported_not_panicked() {
+    let err = run_err("let = 1;\nprint \"open;");
+    match err {
+        InterpretError::InterpretCompileError(msg) => assert_eq!(
+            msg,
+            "[line 1] Error at '=': Expected variable name\n[line 2] Error at '\"': Unterminated string"
+        ),
+        _ => panic!("expected compile error"),
+    }
+}
+
+#[test]
+fn evaluate_returns_expression_value() {
+    let mut vm = run("let x = 4;");
+    let value = vm.evaluate("x * 2".to_string()).unwrap();
+    assert_eq!(value.get_number(), Some(8.0));
+    assert!(vm.evaluate("let y = 1;".to_string()).is_err());
+}
+
+#[test]
+fn vm_recovers_after_runtime_error() {
+    let mut vm = run("fn f() { return undefined; }");
+    assert!(vm.interpret("f();".to_string()).is_err());
+    assert!(vm.interpret("let after = 1;".to_string()).is_ok());
+    assert_eq!(global(&mut vm, "after").get_number(), Some(1.0));
+}
+
+#[test]
+fn lists_are_built_indexed_and_assigned() {
+    let mut vm = run("let xs = [1, \"two\", [3]];\nlet a = xs[0] + xs[2][0];\nxs[1] = xs[0] = 5;");
+    assert_eq!(global(&mut vm, "a").get_number(), Some(4.0));
+    assert_eq!(global(&mut vm, "xs").to_string(), "[5, 5, [3]]");
+}
+
+#[test]
+fn bad_indexes_are_runtime_errors() {
+    for source in ["[1][1];", "[1][-1];", "[1][1 / 2];", "[1][\"0\"];", "1[0];", "let s = \"a\"; s[0] = 1;"] {
+        assert!(matches!(run_err(source), InterpretError::InterpretRuntimeError(_)), "{}", source);
+    }
+}
+
+#[test]
+fn list_items_survive_collection() {
+    let mut vm = run("let xs = [\"a\" + \"b\", [\"c\" + \"d\"]];");
+    vm.collect_garbage();
+    assert_eq!(global(&mut vm, "xs").to_string(), "[\"ab\", [\"cd\"]]");
+}
+
+#[test]
+fn items_moved_during_a_collection_survive_it() {
+    // every round pops the items out of one list into another while the
+    // garbage it makes keeps the collector going
+    let source = "let a = [];\n\
+                  for (let i = 0; i < 20000; i = i + 1) push(a, [i, \"item\" + chr(65)]);\n\
+                  for (let round = 0; round < 6; round = round + 1) {\n\
+                    let b = [];\n\
+                    while (len(a) > 0) { push(b, pop(a)); let junk = [[round], \"x\" + chr(65 + round)]; }\n\
+                    if (round == 0 or round == 3) { shuffle(b); let first = b[0]; b[0] = b[len(b) - 1]; b[len(b) - 1] = first; }\n\
+                    a = b;\n\
+                  }\n\
+                  let total = 0;\n\
+                  for (let i = 0; i < len(a); i = i + 1) { total = total + a[i][0] + len(a[i][1]); }";
+    let mut vm = Vm::builder().seed(1).build();
+    let before = vm.gc_stats();
+    assert!(vm.interpret(source.to_string()).is_ok());
+    let stats = vm.gc_stats();
+    assert!(stats.cycles > before.cycles, "{:?}", stats);
+    assert!(stats.pauses > stats.cycles * 10, "collections should be spread over many pauses: {:?}", stats);
+    assert!(stats.max_pause >= stats.last_pause && stats.total_pause >= stats.max_pause);
+    assert_eq!(global(&mut vm, "total").get_number(), Some(200_090_000.0));
+    vm.collect_garbage();
+    assert_eq!(vm.gc_stats().pauses, stats.pauses + 1);
+}
+
+#[test]
+fn heap_accounting_follows_what_scripts_allocate() {
+    // one list pushed to 100000 times, with collections kept out of the way
+    let mut vm = Vm::builder().gc_threshold(usize::MAX).build();
+    let before = vm.gc_stats().bytes_allocated;
+    assert!(vm.interpret("let xs = []; for (let i = 0; i < 100000; i = i + 1) push(xs, i);".to_string()).is_ok());
+    let grown = vm.gc_stats().bytes_allocated - before;
+    assert!(grown >= 100_000 * std::mem::size_of::<Value>(), "{} bytes", grown);
+
+    // each string is 64 KiB, and they used to count for a few dozen bytes each
+    let source = "let s = \"x\"; for (let i = 0; i < 16; i = i + 1) s = s + s;\n\
+                  for (let i = 0; i < 200; i = i + 1) { let t = s + chr(65 + i); }";
+    let mut vm = run(source);
+    let stats = vm.gc_stats();
+    assert!(stats.cycles > 0, "{:?}", stats);
+    assert!(stats.bytes_allocated < 8 * 1024 * 1024, "{:?}", stats);
+    assert_eq!(global(&mut vm, "s").get_string().map(|s| s.s.len()), Some(65536));
+}
+
+#[test]
+fn the_builder_sets_when_the_collector_runs() {
+    let source = "for (let i = 0; i < 2000; i = i + 1) { let junk = [i, [i]]; }";
+    let mut vm = Vm::builder().gc_threshold(usize::MAX).build();
+    assert!(vm.interpret(source.to_string()).is_ok());
+    assert_eq!(vm.gc_stats().cycles, 0);
+
+    let mut vm = Vm::builder().gc_threshold(64 * 1024).gc_growth_factor(1.5).build();
+    assert!(vm.interpret(source.to_string()).is_ok());
+    assert!(vm.gc_stats().cycles > 0);
+    vm.collect_garbage();
+    let stats = vm.gc_stats();
+    assert_eq!(stats.next_gc, ((stats.bytes_allocated as f64 * 1.5) as usize).max(64 * 1024));
+}
+
+#[test]
+#[should_panic(expected = "invalid gc growth factor NaN")]
+fn the_builder_refuses_a_growth_factor_that_never_collects() {
+    Vm::builder().gc_growth_factor(f64::NAN);
+}
+
+#[test]
+fn strings_compare_lexicographically() {
+    let mut vm = run("let a = \"apple\" < \"banana\"; let b = \"b\" > \"abc\"; let c = \"a\" >= \"a\"; let d = \"Z\" > \"a\";");
+    assert_eq!(global(&mut vm, "a").get_bool(), Some(true));
+    assert_eq!(global(&mut vm, "b").get_bool(), Some(true));
+    assert_eq!(global(&mut vm, "c").get_bool(), Some(true));
+    assert_eq!(global(&mut vm, "d").get_bool(), Some(false));
+}
+
+#[test]
+fn mismatched_operands_are_runtime_errors() {
+    for source in ["1 - \"a\";", "\"a\" < 1;", "nil * 2;", "[] > [];"] {
+        match run_err(source) {
+            InterpretError::InterpretRuntimeError(msg) => assert!(msg.contains("must be numbers"), "{}", msg),
+            other => panic!("expected runtime error for {}, got {:?}", source, other),
+        }
+    }
+}
+
+/// what `source` prints
+fn output(source: &str) -> String {
+    let out = MemoryOutput::new();
+    let mut vm = Vm::builder().output(out.clone()).build();
+    let result = vm.interpret(source.to_string());
+    assert!(result.is_ok(), "expected program to run, got: {:?}", result);
+    out.contents()
+}
+
+#[test]
+fn print_writes_to_the_configured_output() {
+    assert_eq!(output("print 1 + 2;\nprint \"a\" + \"b\";\nprint [nil, true];"), "3\nab\n[nil, true]\n");
+    assert_eq!(output("fn f(n) { if (n > 0) { print n; f(n - 1); } }\nf(3);"), "3\n2\n1\n");
+}
+
+#[test]
+fn a_function_named_script_prints_as_a_function() {
+    for frontend in [super::Frontend::SinglePass, super::Frontend::Ast] {
+        let out = MemoryOutput::new();
+        let mut vm = Vm::builder().output(out.clone()).frontend(frontend).build();
+        assert!(vm.interpret("fn script() { }\nprint script;".to_string()).is_ok());
+        assert_eq!(out.contents(), "<fn script>\n");
+    }
+}
+
+#[test]
+fn lists_and_functions_are_truthy_in_conditions() {
+    assert_eq!(output("if ([1]) { print \"yes\"; } else { print \"no\"; }"), "yes\n");
+    assert_eq!(output("if ([]) { print \"yes\"; } else { print \"no\"; }"), "yes\n");
+    assert_eq!(output("if (len) { print \"yes\"; } else { print \"no\"; }"), "yes\n");
+    let looped = "let xs = [];\nlet n = 0;\nwhile (xs) {\n  n = n + 1;\n  if (n == 3) { xs = nil; }\n}\nprint n;";
+    assert_eq!(output(looped), "3\n");
+}
+
+#[test]
+fn output_is_kept_up_to_a_runtime_error() {
+    let out = MemoryOutput::new();
+    let mut vm = Vm::builder().output(out.clone()).build();
+    assert!(vm.interpret("print 1;\nprint nope;\nprint 2;".to_string()).is_err());
+    assert_eq!(out.contents(), "1\n");
+    assert!(vm.interpret("print 3;".to_string()).is_ok());
+    assert_eq!(out.contents(), "1\n3\n");
+}
+
+#[test]
+fn builder_configures_the_vm() {
+    let out = MemoryOutput::new();
+    let mut vm = Vm::builder()
+        .output(out.clone())
+        .input(std::io::Cursor::new("typed\n"))
+        .frontend(super::Frontend::Ast)
+        .args(vec!["x".to_string()])
+        .seed(7)
+        .build();
+    let source = "print read_line() + args[0];\nprint random();";
+    assert!(vm.interpret(source.to_string()).is_ok());
+    let seeded = Vm::builder().output(MemoryOutput::new()).seed(7).build().evaluate("random()".to_string());
+    let first_random = seeded.map(|value| value.to_string()).unwrap_or_default();
+    assert_eq!(out.contents(), format!("typedx\n{}\n", first_random));
+}
+
+#[test]
+fn test_blocks_only_run_when_testing() {
+    let source = "let n = 0;\nprint \"script\";\ntest \"a\" { n = n + 1; print n; }\ntest \"b\" { assert_eq(n, 2); }";
+    assert_eq!(output(source), "script\n");
+
+    for frontend in [super::Frontend::SinglePass, super::Frontend::Ast] {
+        let out = MemoryOutput::new();
+        let mut vm = Vm::builder().output(out.clone()).frontend(frontend).build();
+        let outcomes = vm.test(source.to_string()).unwrap();
+        let names: Vec<&str> = outcomes.iter().map(|outcome| outcome.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert!(outcomes[0].result.is_ok());
+        assert!(matches!(&outcomes[1].result, Err(InterpretError::InterpretRuntimeError(msg)) if msg.contains("got 1, expected 2")));
+        assert_eq!(out.contents(), "script\n1\n");
+    }
+}
+
+#[test]
+fn test_is_still_usable_as_a_name() {
+    let source = "let test = 1;\nfn check(test) { return test + 1; }\ntest = check(test);\nprint test;\ntest \"named\" { }";
+    for frontend in [super::Frontend::SinglePass, super::Frontend::Ast] {
+        let out = MemoryOutput::new();
+        let mut vm = Vm::builder().output(out.clone()).frontend(frontend).build();
+        let outcomes = vm.test(source.to_string()).unwrap();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(out.contents(), "2\n");
+    }
+}
+
+#[test]
+fn stack_grows_for_deep_recursion_and_wide_expressions() {
+    let mut vm = run(
+        "fn depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }\n\
+         fn tree(n) { if (n == 0) return 1; return tree(n - 1) + tree(n - 1); }\n\
+         let d = depth(5000);\n\
+         let t = tree(12);",
+    );
+    assert_eq!(global(&mut vm, "d").get_number(), Some(5000.0));
+    assert_eq!(global(&mut vm, "t").get_number(), Some(4096.0));
+
+    let items: Vec<String> = (0..1000).map(|n| n.to_string()).collect();
+    let mut vm = run(&format!("let xs = [{}];\nlet n = len(xs);", items.join(", ")));
+    assert_eq!(global(&mut vm, "n").get_number(), Some(1000.0));
+}
+
+#[test]
+fn call_depth_is_limited_by_the_builder() {
+    // the script and 100 calls of down
+    let source = "fn down(n) { if (n == 0) return 0; let below = down(n - 1); return below; }\ndown(99);";
+    let mut vm = Vm::builder().max_depth(101).build();
+    assert!(vm.interpret(source.to_string()).is_ok());
+
+    let mut vm = Vm::builder().max_depth(100).build();
+    match vm.interpret(source.to_string()) {
+        Err(InterpretError::InterpretRuntimeError(msg)) => assert_eq!(msg, "Stack Overflow"),
+        other => panic!("expected a stack overflow, got {:?}", other),
+    }
+    // the frames left behind don't count against the next script
+    assert!(vm.interpret("down(50);".to_string()).is_ok());
+
+    match run_err("fn forever() { return 1 + forever(); }\nforever();") {
+        InterpretError::InterpretRuntimeError(msg) => assert_eq!(msg, "Stack Overflow"),
+        other => panic!("expected a stack overflow, got {:?}", other),
+    }
+}
+
+#[test]
+fn tail_calls_reuse_the_frame() {
+    // far deeper than the script and a handful of frames would allow otherwise
+    let source = "fn count(n, acc) { if (n == 0) return acc; return count(n - 1, acc + 1); }\n\
+                  fn even(n) { if (n == 0) return true; return odd(n - 1); }\n\
+                  fn odd(n) { if (n == 0) return false; return even(n - 1); }\n\
+                  fn size(xs) { return len(xs); }\n\
+                  print count(100000, 0);\nprint even(50001);\nprint size([1, 2]);";
+    let out = MemoryOutput::new();
+    let mut vm = Vm::builder().output(out.clone()).max_depth(5).build();
+    assert!(vm.interpret(source.to_string()).is_ok());
+    assert_eq!(out.contents(), "100000\nfalse\n2\n");
+
+    match run_err("fn f(a) { return a; }\nfn g() { return f(); }\ng();") {
+        InterpretError::InterpretRuntimeError(msg) => assert_eq!(msg, "Expected 1 args but found 0"),
+        other => panic!("expected an arity error, got {:?}", other),
+    }
+}
+
+#[test]
+fn frames_count_the_tail_calls_they_took() {
+    struct TailCallCounter {
+        seen: Vec<(String, usize)>,
+    }
+
+    impl VmHook for TailCallCounter {
+        fn on_line(&mut self, vm: &Vm, _line: usize, _depth: usize) -> HookAction {
+            let frame = vm.frames().last().unwrap();
+            self.seen.push((frame.function().name.s.clone(), frame.tail_calls()));
+            HookAction::Continue
+        }
+    }
+
+    let mut vm = Vm::init_vm();
+    let mut hook = TailCallCounter { seen: Vec::new() };
+    let source = "fn c() {\n  return 1;\n}\nfn b() { return c(); }\nfn a() { return b(); }\na();\n";
+    vm.debug(source.to_string(), &mut hook).unwrap();
+    assert!(hook.seen.contains(&("c".to_string(), 2)), "{:?}", hook.seen);
+    assert_eq!(vm.frames().len(), 0);
+}
+
+#[test]
+fn globals_are_bound_late_across_scripts() {
+    let mut vm = Vm::init_vm();
+    // `g` gets its slot here but isn't defined until the next script
+    assert!(vm.interpret("fn f() { return g() + 1; }".to_string()).is_ok());
+    assert!(matches!(vm.evaluate("f()".to_string()), Err(InterpretError::InterpretRuntimeError(_))));
+    assert!(vm.interpret("fn g() { return 1; }".to_string()).is_ok());
+    assert_eq!(vm.evaluate("f()".to_string()).unwrap().get_number(), Some(2.0));
+    assert!(vm.interpret("fn g() { return 10; }\nlet x = 1;\nlet x = x + f();".to_string()).is_ok());
+    assert_eq!(global(&mut vm, "x").get_number(), Some(12.0));
+
+    match run_err("missing = 1;") {
+        InterpretError::InterpretRuntimeError(msg) => assert_eq!(msg, "Undefined Variable"),
+        other => panic!("expected an undefined variable, got {:?}", other),
+    }
+}
+
+#[test]
+fn global_references_share_one_slot() {
+    use crate::bytecode::Opcode;
+
+    let mut vm = Vm::init_vm();
+    let source = "let total = 0;\ntotal = total + total + total;";
+    let script = vm.frontend.compile(source.to_string(), &mut vm.gc, &mut vm.globals).unwrap();
+    assert_eq!(script.chunk.constants.len(), 1);
+    let slots: Vec<String> = script
+        .chunk
+        .instructions()
+        .filter_map(|(_, op)| match op {
+            Opcode::OP_GET_GLOBAL(slot) | Opcode::OP_SET_GLOBAL(slot) => {
+                Some(vm.globals.name(slot).s.clone())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(slots, ["total", "total", "total", "total"]);
+}